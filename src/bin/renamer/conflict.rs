@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::fs::{metadata, read_dir};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::file_drive::file_identity;
+use crate::magic::FileType;
+use crate::types::{MetadataBackend, Video, VideoData};
+
+/// What to do when the destination of a file already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Leave both files as they are
+    #[default]
+    Skip,
+    /// Move the source to the trash when it is identical to the destination
+    TrashSource,
+    /// Move the destination to the trash and put the source in its place
+    Overwrite,
+    /// Like `Overwrite`, but only when the source has a higher resolution
+    Upgrade,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "trash-source" => Ok(ConflictPolicy::TrashSource),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "upgrade" => Ok(ConflictPolicy::Upgrade),
+            _ => Err(format!(
+                "Unknown conflict policy {:?}, expected skip, trash-source, overwrite or upgrade",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do when two files in the same run would get the same name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Report both sources and leave the later one where it is
+    #[default]
+    Skip,
+    /// Add ` (2)`, ` (3)`, ... before the extension of later files
+    Number,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(DuplicatePolicy::Skip),
+            "number" => Ok(DuplicatePolicy::Number),
+            _ => Err(format!(
+                "Unknown duplicate policy {:?}, expected skip or number",
+                s
+            )),
+        }
+    }
+}
+
+/// `name` with ` (n)` added before its extension
+pub fn numbered_name(name: &str, n: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{} ({}).{}", stem, n, extension),
+        None => format!("{} ({})", name, n),
+    }
+}
+
+/// Names given out so far in a run and the file each went to
+pub struct UsedNames {
+    names: HashMap<String, PathBuf>,
+    /// Whether names differing only in case are duplicates
+    case_insensitive: bool,
+}
+
+impl UsedNames {
+    pub fn new(case_insensitive: bool) -> Self {
+        Self {
+            names: HashMap::new(),
+            case_insensitive,
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.case_insensitive {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Give `name` to `source`, numbering it when another file already has
+    /// it. With `DuplicatePolicy::Skip` the file that has it is returned instead.
+    pub fn claim(
+        &mut self,
+        name: &str,
+        source: &Path,
+        policy: DuplicatePolicy,
+    ) -> Result<String, PathBuf> {
+        let name = match self.names.get(&self.key(name)) {
+            None => name.to_string(),
+            Some(other) if policy == DuplicatePolicy::Skip => return Err(other.clone()),
+            Some(_) => (2..)
+                .map(|n| numbered_name(name, n))
+                .find(|name| !self.names.contains_key(&self.key(name)))
+                .unwrap(),
+        };
+        self.names.insert(self.key(&name), source.to_path_buf());
+        Ok(name)
+    }
+
+    /// Free a name given to a file that won't be moved after all
+    pub fn release(&mut self, name: &str) {
+        self.names.remove(&self.key(name));
+    }
+}
+
+/// What to check before applying the conflict policy to an existing destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DestExistsAction {
+    /// Apply the conflict policy straight away
+    #[default]
+    Policy,
+    /// Compare both files by SHA-256 and skip the source when they are
+    /// identical, this reads both files in full so it is slow for large videos
+    HashCompare,
+}
+
+impl FromStr for DestExistsAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "policy" => Ok(DestExistsAction::Policy),
+            "hash-compare" => Ok(DestExistsAction::HashCompare),
+            _ => Err(format!(
+                "Unknown destination exists action {:?}, expected policy or hash-compare",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether two paths reach the same file, e.g. a source that is already its
+/// own destination because `from` and `to` are the same directory
+pub fn same_file<P1: AsRef<Path>, P2: AsRef<Path>>(a: P1, b: P2) -> bool {
+    let identity = |path: &Path| metadata(path).ok().as_ref().and_then(file_identity);
+    match (identity(a.as_ref()), identity(b.as_ref())) {
+        (Some(a), Some(b)) => a == b,
+        // Without file ids the resolved paths are the best there is
+        _ => match (a.as_ref().canonicalize(), b.as_ref().canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        },
+    }
+}
+
+/// What a video is regardless of how it is named, the title is reduced to its
+/// words as names can't hold some of its characters
+pub fn video_key(video: &Video) -> String {
+    let words = |title: &str| {
+        title
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    match &video.info {
+        VideoData::Movie(movie, _) => format!(
+            "movie {} {} {}",
+            words(&movie.title),
+            movie.release_year,
+            words(movie.edition.as_deref().unwrap_or_default())
+        ),
+        VideoData::Episode(episode, _) => format!(
+            "episode {} {} {}",
+            words(&episode.series.title),
+            episode.season,
+            episode.episode
+        ),
+    }
+}
+
+/// The videos already in a destination by `video_key`, so `--on-conflict
+/// upgrade` finds a video whose name has a different resolution in it
+#[derive(Debug, Default)]
+pub struct Library {
+    videos: HashMap<String, PathBuf>,
+}
+
+impl Library {
+    /// Parse the name of every video directly inside `directory`
+    pub fn scan(directory: &Path, backend: MetadataBackend) -> std::io::Result<Self> {
+        let mut videos = HashMap::new();
+        let entries = match read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self { videos }),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let file_type = match FileType::from_path(&path) {
+                Ok(FileType::Unknown | FileType::Incomplete) | Err(_) => continue,
+                Ok(file_type) => file_type,
+            };
+            if let Ok(video) = Video::from_path_with_backend(path.clone(), file_type, backend) {
+                videos.insert(video_key(&video), path);
+            }
+        }
+        Ok(Self { videos })
+    }
+
+    /// The video already in the destination that `key` names
+    pub fn find(&self, key: &str) -> Option<&Path> {
+        self.videos.get(key).map(PathBuf::as_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_names_in_one_run() {
+        let parse = |name: &str| {
+            Video::from_path_with_backend(
+                PathBuf::from(name),
+                FileType::AVI,
+                MetadataBackend::Native,
+            )
+            .unwrap()
+        };
+        let first = parse("Some.Movie.2010.1080p.avi");
+        let second = parse("Some Movie 2010 1080p.avi");
+        let name = first.generate_file_name();
+        assert_eq!(name, second.generate_file_name());
+
+        let mut used = UsedNames::new(false);
+        let policy = DuplicatePolicy::Skip;
+        assert_eq!(used.claim(&name, &first.path, policy), Ok(name.clone()));
+        assert_eq!(
+            used.claim(&name, &second.path, policy),
+            Err(first.path.clone())
+        );
+
+        let policy = DuplicatePolicy::Number;
+        assert_eq!(
+            used.claim(&name, &second.path, policy).unwrap(),
+            "Some Movie-1080p (2).avi"
+        );
+        assert_eq!(
+            used.claim(&name, &second.path, policy).unwrap(),
+            "Some Movie-1080p (3).avi"
+        );
+        // A name given back can be used again
+        used.release("Some Movie-1080p (2).avi");
+        assert_eq!(
+            used.claim(&name, &second.path, policy).unwrap(),
+            "Some Movie-1080p (2).avi"
+        );
+    }
+
+    #[test]
+    fn duplicate_names_ignoring_case() {
+        let mut used = UsedNames::new(true);
+        let policy = DuplicatePolicy::Number;
+        assert_eq!(
+            used.claim("Movie-1080p.mkv", Path::new("a.mkv"), policy)
+                .unwrap(),
+            "Movie-1080p.mkv"
+        );
+        assert_eq!(
+            used.claim("MOVIE-1080p.mkv", Path::new("b.mkv"), policy)
+                .unwrap(),
+            "MOVIE-1080p (2).mkv"
+        );
+        assert!(UsedNames::new(false)
+            .claim("MOVIE-1080p.mkv", Path::new("b.mkv"), DuplicatePolicy::Skip)
+            .is_ok());
+    }
+}
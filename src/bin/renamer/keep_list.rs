@@ -0,0 +1,121 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::types::GenericResult;
+
+/// Sources whose originals are kept even when `--delete` is given, read from
+/// a file with one path or glob per line
+pub struct KeepList {
+    patterns: Vec<String>,
+}
+
+impl KeepList {
+    /// Read a keep-list, blank lines and lines starting with `#` are ignored
+    pub fn open<P: AsRef<Path>>(path: P) -> GenericResult<Self> {
+        let path = path.as_ref();
+        let contents = read_to_string(path)
+            .map_err(|e| format!("Unable to read keep list {:?}: {}", path, e))?;
+        let patterns = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.replace('\\', "/"))
+            .collect();
+        Ok(Self { patterns })
+    }
+
+    /// Whether the source's path, absolute path or file name matches an entry
+    pub fn contains<P: AsRef<Path>>(&self, source: P) -> bool {
+        let source = source.as_ref();
+        let mut candidates = vec![source.to_string_lossy().replace('\\', "/")];
+        if let Ok(absolute) = source.canonicalize() {
+            candidates.push(absolute.to_string_lossy().replace('\\', "/"));
+        }
+        if let Some(name) = source.file_name() {
+            candidates.push(name.to_string_lossy().to_string());
+        }
+        self.patterns.iter().any(|pattern| {
+            candidates
+                .iter()
+                .any(|candidate| glob_match(pattern, candidate))
+        })
+    }
+}
+
+/// Match `text` against a glob where `*` matches any run of characters
+/// (including `/`) and `?` matches exactly one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, star_t)) => {
+                    p = star + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("*.mkv", "Movie.mkv"));
+        assert!(glob_match("*.mkv", "dir/Movie.mkv"));
+        assert!(glob_match("Show.S01E0?.mkv", "Show.S01E05.mkv"));
+        assert!(!glob_match("Show.S01E0?.mkv", "Show.S01E10.mkv"));
+        assert!(glob_match("a*b*c", "aXbYc"));
+        assert!(!glob_match("a*b*c", "aXbY"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn kept_and_deleted_files() {
+        let path = tempfile::Builder::new()
+            .prefix("renamer-keep-")
+            .suffix(".txt")
+            .tempfile()
+            .unwrap()
+            .into_temp_path();
+        std::fs::write(
+            &path,
+            "# Originals to keep\n\nKeep.Me.2010.mkv\nshows\\Show\\*.S01E0?.mkv\n  *.iso  \n",
+        )
+        .unwrap();
+        let list = KeepList::open(&path).unwrap();
+
+        for kept in [
+            "downloads/Keep.Me.2010.mkv",
+            "shows/Show/Show.S01E02.mkv",
+            "Disc.iso",
+        ] {
+            assert!(list.contains(Path::new(kept)), "{}", kept);
+        }
+        for deleted in [
+            "downloads/Other.2010.mkv",
+            "shows/Show/Show.S01E12.mkv",
+            "shows/Other/Show.S01E02.mkv",
+        ] {
+            assert!(!list.contains(Path::new(deleted)), "{}", deleted);
+        }
+    }
+}
@@ -0,0 +1,2182 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::env::current_dir;
+use std::fs::{metadata, read_dir, FileTimes, OpenOptions};
+use std::io::ErrorKind;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::time::Instant;
+
+mod conflict;
+mod keep_list;
+mod nfo;
+mod plan;
+mod preflight;
+mod profile;
+mod progress;
+mod prompt;
+#[cfg(feature = "json")]
+mod state;
+#[cfg(feature = "json")]
+mod undo;
+mod verify;
+#[cfg(feature = "watch")]
+mod watch;
+
+#[cfg(feature = "imdb")]
+use not_sus_renamer::imdb;
+use not_sus_renamer::{file_drive, magic, recursive_read_dir, summary, types};
+
+use crate::conflict::{
+    same_file, video_key, ConflictPolicy, DestExistsAction, DuplicatePolicy, Library, UsedNames,
+};
+use crate::file_drive::{drive_identifier, files_on_same_drive};
+use crate::keep_list::KeepList;
+use crate::magic::{parse_extension_map, split_extension, FileType};
+use crate::plan::{PlannedAction, PlannedOperation};
+use crate::profile::Profile;
+use crate::progress::{copy_with_progress, ProgressReader};
+use crate::prompt::{Prompter, Review};
+use crate::recursive_read_dir::{read_dir_recursive, DiscFolders, WalkOrder};
+#[cfg(feature = "json")]
+use crate::state::{Fingerprint, StateFile};
+use crate::summary::{Outcome, Summary};
+use crate::types::{
+    find_companions, read_matroska_tags, sidecar_suffix, GenericResult, ImdbKind, ImdbTagFormat,
+    ImdbWhen, MetadataBackend, NameFormat, NameTemplate, Subtitle, TemplateKind, TitleSource,
+    Video, ILLEGAL_NAME_CHARS,
+};
+#[cfg(feature = "json")]
+use crate::undo::{LogEntry, UndoLog};
+use crate::verify::{files_hash_identical, verify_copy, verify_matroska_streams};
+
+/// How a file will be moved into the destination directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    /// Instant rename using the OS API, only possible on the same drive
+    Rename,
+    /// Copy the file, leaving the original in place
+    Copy,
+    /// Copy the file then remove the original
+    CopyAndDelete,
+    /// Hard link the file, sharing its content with the original
+    Hardlink,
+}
+
+impl Operation {
+    fn new(same_drive: bool, delete_old: bool) -> Self {
+        match (same_drive, delete_old) {
+            (true, true) => Operation::Rename,
+            (false, true) => Operation::CopyAndDelete,
+            (_, false) => Operation::Copy,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Operation::Rename => "rename (same drive), original removed",
+            Operation::Copy => "copy, original kept",
+            Operation::CopyAndDelete => "copy (cross drive), original removed",
+            Operation::Hardlink => "hardlink, original kept for seeding",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Options {
+    from_directory: PathBuf,
+    to_directory: PathBuf,
+    delete_old: bool,
+    /// Hard link into the destination, keeping originals and their content untouched
+    seedbox: bool,
+    dry_run: bool,
+    dont_recurse: bool,
+    verbose: bool,
+    embed_subtitles: bool,
+    case_insensitive_dedupe: bool,
+    /// Sources whose size and modification time match the state file are
+    /// skipped, dry runs read it but never update it. `--watch` keeps one in
+    /// the destination when none is given
+    state_file: Option<PathBuf>,
+    strip_year_from_title: bool,
+    /// Keep titles cased as they are in the file name
+    raw_titles: bool,
+    walk_order: WalkOrder,
+    disc_folders: DiscFolders,
+    no_recurse_into: Vec<String>,
+    on_conflict: ConflictPolicy,
+    /// What to do when files in this run would get the same name
+    on_duplicate: DuplicatePolicy,
+    probe: bool,
+    skip_hidden: bool,
+    min_resolution: Option<u64>,
+    max_resolution: Option<u64>,
+    keep_unknown_resolution: bool,
+    reject_dir: Option<PathBuf>,
+    sort: bool,
+    stage: bool,
+    /// Give copied and rewritten files the source's timestamps
+    preserve_time: bool,
+    /// Check copies match their source before the source is removed
+    verify: bool,
+    series: Option<String>,
+    season: u32,
+    start_episode: u32,
+    write_nfo: bool,
+    overwrite_nfo: bool,
+    title_from: TitleSource,
+    move_subtitles: bool,
+    /// Also move artwork and NFOs named after the video
+    move_companions: bool,
+    metadata_backend: MetadataBackend,
+    parent_as_fallback_title: bool,
+    imdb_tag_format: ImdbTagFormat,
+    tag_source_path: bool,
+    preflight: bool,
+    force: bool,
+    assume_yes: bool,
+    /// Ask before applying each change
+    interactive: bool,
+    /// Print the plan as JSON instead of changing anything
+    json: bool,
+    allowed_dest_drives: Vec<u64>,
+    imdb_when: ImdbWhen,
+    /// Empty for the default movie kinds
+    imdb_kinds: Vec<ImdbKind>,
+    /// Always search the IMDB index, ignoring and not updating the search cache
+    no_cache: bool,
+    extension_map: HashMap<String, FileType>,
+    max_files: Option<usize>,
+    name_format: NameFormat,
+    dump_parse: bool,
+    /// Print the tags already in this file and exit
+    dump_tags: Option<PathBuf>,
+    /// Where each file put into place is recorded, in the destination by default
+    log: Option<PathBuf>,
+    /// Reverse the files recorded in this undo log and exit
+    undo: Option<PathBuf>,
+    /// Only correct extensions that don't match the detected type, in place
+    fix_extension_only: bool,
+    warn_multi_video: bool,
+    dest_exists_action: DestExistsAction,
+    /// Check the IMDB index works and exit without processing files
+    check_imdb: bool,
+    /// Sources whose originals are kept even with `--delete`
+    keep_original_list: Option<PathBuf>,
+    /// Remux MP4s into MKVs as they're moved so they can be tagged
+    remux_mp4: bool,
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    watch: bool,
+}
+
+const USAGE: &str = "Usage: not-sus-renamer [options] [from directory] [to directory]
+
+Both directories default to the current directory.
+
+Moving:
+  -d, --delete                    Remove originals (renames on the same drive)
+      --dry                       Print what would happen without changing anything
+      --json                      Print the plan as JSON without changing anything
+  -n, --dont-recurse              Only look at files directly in the from directory
+      --seedbox                   Hardlink into the destination, keeping originals untouched
+      --stage                     Write into a staging directory and move into place at the end
+      --verify                    Check copies match before removing the originals
+      --no-preserve-time          Give copies the current time instead of the original's
+      --keep-original-list <file> Keep the originals of the paths or globs listed in the file
+      --state-file <file>         Skip files processed by an earlier run
+      --log <file>                Record each file moved, in the destination by default
+      --undo <file>               Put back the files recorded in a log
+      --on-conflict <skip|trash-source|overwrite|upgrade>
+      --on-duplicate <skip|number>
+      --dest-exists-action <policy|hash-compare>
+      --case-insensitive-dedupe   Treat destinations differing only by case as the same
+      --preflight                 Check the whole plan before moving anything
+      --force                     Continue when preflight finds problems
+  -y, --assume-yes                Answer yes to every question
+  -i, --interactive               Ask before applying each change
+      --allowed-dest-drive <id>   Only write to this drive, may be repeated
+      --watch                     Keep processing new files as they finish writing
+
+Finding files:
+      --walk-order <dfs|bfs>
+      --skip-hidden, --no-skip-hidden
+      --no-recurse-into <name>    Never descend into directories with this name
+      --disc-folders <single|skip>
+      --extension-map <.ext=type,...>
+      --sort                      Process files in natural order
+      --max-files <n>
+      --min-resolution <p>, --max-resolution <p>
+      --keep-unknown-resolution, --reject-dir <dir>
+
+Naming:
+      --profile <plex|jellyfin|kodi|emby>
+  -t, --template <template>       Name movies and episodes alike, e.g. {title}.{resolution}p.{ext}
+                                  or {title} ({year})/{title} with / between directories
+      --movie-pattern <template>, --episode-pattern <template>
+      --section-separator <text>
+      --series-year, --no-series-year, --movie-year, --no-movie-year
+      --strip-year-from-title
+      --raw-titles                Keep titles cased as in the file name
+      --title-from <file|dir|auto>, --parent-as-fallback-title
+      --series <title>, --season <n>, --start-episode <n>
+
+Metadata:
+      --metadata-backend <native|mediainfo|auto>
+      --embed-subtitles, --move-subtitles, --no-move-subtitles
+      --move-companions           Move subtitles, artwork and NFOs named after the video
+      --remux-mp4                 Remux H.264/AAC MP4s into tagged MKVs
+      --write-nfo, --no-write-nfo, --overwrite-nfo
+      --tag-source-path           Record the source path in an ORIGINAL_PATH tag
+      --imdb-tag-format <id|number|url>
+      --imdb-when <missing|ambiguous|always>
+      --imdb-kinds <kind>         May be repeated, e.g. movie, tvmovie or short
+      --no-cache                  Always search the IMDB index, skipping the search cache
+
+Inspecting:
+  -v, --verbose
+      --probe, --dump-parse, --warn-multi-video
+      --dump-tags <file>          Print the tags already in an MKV
+      --fix-extension-only        Only correct extensions that don't match the contents
+      --check-imdb                Check the IMDB index works
+  -h, --help                      Print this message";
+
+/// Take the value following an option that requires one
+fn option_value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> std::io::Result<String> {
+    args.next().ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} requires a value", option),
+        )
+    })
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> std::io::Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| {
+        std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid number {:?}: {}", value, e),
+        )
+    })
+}
+
+fn parse_options() -> std::io::Result<Options> {
+    let mut args = std::env::args();
+    args.next().expect("arg0");
+    let cwd = current_dir()?;
+
+    // Profiles only set defaults, so one is applied before any other option
+    let profile: Option<Profile> =
+        match std::env::args().skip_while(|arg| arg != "--profile").nth(1) {
+            Some(profile) => Some(
+                profile
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+            ),
+            None => None,
+        };
+
+    let mut delete_old = false;
+    let mut seedbox = false;
+    let mut dry_run = false;
+    let mut dont_recurse = false;
+    let mut verbose = false;
+    let mut embed_subtitles = false;
+    let mut remux_mp4 = false;
+    // Default filesystems on macOS and Windows ignore case
+    let mut case_insensitive_dedupe = cfg!(any(windows, target_os = "macos"));
+
+    let mut state_file = None;
+    let mut strip_year_from_title = false;
+    let mut raw_titles = false;
+    let mut walk_order = WalkOrder::default();
+    let mut disc_folders = DiscFolders::default();
+    let mut no_recurse_into = Vec::new();
+    let mut on_conflict = ConflictPolicy::default();
+    let mut on_duplicate = DuplicatePolicy::default();
+    let mut probe = false;
+    let mut skip_hidden = true;
+    let mut preserve_time = true;
+    let mut min_resolution = None;
+    let mut max_resolution = None;
+    let mut keep_unknown_resolution = false;
+    let mut reject_dir = None;
+    let mut sort = false;
+    let mut stage = false;
+    let mut verify = false;
+    let mut series = None;
+    let mut season = 1;
+    let mut start_episode = 1;
+    let mut write_nfo = false;
+    let mut overwrite_nfo = false;
+    let mut title_from = TitleSource::default();
+    let mut move_subtitles = false;
+    let mut move_companions = false;
+    let mut metadata_backend = MetadataBackend::default();
+    let mut parent_as_fallback_title = false;
+    let mut imdb_tag_format = ImdbTagFormat::default();
+    let mut tag_source_path = false;
+    let mut preflight = false;
+    let mut force = false;
+    let mut assume_yes = false;
+    let mut interactive = false;
+    let mut json = false;
+    let mut allowed_dest_drives = Vec::new();
+    let mut imdb_when = ImdbWhen::default();
+    let mut imdb_kinds = Vec::new();
+    let mut no_cache = false;
+    let mut extension_map = HashMap::new();
+    let mut max_files = None;
+    let mut name_format = NameFormat::default();
+    let mut dump_parse = false;
+    let mut dump_tags = None;
+    let mut log = None;
+    let mut undo = None;
+    let mut fix_extension_only = false;
+    let mut warn_multi_video = false;
+    let mut dest_exists_action = DestExistsAction::default();
+    let mut check_imdb = false;
+    let mut keep_original_list = None;
+    let mut watch = false;
+
+    if let Some(profile) = profile {
+        profile.apply(&mut name_format, &mut write_nfo, &mut move_subtitles);
+    }
+
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        let argument = match arg.strip_prefix('-') {
+            Some(argument) => argument,
+            None => {
+                positional.push(PathBuf::from(&arg));
+                continue;
+            }
+        };
+        match argument {
+            "-dont-recurse" | "n" => dont_recurse = true,
+            "-delete" | "d" => delete_old = true,
+            "-seedbox" => seedbox = true,
+            "-dry" => dry_run = true,
+            "-verbose" | "v" => verbose = true,
+            "-probe" => probe = true,
+            "-dump-parse" => dump_parse = true,
+            "-fix-extension-only" => fix_extension_only = true,
+            "-dump-tags" => dump_tags = Some(PathBuf::from(option_value(&mut args, &arg)?)),
+            "-log" => log = Some(PathBuf::from(option_value(&mut args, &arg)?)),
+            "-undo" => undo = Some(PathBuf::from(option_value(&mut args, &arg)?)),
+            "-watch" => watch = true,
+            "-warn-multi-video" => warn_multi_video = true,
+            "-skip-hidden" => skip_hidden = true,
+            "-no-skip-hidden" => skip_hidden = false,
+            "-embed-subtitles" => embed_subtitles = true,
+            "-remux-mp4" => {
+                if cfg!(not(feature = "remux")) {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "--remux-mp4 requires the remux feature",
+                    ));
+                }
+                remux_mp4 = true
+            }
+            "-move-subtitles" => move_subtitles = true,
+            "-move-companions" => move_companions = true,
+            "-case-insensitive-dedupe" => case_insensitive_dedupe = true,
+            "-strip-year-from-title" => strip_year_from_title = true,
+            "-raw-titles" => raw_titles = true,
+            "-walk-order" => {
+                walk_order = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-no-recurse-into" => no_recurse_into.push(option_value(&mut args, &arg)?),
+            "-disc-folders" => {
+                disc_folders = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-on-conflict" => {
+                on_conflict = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-on-duplicate" => {
+                on_duplicate = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-dest-exists-action" => {
+                dest_exists_action = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-min-resolution" => {
+                min_resolution = Some(
+                    option_value(&mut args, &arg)?
+                        .trim_end_matches('p')
+                        .parse()
+                        .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+                )
+            }
+            "-max-resolution" => {
+                max_resolution = Some(
+                    option_value(&mut args, &arg)?
+                        .trim_end_matches('p')
+                        .parse()
+                        .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+                )
+            }
+            "-keep-unknown-resolution" => keep_unknown_resolution = true,
+            "-reject-dir" => reject_dir = Some(PathBuf::from(option_value(&mut args, &arg)?)),
+            "-sort" => sort = true,
+            "-stage" => stage = true,
+            "-verify" => verify = true,
+            "-no-preserve-time" => preserve_time = false,
+            "-series" => series = Some(option_value(&mut args, &arg)?),
+            "-season" => season = parse_number(&option_value(&mut args, &arg)?)?,
+            "-start-episode" => start_episode = parse_number(&option_value(&mut args, &arg)?)?,
+            "-write-nfo" => write_nfo = true,
+            "-overwrite-nfo" => overwrite_nfo = true,
+            "-title-from" => {
+                title_from = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-metadata-backend" => {
+                metadata_backend = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-parent-as-fallback-title" => parent_as_fallback_title = true,
+            "-tag-source-path" => tag_source_path = true,
+            "-imdb-tag-format" => {
+                imdb_tag_format = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-preflight" => preflight = true,
+            "-force" => force = true,
+            "-assume-yes" | "y" => assume_yes = true,
+            "-interactive" | "i" => interactive = true,
+            "-json" => json = true,
+            "-allowed-dest-drive" => {
+                allowed_dest_drives.push(parse_number(&option_value(&mut args, &arg)?)?)
+            }
+            "-no-cache" => no_cache = true,
+            "-imdb-kinds" => imdb_kinds.push(
+                option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+            ),
+            "-imdb-when" => {
+                imdb_when = option_value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            }
+            "-extension-map" => extension_map.extend(
+                parse_extension_map(&option_value(&mut args, &arg)?)
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+            ),
+            "-max-files" => max_files = Some(parse_number(&option_value(&mut args, &arg)?)?),
+            "-profile" => {
+                option_value(&mut args, &arg)?;
+            }
+            "-series-year" => name_format.series_year = true,
+            "-no-series-year" => name_format.series_year = false,
+            "-movie-year" => name_format.movie_year = true,
+            "-no-movie-year" => name_format.movie_year = false,
+            "-no-write-nfo" => write_nfo = false,
+            "-no-move-subtitles" => move_subtitles = false,
+            "-section-separator" => name_format.section_separator = option_value(&mut args, &arg)?,
+            "-movie-pattern" => {
+                name_format.movie_template = Some(
+                    NameTemplate::parse(&option_value(&mut args, &arg)?, TemplateKind::Movie)
+                        .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+                )
+            }
+            "-episode-pattern" => {
+                name_format.episode_template = Some(
+                    NameTemplate::parse(&option_value(&mut args, &arg)?, TemplateKind::Episode)
+                        .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+                )
+            }
+            // Both kinds, so only placeholders shared by movies and episodes
+            "-template" | "t" => {
+                let template = option_value(&mut args, &arg)?;
+                name_format.movie_template = Some(
+                    NameTemplate::parse(&template, TemplateKind::Movie)
+                        .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+                );
+                name_format.episode_template = Some(
+                    NameTemplate::parse(&template, TemplateKind::Episode)
+                        .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?,
+                );
+            }
+            "-check-imdb" => check_imdb = true,
+            "-keep-original-list" => {
+                keep_original_list = Some(PathBuf::from(option_value(&mut args, &arg)?))
+            }
+            "-state-file" => state_file = Some(PathBuf::from(option_value(&mut args, &arg)?)),
+            "-help" | "h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unknown option {:?}\n\n{}", arg, USAGE),
+                ))
+            }
+        }
+    }
+
+    if watch && cfg!(not(feature = "watch")) {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "--watch requires the watch feature",
+        ));
+    }
+    // The plan, state file and undo log are all JSON
+    let json_option = [
+        (json, "--json"),
+        (state_file.is_some(), "--state-file"),
+        (log.is_some(), "--log"),
+        (undo.is_some(), "--undo"),
+    ]
+    .into_iter()
+    .find(|(used, _)| *used);
+    if let (Some((_, option)), true) = (json_option, cfg!(not(feature = "json"))) {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} requires the json feature", option),
+        ));
+    }
+
+    // Numbering episodes only makes sense for one directory in a known order
+    if series.is_some() && !(dont_recurse && sort) {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "--series requires --dont-recurse and --sort",
+        ));
+    }
+    if start_episode == 0 {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "--start-episode must be at least 1",
+        ));
+    }
+
+    let mut positional = positional.into_iter();
+    let from_directory = positional.next().unwrap_or(cwd.clone());
+    let to_directory = positional.next().unwrap_or(cwd);
+
+    Ok(Options {
+        from_directory,
+        to_directory,
+        delete_old,
+        seedbox,
+        dry_run,
+        dont_recurse,
+        verbose,
+        embed_subtitles,
+        remux_mp4,
+        case_insensitive_dedupe,
+        state_file,
+        strip_year_from_title,
+        raw_titles,
+        walk_order,
+        disc_folders,
+        no_recurse_into,
+        on_conflict,
+        on_duplicate,
+        probe,
+        skip_hidden,
+        min_resolution,
+        max_resolution,
+        keep_unknown_resolution,
+        reject_dir,
+        sort,
+        stage,
+        preserve_time,
+        verify,
+        series,
+        season,
+        start_episode,
+        write_nfo,
+        overwrite_nfo,
+        title_from,
+        move_subtitles,
+        move_companions,
+        metadata_backend,
+        parent_as_fallback_title,
+        imdb_tag_format,
+        tag_source_path,
+        preflight,
+        force,
+        assume_yes,
+        interactive,
+        json,
+        allowed_dest_drives,
+        imdb_when,
+        imdb_kinds,
+        no_cache,
+        extension_map,
+        max_files,
+        name_format,
+        dump_parse,
+        dump_tags,
+        log,
+        undo,
+        fix_extension_only,
+        warn_multi_video,
+        dest_exists_action,
+        check_imdb,
+        keep_original_list,
+        watch,
+    })
+}
+
+/// Name of the directory inside the destination that `--stage` writes to
+const STAGING_DIRECTORY: &str = ".not-sus-renamer-staging";
+
+/// Undo log written in the destination unless `--log` is given
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+const UNDO_LOG_NAME: &str = ".not-sus-renamer-undo.jsonl";
+
+/// State file `--watch` keeps in the destination unless `--state-file` is given
+#[cfg_attr(not(feature = "watch"), allow(dead_code))]
+const WATCH_STATE_NAME: &str = ".not-sus-renamer-state.jsonl";
+
+fn is_legal_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name
+            .chars()
+            .any(|c| c.is_control() || ILLEGAL_NAME_CHARS.contains(&c))
+}
+
+/// Read the destination name from a `<basename>.name` sidecar next to the video.
+///
+/// When present the sidecar is used verbatim as the output file name, taking
+/// precedence over the generated name and any IMDB lookup. Tags are still
+/// written from the name parsed from the original file.
+fn read_name_sidecar(path: &Path) -> GenericResult<Option<String>> {
+    let sidecar = path.with_extension("name");
+    let name = match std::fs::read_to_string(&sidecar) {
+        Ok(name) => name.trim().to_string(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if !is_legal_file_name(&name) {
+        return Err(format!(
+            "Sidecar {:?} contains an invalid file name {:?}",
+            sidecar, name
+        )
+        .into());
+    }
+    Ok(Some(name))
+}
+
+/// Whether every directory and the file name of a name relative to the
+/// destination, with `/` between directories, are legal
+fn is_legal_relative_name(name: &str) -> bool {
+    name.split('/').all(is_legal_file_name)
+}
+
+/// Find a file in `directory` with the same name ignoring case, `name` may
+/// start with directories which must match exactly
+fn find_case_insensitive(directory: &Path, name: &str) -> std::io::Result<Option<PathBuf>> {
+    let (directory, name) = match name.rsplit_once('/') {
+        Some((parents, name)) => (directory.join(parents), name),
+        None => (directory.to_path_buf(), name),
+    };
+    let entries = match read_dir(&directory) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        entries => entries?,
+    };
+    let name = name.to_lowercase();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().to_lowercase() == name {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+/// Remove a directory and every directory inside it that is left empty,
+/// keeping any that still hold files
+fn remove_empty_directories(directory: &Path) {
+    if let Ok(entries) = read_dir(directory) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                remove_empty_directories(&entry.path());
+            }
+        }
+    }
+    let _ = std::fs::remove_dir(directory);
+}
+
+/// Compare paths the way a person would, so runs of digits are compared by
+/// value and `title_t2` sorts before `title_t10`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn take_number(chars: &mut Peekable<Chars>) -> String {
+        let mut digits = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+            digits.push(c);
+        }
+        digits.trim_start_matches('0').to_string()
+    }
+
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Rename a video in place when its extension doesn't match its contents,
+/// `None` for files that aren't videos
+fn fix_extension(path: &Path, dry_run: bool) -> GenericResult<Option<Outcome>> {
+    let file_type = FileType::from_path(path)?;
+    let extension = match file_type.canonical_extension() {
+        Some(extension) => extension,
+        None => return Ok(None),
+    };
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (stem, current_extension) = split_extension(&file_name);
+    if file_type.matches_extension(&current_extension) {
+        return Ok(Some(Outcome::Skipped));
+    }
+    let new_path = path.with_file_name(format!("{}.{}", stem, extension));
+    println!("{:?} -> {:?}", path, new_path);
+    if dry_run {
+        return Ok(Some(Outcome::Planned));
+    }
+    if new_path.exists() {
+        eprintln!("Skipping {:?} as {:?} already exists", path, new_path);
+        return Ok(Some(Outcome::Skipped));
+    }
+    let bytes = metadata(path)?.len();
+    std::fs::rename(path, &new_path)?;
+    Ok(Some(Outcome::Renamed { bytes }))
+}
+
+/// Copy a directory and everything in it, returning the bytes copied
+fn copy_dir_all(from: &Path, to: &Path) -> std::io::Result<u64> {
+    std::fs::create_dir(to)?;
+    let mut bytes = 0;
+    for entry in read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        bytes += if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?
+        } else {
+            std::fs::copy(entry.path(), &target)?
+        };
+    }
+    Ok(bytes)
+}
+
+/// Give `path` the modified and accessed times of `source`, and the created
+/// time on windows where it can be set
+fn restore_times(source: &std::fs::Metadata, path: &Path) -> std::io::Result<()> {
+    let mut times = FileTimes::new();
+    if let Ok(modified) = source.modified() {
+        times = times.set_modified(modified);
+    }
+    if let Ok(accessed) = source.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    #[cfg(windows)]
+    if let Ok(created) = source.created() {
+        use std::os::windows::fs::FileTimesExt;
+        times = times.set_created(created);
+    }
+    OpenOptions::new().write(true).open(path)?.set_times(times)
+}
+
+/// Move or copy a whole disc folder, never replacing an existing destination
+fn move_disc(from: &Path, to: &Path, delete_old: bool) -> GenericResult<Outcome> {
+    if to.exists() {
+        eprintln!("Skipping {:?} as {:?} already exists", from, to);
+        return Ok(Outcome::Skipped);
+    }
+    if delete_old && files_on_same_drive(from, to.parent().unwrap_or(to))? {
+        std::fs::rename(from, to)?;
+        // Not worth walking the disc just to total it, nothing was copied
+        return Ok(Outcome::Renamed { bytes: 0 });
+    }
+    let bytes = copy_dir_all(from, to)?;
+    if delete_old {
+        std::fs::remove_dir_all(from)?;
+    }
+    Ok(Outcome::Copied { bytes })
+}
+
+/// Why a file is outside `--min-resolution` and `--max-resolution`, if it is
+fn resolution_rejection(
+    resolution: u64,
+    min_resolution: Option<u64>,
+    max_resolution: Option<u64>,
+    keep_unknown_resolution: bool,
+) -> Option<String> {
+    if min_resolution.is_none() && max_resolution.is_none() {
+        return None;
+    }
+    match (resolution, min_resolution, max_resolution) {
+        (0, _, _) if keep_unknown_resolution => None,
+        (0, _, _) => Some("its resolution is unknown".to_string()),
+        (resolution, Some(min_resolution), _) if resolution < min_resolution => {
+            Some(format!("{}p is below {}p", resolution, min_resolution))
+        }
+        (resolution, _, Some(max_resolution)) if resolution > max_resolution => {
+            Some(format!("{}p is above {}p", resolution, max_resolution))
+        }
+        _ => None,
+    }
+}
+
+/// The file already in the destination, found by `name` or, with a library
+/// for `--on-conflict upgrade`, by what it is under any other name
+fn find_existing(
+    to_directory: &Path,
+    name: &str,
+    case_insensitive: bool,
+    library: Option<(&Library, &str)>,
+    source: &Path,
+) -> std::io::Result<Option<PathBuf>> {
+    let by_name = if case_insensitive {
+        find_case_insensitive(to_directory, name)?
+    } else {
+        let path = to_directory.join(name);
+        match metadata(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Ok(_) => Some(path),
+            Err(e) => return Err(e),
+        }
+    };
+    Ok(by_name.or_else(|| {
+        let (library, key) = library?;
+        library
+            .find(key)
+            .filter(|path| !same_file(source, path))
+            .map(Path::to_path_buf)
+    }))
+}
+
+/// Whether `--on-conflict` replaces the `existing` destination with `file`
+fn replaces_existing(
+    on_conflict: ConflictPolicy,
+    file: &Video,
+    existing: &Path,
+    backend: MetadataBackend,
+) -> GenericResult<bool> {
+    // Running over files already in place finds each one as its own destination
+    if same_file(&file.path, existing) {
+        return Ok(false);
+    }
+    match on_conflict {
+        ConflictPolicy::Skip | ConflictPolicy::TrashSource => Ok(false),
+        ConflictPolicy::Overwrite => Ok(true),
+        ConflictPolicy::Upgrade => {
+            let existing_type = FileType::from_path(existing)?;
+            let existing_resolution =
+                Video::from_path_with_backend(existing.to_path_buf(), existing_type, backend)?
+                    .metadata()
+                    .get_resolution();
+            let resolution = file.metadata().get_resolution();
+            // Unknown resolutions can't be compared so they are never replaced
+            Ok(existing_resolution > 0 && resolution > existing_resolution)
+        }
+    }
+}
+
+/// Run the IMDB self-test for `--check-imdb`, failing if any step failed
+#[cfg(feature = "imdb")]
+fn check_imdb_index() -> GenericResult<()> {
+    eprintln!("Checking IMDB index");
+    let report = imdb::self_test(current_dir()?.join("datasets"));
+    println!("{}", report);
+    if report.passed() {
+        Ok(())
+    } else {
+        Err("IMDB self-test failed".into())
+    }
+}
+
+#[cfg(not(feature = "imdb"))]
+fn check_imdb_index() -> GenericResult<()> {
+    Err("--check-imdb requires the imdb feature".into())
+}
+
+/// Print what was read from a file without renaming it
+fn print_probe(file: &Video) {
+    let meta = file.metadata();
+    println!("{:?}", file.path);
+    println!("  Type:        {:?}", file.file_type);
+    println!(
+        "  Resolution:  {}x{} ({}p)",
+        meta.resolution.0,
+        meta.resolution.1,
+        meta.get_resolution()
+    );
+    if let Some(length) = meta.length {
+        println!("  Length:      {:?}", length);
+    }
+    if let Some(muxing_app) = &meta.muxing_app {
+        println!("  Muxing app:  {}", muxing_app);
+    }
+    if let Some(writing_app) = &meta.writing_app {
+        println!("  Writing app: {}", writing_app);
+    }
+    for (i, track) in meta.tracks.iter().enumerate() {
+        println!(
+            "  Track {}:     {:?} {} {}",
+            i + 1,
+            track.kind,
+            track.language.as_deref().unwrap_or("und"),
+            track
+                .name
+                .as_ref()
+                .map(|name| format!("{:?}", name))
+                .unwrap_or_default()
+        );
+    }
+    if file.file_type.is_matroska() {
+        println!("  Tags:        {}", tags_json(file));
+    }
+}
+
+/// Warn about files whose video tracks have different dimensions, the
+/// largest track is still the one used
+fn warn_if_multi_video(file: &Video) {
+    let tracks = &file.metadata().video_tracks;
+    if tracks.iter().any(|track| *track != tracks[0]) {
+        let dimensions: Vec<_> = tracks
+            .iter()
+            .map(|(width, height)| format!("{}x{}", width, height))
+            .collect();
+        eprintln!(
+            "Warning: {:?} has {} video tracks ({}), using the largest",
+            file.path,
+            tracks.len(),
+            dimensions.join(", ")
+        );
+    }
+}
+
+/// The tags that would be written to a file as JSON, sorted by name
+fn tags_json(file: &Video) -> String {
+    let tags: BTreeMap<_, _> = file
+        .compute_tags()
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .collect();
+    #[cfg(feature = "json")]
+    return serde_json::to_string(&tags).unwrap_or_default();
+    #[cfg(not(feature = "json"))]
+    return format!("{:?}", tags);
+}
+
+/// Print the tags already in a Matroska file for `--dump-tags`
+fn print_existing_tags(path: &Path) -> GenericResult<()> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let existing = read_matroska_tags(&mut file)?;
+    println!("{:?}", path);
+    if let Some(title) = &existing.title {
+        println!("  {:<20} {}", "(Info) Title", title);
+    }
+    if existing.tags.is_empty() {
+        println!("  No tags");
+    }
+    for (name, value) in existing.tags.iter() {
+        println!("  {:<20} {}", name, value);
+    }
+    Ok(())
+}
+
+/// Move a file, copying it when a rename isn't possible across drives
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+fn main() -> GenericResult<()> {
+    #[cfg(feature = "debug")]
+    {
+        simple_logger::SimpleLogger::new()
+            .with_level(log::LevelFilter::max())
+            .init()
+            .unwrap();
+    }
+
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    #[cfg(feature = "watch")]
+    if options.watch {
+        return watch::watch(options, run);
+    }
+    let summary = run(options)?;
+    // Nothing is processed by --probe, --dump-parse, --dump-tags or --check-imdb
+    if summary.seen > 0 {
+        eprintln!("{}", summary);
+    }
+    if summary.errored > 0 {
+        return Err(format!("{} files failed", summary.errored).into());
+    }
+    Ok(())
+}
+
+/// Process every video in the source directory once
+fn run(options: Options) -> GenericResult<Summary> {
+    let started = Instant::now();
+    let Options {
+        from_directory,
+        to_directory,
+        delete_old,
+        seedbox,
+        dry_run,
+        dont_recurse,
+        verbose,
+        embed_subtitles,
+        remux_mp4,
+        case_insensitive_dedupe,
+        state_file,
+        strip_year_from_title,
+        raw_titles,
+        walk_order,
+        disc_folders,
+        no_recurse_into,
+        on_conflict,
+        on_duplicate,
+        probe,
+        skip_hidden,
+        min_resolution,
+        max_resolution,
+        keep_unknown_resolution,
+        reject_dir,
+        sort,
+        stage,
+        preserve_time,
+        verify,
+        series,
+        season,
+        start_episode,
+        write_nfo,
+        overwrite_nfo,
+        title_from,
+        move_subtitles,
+        move_companions,
+        metadata_backend,
+        parent_as_fallback_title,
+        imdb_tag_format,
+        tag_source_path,
+        preflight,
+        force,
+        assume_yes,
+        interactive,
+        json,
+        allowed_dest_drives,
+        #[cfg_attr(not(feature = "imdb"), allow(unused_variables))]
+        imdb_when,
+        #[cfg_attr(not(feature = "imdb"), allow(unused_variables))]
+        imdb_kinds,
+        #[cfg_attr(not(feature = "imdb"), allow(unused_variables))]
+        no_cache,
+        extension_map,
+        max_files,
+        name_format,
+        dump_parse,
+        dump_tags,
+        #[cfg_attr(not(feature = "json"), allow(unused_variables))]
+        log,
+        #[cfg_attr(not(feature = "json"), allow(unused_variables))]
+        undo,
+        fix_extension_only,
+        warn_multi_video,
+        dest_exists_action,
+        check_imdb,
+        keep_original_list,
+        watch: _,
+    } = options;
+
+    if let Some(path) = dump_tags {
+        print_existing_tags(&path)?;
+        return Ok(Summary::default());
+    }
+
+    #[cfg(feature = "json")]
+    if let Some(path) = undo {
+        undo::undo(&path, dry_run)?;
+        return Ok(Summary::default());
+    }
+
+    if check_imdb {
+        check_imdb_index()?;
+        return Ok(Summary::default());
+    }
+
+    if fix_extension_only {
+        let mut summary = Summary::default();
+        let entries = read_dir_recursive(&from_directory, !dont_recurse)?
+            .with_order(walk_order)
+            .with_skip_hidden(skip_hidden)
+            .with_disc_folders(DiscFolders::Skip)
+            .with_no_recurse_into(&no_recurse_into);
+        for entry in entries {
+            let path = entry.path();
+            match fix_extension(&path, dry_run) {
+                Ok(Some(outcome)) => summary.record(outcome),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Error processing {:?}: {}", path, e);
+                    summary.record_failure(path, e.to_string());
+                }
+            }
+        }
+        summary.elapsed = started.elapsed();
+        return Ok(summary);
+    }
+
+    // The plan is worked out exactly as a dry run would
+    let dry_run = dry_run || json;
+    if interactive && dry_run {
+        return Err(
+            "--interactive asks before changing anything so can't be used with --dry or --json"
+                .into(),
+        );
+    }
+
+    if let (Some(min_resolution), Some(max_resolution)) = (min_resolution, max_resolution) {
+        if min_resolution > max_resolution {
+            return Err(format!(
+                "--min-resolution {}p is above --max-resolution {}p",
+                min_resolution, max_resolution
+            )
+            .into());
+        }
+    }
+
+    let prompter = Prompter::new(assume_yes);
+
+    eprintln!(
+        "Moving videos from {:?} -> {:?}",
+        from_directory, to_directory
+    );
+    eprintln!("  Delete old: {:?}", delete_old);
+    eprintln!("  Dry run:    {:?}", dry_run);
+    eprintln!("  Recursion:  {:?}", !dont_recurse);
+    if let Some(state_file) = &state_file {
+        // Dry runs still skip unchanged files so they show what a real run would do
+        eprintln!(
+            "  State file: {:?}{}",
+            state_file,
+            if dry_run { " (not updated)" } else { "" }
+        );
+    }
+
+    let dest_drive = drive_identifier(&to_directory)?;
+    if verbose {
+        if let Some(dest_drive) = dest_drive {
+            eprintln!("  Dest drive: {}", dest_drive);
+        }
+    }
+    if !allowed_dest_drives.is_empty()
+        && !dest_drive.is_some_and(|drive| allowed_dest_drives.contains(&drive))
+    {
+        return Err(format!(
+            "{:?} is on drive {} which is not an allowed destination drive",
+            to_directory,
+            dest_drive.map_or("unknown".to_string(), |drive| drive.to_string())
+        )
+        .into());
+    }
+
+    if seedbox {
+        if delete_old {
+            return Err(
+                "--seedbox keeps the originals for seeding so can't be used with --delete".into(),
+            );
+        }
+        if embed_subtitles {
+            return Err(
+                "--seedbox can't embed subtitles as hardlinks share the original's content".into(),
+            );
+        }
+        if on_conflict == ConflictPolicy::TrashSource {
+            return Err(
+                "--seedbox keeps the originals for seeding so can't be used with --on-conflict trash-source"
+                    .into(),
+            );
+        }
+        if !files_on_same_drive(&from_directory, &to_directory)? {
+            return Err(format!(
+                "--seedbox hardlinks files so {:?} must be on the same drive as {:?}",
+                to_directory, from_directory
+            )
+            .into());
+        }
+    }
+
+    let mut summary = Summary::default();
+    // TODO: Optimize parsing so only need to open file once
+    // Disc folders are the only directories the walk returns
+    let (discs, entries): (Vec<_>, Vec<_>) = read_dir_recursive(&from_directory, !dont_recurse)?
+        .with_order(walk_order)
+        .with_skip_hidden(skip_hidden)
+        .with_disc_folders(disc_folders)
+        .with_no_recurse_into(&no_recurse_into)
+        .partition(|entry| entry.file_type().is_ok_and(|t| t.is_dir()));
+    let mut files: Vec<_> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let (_, extension) = split_extension(&entry.file_name().to_string_lossy());
+            let mapped_type = extension_map.get(&extension).copied();
+            match mapped_type.map_or_else(|| FileType::from_path(entry.path()), Ok) {
+                Ok(FileType::Incomplete) => {
+                    if verbose {
+                        eprintln!("Skipping {:?} as it is empty or incomplete", entry.path());
+                    }
+                    None
+                }
+                Ok(video_type) if video_type != FileType::Unknown => {
+                    let mut video = match Video::from_path_with_backend(
+                        entry.path(),
+                        video_type,
+                        metadata_backend,
+                    ) {
+                        Ok(video) => video,
+                        Err(e) => {
+                            eprintln!("Error parsing {:?}: {}", entry.path(), e);
+                            summary.record_failure(entry.path(), e.to_string());
+                            return None;
+                        }
+                    };
+                    if let Some(extension) = mapped_type.and_then(|t| t.canonical_extension()) {
+                        video.file_extension = extension.to_string();
+                    }
+                    Some(video)
+                }
+                _ => None,
+            }
+        })
+        .collect();
+
+    if warn_multi_video {
+        files.iter().for_each(warn_if_multi_video);
+    }
+
+    for file in files.iter_mut() {
+        file.apply_title_source(title_from);
+        file.imdb_tag_format = imdb_tag_format;
+        if tag_source_path {
+            file.original_path = file
+                .path
+                .strip_prefix(&from_directory)
+                .ok()
+                .map(|path| path.to_string_lossy().replace('\\', "/"));
+        }
+        // Named as the MKV it will become
+        if remux_mp4 && file.file_type == FileType::MP4 {
+            file.file_extension = "mkv".to_string();
+        }
+    }
+
+    // A folder holding a single movie is usually named after it
+    if parent_as_fallback_title {
+        let mut videos_per_folder: BTreeMap<PathBuf, usize> = BTreeMap::new();
+        for file in files.iter() {
+            if let Some(parent) = file.path.parent() {
+                *videos_per_folder.entry(parent.to_path_buf()).or_default() += 1;
+            }
+        }
+        for file in files.iter_mut() {
+            let is_alone = file
+                .path
+                .parent()
+                .is_some_and(|parent| videos_per_folder.get(parent) == Some(&1));
+            if is_alone && file.path.parent() != Some(from_directory.as_path()) {
+                file.apply_movie_folder_title();
+            }
+        }
+    }
+
+    if !raw_titles {
+        files.iter_mut().for_each(Video::normalize_title_case);
+    }
+
+    if sort {
+        files.sort_by(|a, b| natural_cmp(&a.path.to_string_lossy(), &b.path.to_string_lossy()));
+    }
+
+    // Applied after sorting so the same files are picked each run
+    let found_files = files.len();
+    if let Some(max_files) = max_files {
+        files.truncate(max_files);
+    }
+    let files_limited = files.len() < found_files;
+
+    if let Some(series) = &series {
+        let file_count = files.len();
+        for (i, file) in files.iter_mut().enumerate() {
+            let episode = u32::try_from(i)
+                .ok()
+                .and_then(|i| start_episode.checked_add(i))
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "--start-episode {} is too large to number {} files",
+                            start_episode, file_count
+                        ),
+                    )
+                })?;
+            file.set_episode(series, season, episode);
+        }
+    }
+
+    if probe {
+        files.iter().for_each(print_probe);
+        return Ok(Summary::default());
+    }
+
+    if dump_parse {
+        for file in files.iter() {
+            let (_, trace) =
+                Video::from_path_traced(file.path.clone(), file.file_type, metadata_backend)?;
+            println!("{:?}", file.path);
+            println!("{}", trace);
+        }
+        return Ok(Summary::default());
+    }
+
+    // Names are checked as parsed, IMDB lookups happen later and are only
+    // covered by the per-file checks
+    if preflight {
+        let plan: Vec<_> = files
+            .iter()
+            .map(|file| {
+                let mut file = file.clone();
+                if strip_year_from_title {
+                    file.strip_year_from_title();
+                }
+                let name = match read_name_sidecar(&file.path) {
+                    Ok(name) => {
+                        Ok(name.unwrap_or_else(|| file.generate_file_name_with(&name_format)))
+                    }
+                    Err(e) => Err(e.to_string()),
+                };
+                preflight::PlannedFile {
+                    source: file.path,
+                    name,
+                }
+            })
+            .collect();
+        // Hardlinks take no space, just like renames, but remuxes are new files
+        let issues = preflight::check(
+            &plan,
+            &to_directory,
+            (delete_old || seedbox) && !remux_mp4,
+            case_insensitive_dedupe,
+        );
+        for issue in issues.iter() {
+            eprintln!("{:?}: {}", issue.path, issue.problem);
+        }
+        if !issues.is_empty()
+            && !force
+            && !prompter.confirm("Problems were found, continue anyway?", false)?
+        {
+            return Err(format!(
+                "Preflight found {} problems, nothing was moved (use --force to continue anyway)",
+                issues.len()
+            )
+            .into());
+        }
+    }
+
+    // Renaming works without IMDB so a missing dataset only disables lookups
+    #[cfg(feature = "imdb")]
+    let mut searcher = {
+        eprintln!("Opening IMDB index");
+        match imdb::open_searcher(current_dir()?.join("datasets")) {
+            Ok(searcher) => Some(searcher),
+            Err(e) => {
+                eprintln!("Warning: IMDB lookups disabled, {}", e);
+                None
+            }
+        }
+    };
+    #[cfg(feature = "imdb")]
+    let mut search_cache = match (&searcher, no_cache) {
+        (Some(_), false) => match imdb::SearchCache::open(current_dir()?.join("datasets")) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                eprintln!("Warning: IMDB search cache disabled, {}", e);
+                None
+            }
+        },
+        _ => None,
+    };
+
+    #[cfg(feature = "json")]
+    let mut state_file = match state_file {
+        Some(path) => Some(StateFile::open(path)?),
+        None => None,
+    };
+    let keep_list = match keep_original_list {
+        Some(path) => Some(KeepList::open(path)?),
+        None => None,
+    };
+
+    let mut rejected = 0;
+    let library = match on_conflict {
+        ConflictPolicy::Upgrade => Some(Library::scan(&to_directory, metadata_backend)?),
+        _ => None,
+    };
+
+    // Files are written to a staging directory on the destination drive and
+    // moved into place at the end so nothing watching sees partial files
+    let work_directory = if stage {
+        to_directory.join(STAGING_DIRECTORY)
+    } else {
+        to_directory.clone()
+    };
+    if stage && !dry_run {
+        std::fs::create_dir_all(&work_directory)?;
+    }
+    let mut staged = Vec::new();
+    let mut review = Review::new(interactive);
+    let mut plan = Vec::new();
+    #[cfg(feature = "json")]
+    let mut undo_log = if dry_run {
+        None
+    } else {
+        Some(UndoLog::open(
+            log.unwrap_or_else(|| to_directory.join(UNDO_LOG_NAME)),
+        )?)
+    };
+
+    // Discs are moved whole, named after the folder holding VIDEO_TS or BDMV
+    for disc in discs {
+        if review.has_quit() {
+            break;
+        }
+        let path = disc.path();
+        let destination = to_directory.join(disc.file_name());
+        if json {
+            let action = if destination.exists() {
+                PlannedAction::Skip
+            } else {
+                Operation::new(files_on_same_drive(&path, &to_directory)?, delete_old).into()
+            };
+            plan.push(PlannedOperation::for_disc(path, destination, action));
+            summary.record(Outcome::Planned);
+            continue;
+        }
+        println!("{:?} -> {:?} (disc)", path, destination);
+        if dry_run {
+            summary.record(Outcome::Planned);
+            continue;
+        }
+        if !review.approve(&prompter)? {
+            summary.record(Outcome::Skipped);
+            continue;
+        }
+        match move_disc(&path, &destination, delete_old) {
+            Ok(outcome) => summary.record(outcome),
+            Err(e) => {
+                eprintln!("Error processing {:?}: {}", path, e);
+                summary.record_failure(path, e.to_string());
+            }
+        }
+    }
+
+    // Names given out so far in this run and the file each went to
+    let mut used_names = UsedNames::new(case_insensitive_dedupe);
+
+    for mut file in files {
+        if review.has_quit() {
+            break;
+        }
+        let path = file.path.clone();
+        // Errors are recorded against the file so the rest can still be processed
+        let mut process = || -> GenericResult<Outcome> {
+            if let Some(reason) = resolution_rejection(
+                file.metadata().get_resolution(),
+                min_resolution,
+                max_resolution,
+                keep_unknown_resolution,
+            ) {
+                rejected += 1;
+                if json {
+                    let reject_path = reject_dir
+                        .as_ref()
+                        .map(|reject_dir| reject_dir.join(file.path.file_name().unwrap()));
+                    plan.push(PlannedOperation::for_video(
+                        &file,
+                        reject_path,
+                        PlannedAction::Reject,
+                    ));
+                    return Ok(Outcome::Skipped);
+                }
+                match &reject_dir {
+                    Some(reject_dir) => {
+                        let reject_path = reject_dir.join(file.path.file_name().unwrap());
+                        println!("{:?} -> {:?} (rejected)", file.path, reject_path);
+                        if !dry_run {
+                            std::fs::create_dir_all(reject_dir)?;
+                            move_file(&file.path, &reject_path)?;
+                        }
+                    }
+                    None => eprintln!("Skipping {:?} as {}", file.path, reason),
+                }
+                return Ok(Outcome::Skipped);
+            }
+
+            #[cfg(feature = "json")]
+            let fingerprint = Fingerprint::from_path(&file.path)?;
+            // Taken now as the source may be gone by the time the copy is done
+            let source_metadata = metadata(&file.path)?;
+            #[cfg(feature = "json")]
+            if let Some(state_file) = state_file.as_ref() {
+                if state_file.is_unchanged(&file.path, &fingerprint) {
+                    if verbose {
+                        eprintln!("Skipping {:?} as it was already processed", file.path);
+                    }
+                    if json {
+                        plan.push(PlannedOperation::for_video(
+                            &file,
+                            None,
+                            PlannedAction::Skip,
+                        ));
+                    }
+                    return Ok(Outcome::Skipped);
+                }
+            }
+
+            if strip_year_from_title {
+                file.strip_year_from_title();
+            }
+
+            let name_override = read_name_sidecar(&file.path)?;
+            let new_file_name = name_override
+                .clone()
+                .unwrap_or_else(|| file.generate_file_name_with(&name_format));
+            let new_file_name = match used_names.claim(&new_file_name, &file.path, on_duplicate) {
+                Ok(name) => name,
+                Err(other) => {
+                    eprintln!(
+                        "Skipping {:?} as {:?} is also named {:?}",
+                        file.path, other, new_file_name
+                    );
+                    if json {
+                        plan.push(PlannedOperation::for_video(
+                            &file,
+                            None,
+                            PlannedAction::Skip,
+                        ));
+                    }
+                    return Ok(Outcome::Skipped);
+                }
+            };
+            // Taken before IMDB changes the titles, like the name
+            let key = video_key(&file);
+            let final_file_path = to_directory.join(&new_file_name);
+            let mut new_file_path = work_directory.join(&new_file_name);
+            if !json {
+                println!("{:?} -> {:?}", file.path, final_file_path);
+            }
+
+            // Kept files are copied as if `--delete` wasn't given
+            let delete_old = delete_old
+                && !keep_list
+                    .as_ref()
+                    .is_some_and(|list| list.contains(&file.path));
+            let same_drive = files_on_same_drive(&file.path, &to_directory)?;
+            let operation = if seedbox {
+                Operation::Hardlink
+            } else {
+                Operation::new(same_drive, delete_old)
+            };
+
+            #[cfg(feature = "imdb")]
+            if let (None, Some(searcher), true) = (
+                &name_override,
+                searcher.as_mut(),
+                file.needs_imdb_lookup(imdb_when),
+            ) {
+                if let Ok(Some(result)) =
+                    imdb::search_for_video(searcher, &file.info, &imdb_kinds, search_cache.as_mut())
+                {
+                    file.update_from_imdb(&result)?;
+                }
+            }
+
+            let will_be_mkv =
+                file.file_type.is_matroska() || (remux_mp4 && file.file_type == FileType::MP4);
+            let subtitles = if embed_subtitles && will_be_mkv {
+                Subtitle::find_for_video(&file.path)?
+                    .iter()
+                    .map(|path| Subtitle::from_path(path, &file.path))
+                    .collect::<GenericResult<Vec<_>>>()?
+            } else {
+                Vec::new()
+            };
+
+            // Subtitles that aren't embedded keep their qualifiers, e.g. `.en.forced.srt`
+            let mut sidecar_sources = Vec::new();
+            if (move_subtitles || move_companions) && subtitles.is_empty() {
+                sidecar_sources.extend(Subtitle::find_for_video(&file.path)?);
+            }
+            if move_companions {
+                sidecar_sources.extend(find_companions(&file.path)?);
+            }
+            let (new_file_stem, _) = split_extension(&new_file_name);
+            let sidecars: Vec<_> = sidecar_sources
+                .into_iter()
+                .filter_map(|path| {
+                    let suffix = sidecar_suffix(&path, &file.path)?;
+                    let name = format!("{}{}", new_file_stem, suffix);
+                    Some((path, work_directory.join(&name), to_directory.join(&name)))
+                })
+                .collect();
+
+            if interactive {
+                // Shows the title found on IMDB, which only goes into the tags
+                if file.file_type.is_matroska() {
+                    println!("  tags {}", tags_json(&file));
+                }
+                if !review.approve(&prompter)? {
+                    used_names.release(&new_file_name);
+                    return Ok(Outcome::Skipped);
+                }
+            }
+
+            if json {
+                let existing = find_existing(
+                    &to_directory,
+                    &new_file_name,
+                    case_insensitive_dedupe,
+                    library.as_ref().map(|library| (library, key.as_str())),
+                    &file.path,
+                )?;
+                let action = match existing {
+                    Some(existing)
+                        if !replaces_existing(on_conflict, &file, &existing, metadata_backend)? =>
+                    {
+                        PlannedAction::Skip
+                    }
+                    _ => operation.into(),
+                };
+                plan.push(PlannedOperation::for_video(
+                    &file,
+                    Some(final_file_path),
+                    action,
+                ));
+                return Ok(Outcome::Planned);
+            }
+
+            if dry_run {
+                println!("  {}", operation.describe());
+                if write_nfo && nfo::build_nfo(&file.info).is_some() {
+                    println!("  write {:?}", final_file_path.with_extension("nfo"));
+                }
+                if verbose && file.file_type.is_matroska() {
+                    println!("  tags {}", tags_json(&file));
+                }
+                for subtitle in subtitles.iter() {
+                    println!(
+                        "  embed {:?} ({}, {} events)",
+                        subtitle.path,
+                        subtitle.language,
+                        subtitle.events.len()
+                    );
+                }
+                for (sidecar, _, final_path) in sidecars.iter() {
+                    println!("  sidecar {:?} -> {:?}", sidecar, final_path);
+                }
+                return Ok(Outcome::Planned);
+            }
+
+            let mut is_copied = false;
+            let mut is_metadata_written = false;
+
+            // TODO: Convert mp4 to mkv
+            let existing = find_existing(
+                &to_directory,
+                &new_file_name,
+                case_insensitive_dedupe,
+                library.as_ref().map(|library| (library, key.as_str())),
+                &file.path,
+            )?;
+
+            if let Some(existing) = existing {
+                // Re-running over a library already in place finds each source
+                // as its own destination, which must never be removed
+                let is_source = same_file(&file.path, &existing);
+                if dest_exists_action == DestExistsAction::HashCompare
+                    && !is_source
+                    && files_hash_identical(&file.path, &existing)?
+                {
+                    eprintln!("Skipping {:?} as {:?} is identical", file.path, existing);
+                    // Like every other delete, sources in the keep list aren't `delete_old`
+                    if delete_old {
+                        std::fs::remove_file(&file.path)?;
+                        #[cfg(feature = "json")]
+                        if let Some(undo_log) = undo_log.as_mut() {
+                            undo_log.record(&LogEntry {
+                                source: file.path.clone(),
+                                destination: existing.clone(),
+                                original_kept: false,
+                                backup: None,
+                                duplicate: true,
+                            })?;
+                        }
+                    }
+                    return Ok(Outcome::Skipped);
+                }
+                if on_conflict == ConflictPolicy::TrashSource
+                    && !is_source
+                    && files_hash_identical(&file.path, &existing)?
+                {
+                    eprintln!(
+                        "Moving {:?} to trash as {:?} is identical",
+                        file.path, existing
+                    );
+                    trash::delete(&file.path)?;
+                    return Ok(Outcome::Skipped);
+                }
+                if replaces_existing(on_conflict, &file, &existing, metadata_backend)? {
+                    eprintln!(
+                        "Moving {:?} to trash to replace it with {:?}",
+                        existing, file.path
+                    );
+                    trash::delete(&existing)?;
+                } else {
+                    eprintln!(
+                        "Skipping {:?} as {:?} already exists",
+                        new_file_name, existing
+                    );
+                    new_file_path = existing;
+                    is_copied = true;
+                }
+            }
+
+            let is_staged = stage && !is_copied;
+            // Templates can put files in directories of their own
+            if let Some(parent) = new_file_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            let is_remuxed = remux_mp4 && !is_copied && file.file_type == FileType::MP4;
+            #[cfg(feature = "remux")]
+            if is_remuxed {
+                file.remux_to_matroska(&new_file_path)?;
+                if delete_old {
+                    std::fs::remove_file(&file.path)?;
+                }
+            }
+
+            if !is_copied && !is_remuxed {
+                // Use OS builtin API if on same drive as instant
+                if operation == Operation::Rename {
+                    std::fs::rename(&file.path, &new_file_path)?;
+                } else if operation == Operation::Hardlink {
+                    std::fs::hard_link(&file.path, &new_file_path)?;
+                } else {
+                    let mut old_file = OpenOptions::new().read(true).open(&file.path)?;
+                    let mut new_file = OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&new_file_path)?;
+                    let total_len = old_file.metadata()?.len();
+                    if file.file_type.is_matroska() {
+                        let mut reader = ProgressReader::new(&mut old_file, total_len);
+                        file.insert_into_matroska_with_subtitles(
+                            &mut reader,
+                            &mut new_file,
+                            &subtitles,
+                        )?;
+                        reader.finish();
+                        is_metadata_written = true;
+                    } else {
+                        copy_with_progress(&mut old_file, &mut new_file, total_len)?;
+                    }
+                    if operation == Operation::CopyAndDelete {
+                        if verify && is_metadata_written {
+                            verify_matroska_streams(&file.path, &new_file_path)?;
+                        } else if verify {
+                            verify_copy(&file.path, &new_file_path)?;
+                        }
+                        std::fs::remove_file(&file.path)?;
+                    }
+                }
+            }
+
+            let mut backup = None;
+            // Writing tags into a hardlink would change the file being seeded
+            if !is_metadata_written && file.file_type.is_matroska() && (!seedbox || is_remuxed) {
+                // TODO: Write metadata
+                eprintln!("Updating metadata");
+                let mut old_file = OpenOptions::new().read(true).open(&new_file_path)?;
+                let meta_path = new_file_path.with_extension("with_meta");
+                let mut new_file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&meta_path)?;
+
+                let total_len = old_file.metadata()?.len();
+                let mut reader = ProgressReader::new(&mut old_file, total_len);
+                file.insert_into_matroska_with_subtitles(&mut reader, &mut new_file, &subtitles)?;
+                reader.finish();
+                if verify {
+                    if let Err(e) = verify_matroska_streams(&new_file_path, &meta_path) {
+                        let _ = std::fs::remove_file(&meta_path);
+                        return Err(e);
+                    }
+                }
+                let backup_path = new_file_path.with_extension("mkv.bak");
+                // A fresh remux has nothing worth backing up
+                if !delete_old && !is_remuxed {
+                    std::fs::rename(&new_file_path, &backup_path)?;
+                    backup = Some(backup_path);
+                }
+                std::fs::rename(&meta_path, &new_file_path)?;
+                is_metadata_written = true;
+            }
+
+            // Renames and hardlinks keep their times, anything written anew gets the source's
+            let is_written = is_remuxed
+                || is_metadata_written
+                || matches!(operation, Operation::Copy | Operation::CopyAndDelete);
+            if preserve_time && is_written && !is_copied {
+                if let Err(e) = restore_times(&source_metadata, &new_file_path) {
+                    eprintln!(
+                        "Warning: unable to preserve the times of {:?}, {}",
+                        file.path, e
+                    );
+                }
+            }
+
+            // Only the undo log needs the backup
+            #[cfg(not(feature = "json"))]
+            drop(backup);
+            #[cfg(feature = "json")]
+            if let (Some(undo_log), false) = (undo_log.as_mut(), is_copied) {
+                undo_log.record(&LogEntry {
+                    source: file.path.clone(),
+                    destination: final_file_path.clone(),
+                    original_kept: file.path.exists(),
+                    backup,
+                    duplicate: false,
+                })?;
+            }
+
+            // Subtitles are only removed once they are safely inside the new file
+            if delete_old {
+                for subtitle in subtitles {
+                    std::fs::remove_file(&subtitle.path)?;
+                }
+            }
+
+            if !is_copied {
+                for (sidecar, new_path, final_path) in sidecars {
+                    if delete_old {
+                        move_file(&sidecar, &new_path)?;
+                    } else {
+                        std::fs::copy(&sidecar, &new_path)?;
+                    }
+                    #[cfg(feature = "json")]
+                    if let Some(undo_log) = undo_log.as_mut() {
+                        undo_log.record(&LogEntry {
+                            source: sidecar,
+                            destination: final_path.clone(),
+                            original_kept: !delete_old,
+                            backup: None,
+                            duplicate: false,
+                        })?;
+                    }
+                    if is_staged {
+                        staged.push((new_path, final_path));
+                    }
+                }
+            }
+
+            if write_nfo {
+                // Staged NFOs are checked against where they will end up
+                let nfo_target = if is_staged {
+                    final_file_path.with_extension("nfo")
+                } else {
+                    new_file_path.with_extension("nfo")
+                };
+                if overwrite_nfo || !nfo_target.exists() {
+                    if let Some(nfo_path) =
+                        nfo::write_nfo(&new_file_path, &file.info, overwrite_nfo)?
+                    {
+                        if is_staged {
+                            staged.push((nfo_path, nfo_target));
+                        }
+                    }
+                }
+            }
+
+            if is_staged {
+                staged.push((new_file_path, final_file_path));
+            }
+
+            // Only recorded once everything above succeeded, dry runs never get here
+            #[cfg(feature = "json")]
+            if let Some(state_file) = state_file.as_mut() {
+                state_file.record(&file.path, fingerprint)?;
+            }
+
+            let bytes = source_metadata.len();
+            Ok(if is_copied {
+                Outcome::Skipped
+            } else if operation == Operation::Rename || operation == Operation::Hardlink {
+                Outcome::Renamed { bytes }
+            } else {
+                Outcome::Copied { bytes }
+            })
+        };
+        match process() {
+            Ok(outcome) => summary.record(outcome),
+            Err(e) => {
+                eprintln!("Error processing {:?}: {}", path, e);
+                summary.record_failure(path, e.to_string());
+            }
+        }
+    }
+
+    #[cfg(feature = "imdb")]
+    if let Some(Err(e)) = search_cache.as_mut().map(imdb::SearchCache::save) {
+        eprintln!("Warning: unable to save the IMDB search cache, {}", e);
+    }
+
+    #[cfg(feature = "json")]
+    if json {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+    }
+
+    // Staged files are complete so move them into place, this is atomic as
+    // the staging directory is inside the destination
+    for (staged_path, final_path) in staged {
+        if let Some(parent) = final_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&staged_path, &final_path)?;
+    }
+    if stage && !dry_run {
+        // Left behind if anything failed so the files can be recovered
+        remove_empty_directories(&work_directory);
+    }
+
+    if files_limited {
+        eprintln!(
+            "Limited to the first {} of {} files by --max-files",
+            max_files.unwrap_or_default(),
+            found_files
+        );
+    }
+    if min_resolution.is_some() || max_resolution.is_some() {
+        eprintln!("Rejected {} files outside the resolution limits", rejected);
+    }
+
+    summary.elapsed = started.elapsed();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// An AVI big enough not to look like a broken download, its resolution
+    /// comes from its name
+    fn write_avi(path: &Path) {
+        let mut contents = b"RIFF\0\0\0\0AVI ".to_vec();
+        contents.resize(2048, 0);
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn upgrade_replaces_lower_resolution_under_another_name() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let root = temp.path();
+        let (from, to) = (root.join("from"), root.join("to"));
+        std::fs::create_dir_all(&from).unwrap();
+        std::fs::create_dir_all(&to).unwrap();
+        let existing = to.join("Movie (2010)-720p.avi");
+        let source = from.join("Movie.2010.1080p.avi");
+        write_avi(&existing);
+        write_avi(&source);
+
+        let backend = MetadataBackend::Native;
+        let file = Video::from_path_with_backend(source, FileType::AVI, backend).unwrap();
+        let library = Library::scan(&to, backend).unwrap();
+        let key = video_key(&file);
+        let found = find_existing(
+            &to,
+            "Movie (2010)-1080p.avi",
+            false,
+            Some((&library, &key)),
+            &file.path,
+        )
+        .unwrap();
+        assert_eq!(found.as_deref(), Some(existing.as_path()));
+        assert!(replaces_existing(ConflictPolicy::Upgrade, &file, &existing, backend).unwrap());
+        assert!(!replaces_existing(ConflictPolicy::Skip, &file, &existing, backend).unwrap());
+    }
+
+    #[test]
+    fn templated_directories_are_searched() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let root = temp.path();
+        std::fs::create_dir_all(root.join("Show/Season 01")).unwrap();
+        let existing = root.join("Show/Season 01/show - s01e01.avi");
+        write_avi(&existing);
+
+        let found = find_case_insensitive(root, "Show/Season 01/Show - S01E01.avi").unwrap();
+        assert_eq!(found.as_deref(), Some(existing.as_path()));
+        // A directory that isn't there yet has nothing in it
+        assert!(find_case_insensitive(root, "Other/Show - S01E01.avi")
+            .unwrap()
+            .is_none());
+        assert!(is_legal_relative_name("Show/Season 01/Show - S01E01.avi"));
+        assert!(!is_legal_relative_name("Show/../Show - S01E01.avi"));
+    }
+
+    #[test]
+    fn fix_extension_renames_only_mismatched_files() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let root = temp.path();
+        let matroska = |path: &Path| {
+            let mut contents = vec![0x1a, 0x45, 0xdf, 0xa3];
+            contents.resize(2048, 1);
+            std::fs::write(path, contents).unwrap();
+        };
+        let wrong = root.join("Some.Movie.2010.mp4");
+        let right = root.join("Other.Movie.2011.mkv");
+        matroska(&wrong);
+        matroska(&right);
+
+        assert!(matches!(
+            fix_extension(&right, false).unwrap(),
+            Some(Outcome::Skipped)
+        ));
+        assert!(right.exists());
+        assert!(matches!(
+            fix_extension(&wrong, true).unwrap(),
+            Some(Outcome::Planned)
+        ));
+        assert!(wrong.exists());
+        assert!(matches!(
+            fix_extension(&wrong, false).unwrap(),
+            Some(Outcome::Renamed { .. })
+        ));
+        assert!(!wrong.exists());
+        assert!(root.join("Some.Movie.2010.mkv").exists());
+    }
+
+    #[test]
+    fn min_resolution_keeps_only_larger_videos() {
+        let videos: Vec<Video> = ["Some.Movie.2010.480p.avi", "Other.Movie.2011.1080p.avi"]
+            .iter()
+            .map(|name| {
+                Video::from_path_with_backend(
+                    PathBuf::from(name),
+                    FileType::AVI,
+                    MetadataBackend::Native,
+                )
+                .unwrap()
+            })
+            .collect();
+        let kept: Vec<&Path> = videos
+            .iter()
+            .filter(|video| {
+                resolution_rejection(video.metadata().get_resolution(), Some(720), None, false)
+                    .is_none()
+            })
+            .map(|video| video.path.as_path())
+            .collect();
+        assert_eq!(kept, [Path::new("Other.Movie.2011.1080p.avi")]);
+
+        assert_eq!(
+            resolution_rejection(480, Some(720), None, false).as_deref(),
+            Some("480p is below 720p")
+        );
+        assert_eq!(
+            resolution_rejection(2160, None, Some(1080), false).as_deref(),
+            Some("2160p is above 1080p")
+        );
+    }
+
+    #[test]
+    fn unknown_resolution_is_kept_on_request() {
+        assert_eq!(
+            resolution_rejection(0, Some(720), None, false).as_deref(),
+            Some("its resolution is unknown")
+        );
+        assert_eq!(resolution_rejection(0, Some(720), None, true), None);
+        assert_eq!(resolution_rejection(0, None, Some(1080), true), None);
+        // Without limits nothing is rejected, even an unknown resolution
+        assert_eq!(resolution_rejection(0, None, None, false), None);
+    }
+
+    #[test]
+    fn copied_file_keeps_the_source_times() {
+        use std::time::{Duration, SystemTime};
+
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let root = temp.path();
+        let (source, copy) = (root.join("Movie.2010.avi"), root.join("Movie (2010).avi"));
+        write_avi(&source);
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        OpenOptions::new()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(modified))
+            .unwrap();
+        std::fs::copy(&source, &copy).unwrap();
+
+        restore_times(&metadata(&source).unwrap(), &copy).unwrap();
+        let copied = metadata(&copy).unwrap().modified().unwrap();
+        let difference = copied
+            .duration_since(modified)
+            .unwrap_or_else(|e| e.duration());
+        assert!(difference < Duration::from_secs(1), "{:?}", difference);
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::types::{Entity, Episode, GenericResult, VideoData};
+
+/// Escape text for use inside an XML element
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn push_element(xml: &mut String, name: &str, value: &str) {
+    xml.push_str(&format!("  <{}>{}</{}>\n", name, escape_xml(value), name));
+}
+
+fn push_imdb_id(xml: &mut String, imdb_id: &Option<String>) {
+    if let Some(imdb_id) = imdb_id {
+        xml.push_str(&format!(
+            "  <uniqueid type=\"imdb\" default=\"true\">{}</uniqueid>\n",
+            escape_xml(imdb_id)
+        ));
+    }
+}
+
+fn movie_nfo(movie: &Entity) -> Option<String> {
+    if movie.title.is_empty() {
+        return None;
+    }
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    xml.push_str("<movie>\n");
+    push_element(&mut xml, "title", &movie.title);
+    if movie.release_year != 0 {
+        push_element(&mut xml, "year", &movie.release_year.to_string());
+    }
+    push_imdb_id(&mut xml, &movie.imdb_id);
+    xml.push_str("</movie>\n");
+    Some(xml)
+}
+
+fn episode_nfo(episode: &Episode) -> Option<String> {
+    if episode.series.title.is_empty() || episode.episode == 0 {
+        return None;
+    }
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    xml.push_str("<episodedetails>\n");
+    if !episode.title.is_empty() {
+        push_element(&mut xml, "title", &episode.title);
+    }
+    push_element(&mut xml, "showtitle", &episode.series.title);
+    push_element(&mut xml, "season", &episode.season.to_string());
+    push_element(&mut xml, "episode", &episode.episode.to_string());
+    if episode.series.release_year != 0 {
+        push_element(&mut xml, "year", &episode.series.release_year.to_string());
+    }
+    push_imdb_id(&mut xml, &episode.imdb_id);
+    xml.push_str("</episodedetails>\n");
+    Some(xml)
+}
+
+/// Build a Kodi NFO document, `None` if there isn't enough information
+pub fn build_nfo(info: &VideoData) -> Option<String> {
+    match info {
+        VideoData::Movie(movie, _) => movie_nfo(movie),
+        VideoData::Episode(episode, _) => episode_nfo(episode),
+    }
+}
+
+/// Write a `<basename>.nfo` next to the video.
+///
+/// Returns the path written, or `None` if the video lacked the data for an
+/// NFO or one already exists and `overwrite` is false.
+pub fn write_nfo(
+    video_path: &Path,
+    info: &VideoData,
+    overwrite: bool,
+) -> GenericResult<Option<PathBuf>> {
+    let xml = match build_nfo(info) {
+        Some(xml) => xml,
+        None => return Ok(None),
+    };
+    let nfo_path = video_path.with_extension("nfo");
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let mut file = match options.open(&nfo_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    file.write_all(xml.as_bytes())?;
+    Ok(Some(nfo_path))
+}
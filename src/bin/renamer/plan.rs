@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use crate::types::Video;
+use crate::Operation;
+
+/// What will be done to a file in the `--json` plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum PlannedAction {
+    Rename,
+    Copy,
+    #[cfg_attr(feature = "serde", serde(rename = "copy+delete"))]
+    CopyAndDelete,
+    Hardlink,
+    /// Left alone, e.g. already processed or named like another file
+    Skip,
+    /// Outside `--min-resolution` or `--max-resolution`, moved to the `--reject-dir` when given
+    Reject,
+}
+
+impl From<Operation> for PlannedAction {
+    fn from(operation: Operation) -> Self {
+        match operation {
+            Operation::Rename => PlannedAction::Rename,
+            Operation::Copy => PlannedAction::Copy,
+            Operation::CopyAndDelete => PlannedAction::CopyAndDelete,
+            Operation::Hardlink => PlannedAction::Hardlink,
+        }
+    }
+}
+
+/// One entry of the `--json` plan, kept apart from the internal types so the
+/// output only changes on purpose
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+pub struct PlannedOperation {
+    pub source: PathBuf,
+    /// Missing when the file is skipped where it is
+    pub destination: Option<PathBuf>,
+    /// `mkv`, `mp4`, `avi` or `disc`
+    pub file_type: String,
+    /// Vertical resolution, missing when unknown
+    pub resolution: Option<u64>,
+    pub imdb_id: Option<String>,
+    pub action: PlannedAction,
+}
+
+impl PlannedOperation {
+    pub fn for_video(video: &Video, destination: Option<PathBuf>, action: PlannedAction) -> Self {
+        let resolution = video.metadata().get_resolution();
+        Self {
+            source: video.path.clone(),
+            destination,
+            file_type: video
+                .file_type
+                .canonical_extension()
+                .unwrap_or("unknown")
+                .to_string(),
+            resolution: (resolution > 0).then_some(resolution),
+            imdb_id: video.imdb_id().map(str::to_string),
+            action,
+        }
+    }
+
+    pub fn for_disc(source: PathBuf, destination: PathBuf, action: PlannedAction) -> Self {
+        Self {
+            source,
+            destination: Some(destination),
+            file_type: "disc".to_string(),
+            resolution: None,
+            imdb_id: None,
+            action,
+        }
+    }
+}
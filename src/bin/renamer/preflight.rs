@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fs::{metadata, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::file_drive::{available_space, files_on_same_drive};
+use crate::{find_case_insensitive, is_legal_relative_name};
+
+/// A file that is about to be moved and the name it will be given
+pub struct PlannedFile {
+    pub source: PathBuf,
+    /// `Err` when the name couldn't be worked out, e.g. a bad `.name` sidecar
+    pub name: Result<String, String>,
+}
+
+/// A problem with the plan, reported against the file it affects
+pub struct Issue {
+    pub path: PathBuf,
+    pub problem: String,
+}
+
+impl Issue {
+    fn new<P: AsRef<Path>, S: ToString>(path: P, problem: S) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            problem: problem.to_string(),
+        }
+    }
+}
+
+/// Check the whole plan before anything is moved, returning every problem found
+pub fn check(
+    plan: &[PlannedFile],
+    to_directory: &Path,
+    delete_old: bool,
+    case_insensitive: bool,
+) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut destinations: HashMap<String, &Path> = HashMap::new();
+    let mut bytes_needed = 0;
+
+    for file in plan {
+        let size = match OpenOptions::new()
+            .read(true)
+            .open(&file.source)
+            .and_then(|source| source.metadata())
+        {
+            Ok(meta) => meta.len(),
+            Err(e) => {
+                issues.push(Issue::new(&file.source, format!("unreadable: {}", e)));
+                continue;
+            }
+        };
+
+        let name = match &file.name {
+            Ok(name) => name,
+            Err(e) => {
+                issues.push(Issue::new(&file.source, e));
+                continue;
+            }
+        };
+        if !is_legal_relative_name(name) {
+            issues.push(Issue::new(
+                &file.source,
+                format!("{:?} is not a legal file name", name),
+            ));
+            continue;
+        }
+
+        let destination = to_directory.join(name);
+        let existing = if case_insensitive {
+            find_case_insensitive(to_directory, name)
+        } else {
+            match metadata(&destination) {
+                Ok(_) => Ok(Some(destination.clone())),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        };
+        match existing {
+            Ok(Some(existing)) => issues.push(Issue::new(
+                &file.source,
+                format!("{:?} already exists", existing),
+            )),
+            Ok(None) => {}
+            Err(e) => issues.push(Issue::new(
+                &file.source,
+                format!("can't check {:?}: {}", destination, e),
+            )),
+        }
+
+        let key = if case_insensitive {
+            name.to_lowercase()
+        } else {
+            name.clone()
+        };
+        if let Some(other) = destinations.insert(key, &file.source) {
+            issues.push(Issue::new(
+                &file.source,
+                format!("{:?} is also the destination of {:?}", destination, other),
+            ));
+        }
+
+        // Renames on the same drive don't need any space
+        let same_drive = files_on_same_drive(file.source.as_path(), to_directory).unwrap_or(false);
+        if !(same_drive && delete_old) {
+            bytes_needed += size;
+        }
+    }
+
+    match available_space(to_directory) {
+        Ok(available) if available < bytes_needed => issues.push(Issue::new(
+            to_directory,
+            format!(
+                "{} bytes are needed but only {} are available",
+                bytes_needed, available
+            ),
+        )),
+        Ok(_) => {}
+        Err(e) => issues.push(Issue::new(
+            to_directory,
+            format!("can't check available space: {}", e),
+        )),
+    }
+
+    issues
+}
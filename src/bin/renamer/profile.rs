@@ -0,0 +1,108 @@
+use std::str::FromStr;
+
+use crate::types::NameFormat;
+
+/// Option bundles matching the naming each media server recommends.
+///
+/// A profile only changes defaults, options given alongside it still win.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// `Movie (2010) - 1080p.mkv` and `Show (2020) - S01E05 - 1080p.mkv`, no NFOs
+    /// as Plex ignores them, subtitles moved alongside
+    Plex,
+    /// Plex naming, plus NFOs which Jellyfin reads before its own scrapers
+    Jellyfin,
+    /// Plex naming without the series year, which Kodi takes from the NFO, plus NFOs
+    Kodi,
+    /// The same as Jellyfin, which Emby shares its conventions with
+    Emby,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plex" => Ok(Profile::Plex),
+            "jellyfin" => Ok(Profile::Jellyfin),
+            "kodi" => Ok(Profile::Kodi),
+            "emby" => Ok(Profile::Emby),
+            _ => Err(format!(
+                "Unknown profile {:?}, expected plex, jellyfin, kodi or emby",
+                s
+            )),
+        }
+    }
+}
+
+impl Profile {
+    /// Set the defaults for this profile, called before other options are read
+    pub fn apply(
+        &self,
+        name_format: &mut NameFormat,
+        write_nfo: &mut bool,
+        move_subtitles: &mut bool,
+    ) {
+        name_format.section_separator = " - ".to_string();
+        name_format.movie_year = true;
+        name_format.series_year = *self != Profile::Kodi;
+        *write_nfo = *self != Profile::Plex;
+        *move_subtitles = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::magic::FileType;
+    use crate::types::{MetadataBackend, Video, VideoData};
+
+    use super::*;
+
+    /// The names a profile gives a sample movie and episode, and whether it writes NFOs
+    fn names(profile: Profile) -> (String, String, bool) {
+        let mut name_format = NameFormat::default();
+        let (mut write_nfo, mut move_subtitles) = (false, false);
+        profile.apply(&mut name_format, &mut write_nfo, &mut move_subtitles);
+        assert!(move_subtitles);
+
+        let parse = |name: &str| {
+            Video::from_path_with_backend(
+                PathBuf::from(name),
+                FileType::AVI,
+                MetadataBackend::Native,
+            )
+            .unwrap()
+        };
+        let movie = parse("Some.Movie.2010.1080p.avi");
+        let mut episode = parse("Show.S01E05.1080p.avi");
+        if let VideoData::Episode(episode, _) = &mut episode.info {
+            episode.series.release_year = 2020;
+        }
+        (
+            movie.generate_file_name_with(&name_format),
+            episode.generate_file_name_with(&name_format),
+            write_nfo,
+        )
+    }
+
+    #[test]
+    fn profiles_name_movies_and_episodes() {
+        let plex = (
+            "Some Movie (2010) - 1080p.avi".to_string(),
+            "Show (2020) - S01E05 - 1080p.avi".to_string(),
+            false,
+        );
+        assert_eq!(names(Profile::Plex), plex);
+        assert_eq!(
+            names(Profile::Jellyfin),
+            (plex.0.clone(), plex.1.clone(), true)
+        );
+        assert_eq!(names(Profile::Emby), names(Profile::Jellyfin));
+        assert_eq!(
+            names(Profile::Kodi),
+            (plex.0, "Show - S01E05 - 1080p.avi".to_string(), true)
+        );
+    }
+}
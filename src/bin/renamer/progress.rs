@@ -0,0 +1,118 @@
+use std::io::{stderr, ErrorKind, IsTerminal, Read, Write};
+use std::time::{Duration, Instant};
+
+/// How often the progress line is redrawn
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Bytes moved per read when copying
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Progress through a transfer of a known size, drawn on stderr when it is a
+/// terminal and silent otherwise
+pub struct Progress {
+    total_len: u64,
+    done: u64,
+    started: Instant,
+    last_report: Instant,
+    enabled: bool,
+    reported: bool,
+}
+
+impl Progress {
+    pub fn new(total_len: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            total_len,
+            done: 0,
+            started: now,
+            last_report: now,
+            enabled: stderr().is_terminal(),
+            reported: false,
+        }
+    }
+
+    pub fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        if self.enabled && self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        let percent = if self.total_len == 0 {
+            100.0
+        } else {
+            self.done as f64 * 100.0 / self.total_len as f64
+        };
+        let seconds = self.started.elapsed().as_secs_f64().max(f64::EPSILON);
+        eprint!(
+            "\r  {:5.1}% {} of {} bytes, {:.1} MB/s",
+            percent,
+            self.done,
+            self.total_len,
+            self.done as f64 / seconds / 1_000_000.0
+        );
+        let _ = stderr().flush();
+        self.last_report = Instant::now();
+        self.reported = true;
+    }
+
+    /// Draw the final state and end the line, if anything was drawn
+    pub fn finish(&mut self) {
+        if self.reported {
+            self.report();
+            eprintln!();
+        }
+    }
+}
+
+/// Reports progress as the wrapped reader is read, for transfers that are
+/// streamed through something other than `copy_with_progress`
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R, total_len: u64) -> Self {
+        Self {
+            inner,
+            progress: Progress::new(total_len),
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.progress.finish();
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.advance(read as u64);
+        Ok(read)
+    }
+}
+
+/// Copy `src` into `dst` in chunks, reporting progress through `total_len`
+/// bytes, returning the number of bytes copied
+pub fn copy_with_progress<R: Read, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+    total_len: u64,
+) -> std::io::Result<u64> {
+    let mut progress = Progress::new(total_len);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match src.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        dst.write_all(&buffer[..read])?;
+        progress.advance(read as u64);
+    }
+    progress.finish();
+    Ok(progress.done)
+}
@@ -0,0 +1,164 @@
+use std::io::{stderr, stdin, BufRead, IsTerminal, Write};
+
+/// Asks the user questions, taking the default answer with `--assume-yes` or
+/// when there is no terminal to ask so unattended runs never block.
+///
+/// Default answers:
+/// - continue after preflight problems: no
+/// - apply a change with `--interactive`: yes to all
+pub struct Prompter {
+    assume_yes: bool,
+}
+
+impl Prompter {
+    pub fn new(assume_yes: bool) -> Self {
+        Self { assume_yes }
+    }
+
+    /// Whether prompts are shown at all
+    pub fn is_interactive(&self) -> bool {
+        !self.assume_yes && stdin().is_terminal()
+    }
+
+    /// Ask a yes/no question, an empty answer picks `default`
+    pub fn confirm(&self, question: &str, default: bool) -> std::io::Result<bool> {
+        if !self.is_interactive() {
+            return Ok(default);
+        }
+        let choices = if default { "[Y/n]" } else { "[y/N]" };
+        loop {
+            eprint!("{} {} ", question, choices);
+            stderr().flush()?;
+            let mut answer = String::new();
+            if stdin().read_line(&mut answer)? == 0 {
+                return Ok(default);
+            }
+            match answer.trim().to_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// An answer when reviewing a change with `--interactive`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewAnswer {
+    Yes,
+    No,
+    /// Apply this and every following change
+    All,
+    /// Skip this and every following change
+    Quit,
+}
+
+/// Ask whether to apply a change, the end of input answers no so piped
+/// answers can't loop forever
+pub fn ask_review<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    question: &str,
+) -> std::io::Result<ReviewAnswer> {
+    loop {
+        write!(writer, "{} [y]es / [n]o / [a]ll / [q]uit ", question)?;
+        writer.flush()?;
+        let mut answer = String::new();
+        if reader.read_line(&mut answer)? == 0 {
+            writeln!(writer)?;
+            return Ok(ReviewAnswer::No);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(ReviewAnswer::Yes),
+            "n" | "no" => return Ok(ReviewAnswer::No),
+            "a" | "all" => return Ok(ReviewAnswer::All),
+            "q" | "quit" => return Ok(ReviewAnswer::Quit),
+            _ => continue,
+        }
+    }
+}
+
+/// Remembers `--interactive` answers that cover the rest of the run
+pub struct Review {
+    approve_all: bool,
+    quit: bool,
+}
+
+impl Review {
+    /// Without `--interactive` every change is approved
+    pub fn new(interactive: bool) -> Self {
+        Self {
+            approve_all: !interactive,
+            quit: false,
+        }
+    }
+
+    /// Whether to apply a change, asking on stdin even when it is piped
+    pub fn approve(&mut self, prompter: &Prompter) -> std::io::Result<bool> {
+        if self.quit {
+            return Ok(false);
+        }
+        if self.approve_all || prompter.assume_yes {
+            return Ok(true);
+        }
+        match ask_review(&mut stdin().lock(), &mut stderr(), "Apply?")? {
+            ReviewAnswer::Yes => Ok(true),
+            ReviewAnswer::No => Ok(false),
+            ReviewAnswer::All => {
+                self.approve_all = true;
+                Ok(true)
+            }
+            ReviewAnswer::Quit => {
+                self.quit = true;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Whether the user asked to stop
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn answer(input: &str) -> ReviewAnswer {
+        let mut output = Vec::new();
+        ask_review(&mut Cursor::new(input), &mut output, "Apply?").unwrap()
+    }
+
+    #[test]
+    fn review_answers() {
+        assert_eq!(answer("y\n"), ReviewAnswer::Yes);
+        assert_eq!(answer("NO\n"), ReviewAnswer::No);
+        assert_eq!(answer("a\n"), ReviewAnswer::All);
+        assert_eq!(answer("quit\n"), ReviewAnswer::Quit);
+        // Unknown answers ask again
+        assert_eq!(answer("maybe\n\ny\n"), ReviewAnswer::Yes);
+    }
+
+    #[test]
+    fn end_of_input_answers_no() {
+        assert_eq!(answer(""), ReviewAnswer::No);
+        assert_eq!(answer("maybe\n"), ReviewAnswer::No);
+
+        let mut output = Vec::new();
+        ask_review(&mut Cursor::new("maybe\n"), &mut output, "Apply?").unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Apply? [y]es / [n]o / [a]ll / [q]uit Apply? [y]es / [n]o / [a]ll / [q]uit \n"
+        );
+    }
+
+    #[test]
+    fn approved_without_asking() {
+        assert!(Review::new(false).approve(&Prompter::new(false)).unwrap());
+        assert!(Review::new(true).approve(&Prompter::new(true)).unwrap());
+    }
+}
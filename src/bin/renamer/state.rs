@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fs::{metadata, read_to_string, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::GenericResult;
+
+/// Size and modification time, used to tell if a file changed since it was processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub size: u64,
+    /// Nanoseconds since the unix epoch
+    pub modified: u128,
+}
+
+impl Fingerprint {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let meta = metadata(path)?;
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        Ok(Self {
+            size: meta.len(),
+            modified,
+        })
+    }
+}
+
+/// One line of the state file
+#[derive(Serialize, Deserialize)]
+struct StateEntry {
+    source: String,
+    size: u64,
+    modified: u128,
+}
+
+/// A JSON lines file recording which source files have already been processed.
+///
+/// Files are only recorded once fully processed, so a source that is new or
+/// whose fingerprint changed since (e.g. edited in place) is processed again.
+/// Each one is appended as it is recorded, a later line for the same source
+/// replacing earlier ones.
+pub struct StateFile {
+    path: PathBuf,
+    files: BTreeMap<String, Fingerprint>,
+    /// Opened on the first record so dry runs never create the file
+    file: Option<File>,
+    /// Whether the file has replaced lines or is in the old single object
+    /// format, so it is rewritten before anything is appended
+    needs_rewrite: bool,
+}
+
+/// Key a source by its absolute path, resolving the parent directory so the
+/// key is the same after the source has been moved or deleted
+fn state_key<P: AsRef<Path>>(source: P) -> String {
+    let source = source.as_ref();
+    let parent = match source.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => return source.to_string_lossy().to_string(),
+    };
+    match (parent.canonicalize(), source.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name).to_string_lossy().to_string(),
+        _ => source.to_string_lossy().to_string(),
+    }
+}
+
+impl StateFile {
+    /// Open a state file, starting with an empty state if it doesn't exist yet
+    pub fn open<P: AsRef<Path>>(path: P) -> GenericResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let contents = match read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        // Written by older versions as a single object
+        if let Ok(files) = serde_json::from_str::<BTreeMap<String, Fingerprint>>(&contents) {
+            return Ok(Self {
+                path,
+                files,
+                file: None,
+                needs_rewrite: true,
+            });
+        }
+        let mut files = BTreeMap::new();
+        let mut lines = 0;
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: StateEntry = serde_json::from_str(line).map_err(|e| {
+                format!("Line {} of state file {:?} is invalid: {}", i + 1, path, e)
+            })?;
+            let fingerprint = Fingerprint {
+                size: entry.size,
+                modified: entry.modified,
+            };
+            files.insert(entry.source, fingerprint);
+            lines += 1;
+        }
+        Ok(Self {
+            path,
+            needs_rewrite: lines > files.len(),
+            files,
+            file: None,
+        })
+    }
+
+    /// Whether the source was processed before and hasn't changed since
+    pub fn is_unchanged<P: AsRef<Path>>(&self, source: P, fingerprint: &Fingerprint) -> bool {
+        self.files.get(&state_key(source)) == Some(fingerprint)
+    }
+
+    /// Record a successfully processed source, appending it to the state file
+    /// immediately so an interrupted run doesn't lose progress
+    pub fn record<P: AsRef<Path>>(
+        &mut self,
+        source: P,
+        fingerprint: Fingerprint,
+    ) -> GenericResult<()> {
+        let source = state_key(source);
+        let line = entry_line(&source, &fingerprint)?;
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => {
+                if self.needs_rewrite {
+                    self.rewrite()?;
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                self.file.insert(file)
+            }
+        };
+        file.write_all(line.as_bytes())?;
+        self.files.insert(source, fingerprint);
+        Ok(())
+    }
+
+    /// Replace the file with one line for each source recorded so far
+    fn rewrite(&mut self) -> GenericResult<()> {
+        let temp_path = self.path.with_extension("tmp");
+        let mut contents = String::new();
+        for (source, fingerprint) in self.files.iter() {
+            contents.push_str(&entry_line(source, fingerprint)?);
+        }
+        std::fs::write(&temp_path, contents)?;
+        std::fs::rename(&temp_path, &self.path)?;
+        self.needs_rewrite = false;
+        Ok(())
+    }
+}
+
+/// A line of the state file, ending with a newline
+fn entry_line(source: &str, fingerprint: &Fingerprint) -> GenericResult<String> {
+    let mut line = serde_json::to_string(&StateEntry {
+        source: source.to_string(),
+        size: fingerprint.size,
+        modified: fingerprint.modified,
+    })?;
+    line.push('\n');
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn records_are_appended_and_replaced_on_open() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let root = temp.path();
+        let path = root.join("state.jsonl");
+        let source = root.join("video.mkv");
+        let first = Fingerprint {
+            size: 1,
+            modified: 10,
+        };
+        let second = Fingerprint {
+            size: 2,
+            modified: 20,
+        };
+
+        let mut state = StateFile::open(&path).unwrap();
+        assert!(!path.exists());
+        state.record(&source, first).unwrap();
+        state.record(&source, second).unwrap();
+        assert_eq!(read_to_string(&path).unwrap().lines().count(), 2);
+
+        // The later line wins and the file is compacted before the next append
+        let mut state = StateFile::open(&path).unwrap();
+        assert!(state.is_unchanged(&source, &second));
+        assert!(!state.is_unchanged(&source, &first));
+        state.record(root.join("other.mkv"), first).unwrap();
+        assert_eq!(read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn reads_the_old_single_object_format() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let root = temp.path();
+        let path = root.join("state.json");
+        let source = root.join("video.mkv");
+        let fingerprint = Fingerprint {
+            size: 3,
+            modified: 30,
+        };
+        let old = BTreeMap::from([(state_key(&source), fingerprint)]);
+        std::fs::write(&path, serde_json::to_string_pretty(&old).unwrap()).unwrap();
+
+        let mut state = StateFile::open(&path).unwrap();
+        assert!(state.is_unchanged(&source, &fingerprint));
+        state.record(root.join("other.mkv"), fingerprint).unwrap();
+        let lines = read_to_string(&path).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        assert!(StateFile::open(&path)
+            .unwrap()
+            .is_unchanged(&source, &fingerprint));
+    }
+}
@@ -0,0 +1,168 @@
+use std::fs::{read_to_string, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::move_file;
+use crate::types::GenericResult;
+
+/// One file put into place by a run, a line of the undo log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    /// Whether the source was left in place, i.e. it was copied or hardlinked
+    pub original_kept: bool,
+    /// The untagged copy kept when tags were written into the destination
+    #[serde(default)]
+    pub backup: Option<PathBuf>,
+    /// The source was removed as the destination, already there before the
+    /// run, is identical to it, so it is put back as a copy
+    #[serde(default)]
+    pub duplicate: bool,
+}
+
+/// A JSON lines file of every file put into place, so a run can be undone
+/// with `--undo`.
+///
+/// Entries are appended as soon as each file is in place so an interrupted
+/// run can still be undone. Tags written into a moved file and subtitles
+/// embedded into it can't be taken back out.
+pub struct UndoLog {
+    file: File,
+}
+
+impl UndoLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> GenericResult<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Unable to open undo log {:?}: {}", path, e))?;
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, entry: &LogEntry) -> GenericResult<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Put back every file in an undo log, newest first.
+///
+/// Moved files are moved back, copies are removed and sources removed as
+/// duplicates are copied back from the destination. An entry is skipped
+/// with a warning when its destination is gone, when a moved file's original
+/// path has been reused or when a copy's original has since been removed.
+pub fn undo<P: AsRef<Path>>(path: P, dry_run: bool) -> GenericResult<()> {
+    let path = path.as_ref();
+    let contents =
+        read_to_string(path).map_err(|e| format!("Unable to read undo log {:?}: {}", path, e))?;
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: LogEntry = serde_json::from_str(line)
+            .map_err(|e| format!("Line {} of undo log {:?} is invalid: {}", i + 1, path, e))?;
+        entries.push(entry);
+    }
+
+    for entry in entries.iter().rev() {
+        if !entry.destination.exists() {
+            eprintln!(
+                "Warning: can't undo {:?} as {:?} no longer exists",
+                entry.source, entry.destination
+            );
+            continue;
+        }
+        if entry.duplicate {
+            if entry.source.exists() {
+                eprintln!(
+                    "Warning: can't copy {:?} back as {:?} already exists",
+                    entry.destination, entry.source
+                );
+                continue;
+            }
+            println!("copy {:?} -> {:?}", entry.destination, entry.source);
+            if !dry_run {
+                std::fs::copy(&entry.destination, &entry.source)?;
+            }
+            continue;
+        }
+        if entry.original_kept {
+            // Removing the copy of a file that has since gone would lose it
+            if !entry.source.exists() {
+                eprintln!(
+                    "Warning: keeping {:?} as the original {:?} no longer exists",
+                    entry.destination, entry.source
+                );
+                continue;
+            }
+            println!("remove {:?}", entry.destination);
+            if !dry_run {
+                std::fs::remove_file(&entry.destination)?;
+            }
+        } else {
+            if entry.source.exists() {
+                eprintln!(
+                    "Warning: can't move {:?} back as {:?} already exists",
+                    entry.destination, entry.source
+                );
+                continue;
+            }
+            println!("{:?} -> {:?}", entry.destination, entry.source);
+            if !dry_run {
+                if let Some(parent) = entry.source.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                move_file(&entry.destination, &entry.source)?;
+            }
+        }
+        if let Some(backup) = entry.backup.as_ref().filter(|backup| backup.exists()) {
+            println!("remove {:?}", backup);
+            if !dry_run {
+                std::fs::remove_file(backup)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn removed_duplicate_is_copied_back() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let root = temp.path();
+        std::fs::create_dir_all(root.join("library")).unwrap();
+        let source = root.join("Movie.2010.mkv");
+        let destination = root.join("library").join("Movie-1080p.mkv");
+        std::fs::write(&destination, b"video").unwrap();
+        let log_path = root.join("undo.jsonl");
+        let mut log = UndoLog::open(&log_path).unwrap();
+        log.record(&LogEntry {
+            source: source.clone(),
+            destination: destination.clone(),
+            original_kept: false,
+            backup: None,
+            duplicate: true,
+        })
+        .unwrap();
+
+        undo(&log_path, true).unwrap();
+        assert!(!source.exists());
+        undo(&log_path, false).unwrap();
+        assert_eq!(std::fs::read(&source).unwrap(), b"video");
+        // The library's copy was there before the run so it stays
+        assert!(destination.exists());
+    }
+}
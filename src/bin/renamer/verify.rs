@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fs::{metadata, OpenOptions};
+use std::io::Read;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use webm_iterable::matroska_spec::MatroskaSpec;
+use webm_iterable::WebmIterator;
+
+use crate::types::GenericResult;
+
+/// SHA-256 of everything a reader has left
+fn hash_reader<R: Read>(mut reader: R) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            return Ok(hasher.finalize().into());
+        }
+        hasher.update(&buf[..read]);
+    }
+}
+
+/// Hash a file's contents while streaming it
+fn hash_file<P: AsRef<Path>>(path: P) -> std::io::Result<[u8; 32]> {
+    hash_reader(OpenOptions::new().read(true).open(path)?)
+}
+
+/// Check two files have the same contents by hash, comparing sizes first so
+/// files of different sizes are never read
+pub fn files_hash_identical<P1: AsRef<Path>, P2: AsRef<Path>>(
+    a: P1,
+    b: P2,
+) -> std::io::Result<bool> {
+    if metadata(&a)?.len() != metadata(&b)?.len() {
+        return Ok(false);
+    }
+    Ok(hash_file(a)? == hash_file(b)?)
+}
+
+/// Check a plain copy has exactly the same contents as its source
+pub fn verify_copy(source: &Path, copy: &Path) -> GenericResult<()> {
+    if files_hash_identical(source, copy)? {
+        Ok(())
+    } else {
+        Err(format!(
+            "{:?} doesn't match {:?}, the original was kept",
+            copy, source
+        )
+        .into())
+    }
+}
+
+/// Check a rewritten Matroska file carries the same frames as its source.
+///
+/// The tags and any embedded subtitles legitimately differ, so only the
+/// blocks of the tracks in the source are compared.
+pub fn verify_matroska_streams(source: &Path, copy: &Path) -> GenericResult<()> {
+    let source_tracks = hash_blocks(source)?;
+    let copy_tracks = hash_blocks(copy)?;
+    for (track, hash) in source_tracks.iter() {
+        if copy_tracks.get(track) != Some(hash) {
+            return Err(format!(
+                "Track {} of {:?} doesn't match {:?}, the original was kept",
+                track, copy, source
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// SHA-256 of the frames of each track, keyed by track number
+fn hash_blocks(path: &Path) -> GenericResult<HashMap<u64, [u8; 32]>> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let mut hashers: HashMap<u64, Sha256> = HashMap::new();
+    for tag in WebmIterator::new(&mut file, &[]) {
+        match tag? {
+            MatroskaSpec::SimpleBlock(data) | MatroskaSpec::Block(data) => {
+                if let Some(track) = block_track(&data) {
+                    hashers.entry(track).or_default().update(&data);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(hashers
+        .into_iter()
+        .map(|(track, hasher)| (track, hasher.finalize().into()))
+        .collect())
+}
+
+/// Read the track number a block starts with, stored as an EBML variable size integer
+fn block_track(data: &[u8]) -> Option<u64> {
+    let first = *data.first()?;
+    let length = first.leading_zeros() as usize + 1;
+    if length > 8 || data.len() < length {
+        return None;
+    }
+    // The bits after the length marker in the first byte start the number
+    let first_bits = if length == 8 {
+        0
+    } else {
+        first & (0xff >> length)
+    };
+    Some(
+        data[1..length]
+            .iter()
+            .fold(u64::from(first_bits), |value, byte| {
+                (value << 8) | u64::from(*byte)
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_reader_is_sha256() {
+        let hex = |hash: [u8; 32]| {
+            hash.iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        };
+        assert_eq!(
+            hex(hash_reader(b"abc".as_slice()).unwrap()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(hash_reader(b"".as_slice()).unwrap()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    /// Write a Matroska file with one track-1 block holding `frame` and a
+    /// title, removed when the returned path is dropped
+    fn matroska(name: &str, title: &str, frame: &[u8]) -> tempfile::TempPath {
+        use webm_iterable::matroska_spec::Master;
+        use webm_iterable::WebmWriter;
+
+        let path = tempfile::Builder::new()
+            .prefix(&format!("renamer-{}-", name))
+            .suffix(".mkv")
+            .tempfile()
+            .unwrap()
+            .into_temp_path();
+        let mut file = std::fs::File::create(&path).unwrap();
+        let mut writer = WebmWriter::new(&mut file);
+        let mut block = vec![0x81, 0, 0, 0x80];
+        block.extend_from_slice(frame);
+        for tag in [
+            MatroskaSpec::Segment(Master::Start),
+            MatroskaSpec::Info(Master::Full(vec![MatroskaSpec::Title(title.to_string())])),
+            MatroskaSpec::Cluster(Master::Full(vec![
+                MatroskaSpec::Timecode(0),
+                MatroskaSpec::SimpleBlock(block),
+            ])),
+            MatroskaSpec::Segment(Master::End),
+        ] {
+            writer.write(&tag).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn matroska_streams_ignore_tags() {
+        let source = matroska("source", "Old Title", b"frame");
+        let retagged = matroska("retagged", "New Title", b"frame");
+        let damaged = matroska("damaged", "New Title", b"frane");
+
+        assert!(verify_matroska_streams(&source, &retagged).is_ok());
+        assert!(verify_matroska_streams(&source, &damaged).is_err());
+        assert!(verify_copy(&source, &retagged).is_err());
+    }
+}
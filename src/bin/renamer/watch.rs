@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::env::current_dir;
+use std::fs::metadata;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use notify::{RecursiveMode, Watcher};
+
+use crate::summary::Summary;
+use crate::types::GenericResult;
+use crate::{Options, STAGING_DIRECTORY, UNDO_LOG_NAME, WATCH_STATE_NAME};
+
+/// How long a new file's size has to stay the same before it is processed
+const STABLE_FOR: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Paths changed by the renamer itself, which must not start another run
+struct OwnFiles {
+    /// Missing when the destination is the source or holds it
+    to_directory: Option<PathBuf>,
+    state_file: PathBuf,
+    undo_log: PathBuf,
+}
+
+impl OwnFiles {
+    fn new(options: &Options) -> std::io::Result<Self> {
+        let from_directory = absolute(&options.from_directory)?;
+        let to_directory = absolute(&options.to_directory)?;
+        Ok(Self {
+            undo_log: absolute(
+                options
+                    .log
+                    .as_deref()
+                    .unwrap_or(&to_directory.join(UNDO_LOG_NAME)),
+            )?,
+            state_file: absolute(options.state_file.as_deref().unwrap())?,
+            to_directory: Some(to_directory).filter(|to| !from_directory.starts_with(to)),
+        })
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        if self
+            .to_directory
+            .as_ref()
+            .is_some_and(|to| path.starts_with(to))
+        {
+            return true;
+        }
+        if path == self.state_file || path == self.undo_log {
+            return true;
+        }
+        // Staging directories, backups of tagged files and files being tagged
+        path.components()
+            .any(|component| component.as_os_str() == STAGING_DIRECTORY)
+            || path
+                .extension()
+                .is_some_and(|extension| extension == "bak" || extension == "with_meta")
+    }
+}
+
+/// Canonical where the path exists so it compares with watcher paths
+fn absolute(path: &Path) -> std::io::Result<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(_) => Ok(current_dir()?.join(path)),
+    }
+}
+
+/// Run once, then again each time new files in the source directory have
+/// finished being written, until interrupted with Ctrl-C
+pub fn watch(
+    mut options: Options,
+    run: fn(Options) -> GenericResult<Summary>,
+) -> GenericResult<()> {
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::SeqCst))?;
+    }
+
+    // Without a state file every run would process the same files again
+    if options.state_file.is_none() {
+        options.state_file = Some(options.to_directory.join(WATCH_STATE_NAME));
+    }
+    let own_files = OwnFiles::new(&options)?;
+
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mode = if options.dont_recurse {
+        RecursiveMode::NonRecursive
+    } else {
+        RecursiveMode::Recursive
+    };
+    watcher.watch(&options.from_directory, mode)?;
+
+    while running.load(Ordering::SeqCst) {
+        // A failing file shouldn't stop the watcher, it is retried next run
+        match run(options.clone()) {
+            Ok(summary) => eprintln!("{}", summary),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+
+        eprintln!("Watching {:?} for new files", options.from_directory);
+        // Last seen size of each changed path and when it last changed
+        let mut pending: HashMap<PathBuf, (u64, Instant)> = HashMap::new();
+        while running.load(Ordering::SeqCst) {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) => {
+                    for path in event.paths {
+                        if own_files.contains(&path) {
+                            continue;
+                        }
+                        pending.entry(path).or_insert((u64::MAX, Instant::now()));
+                    }
+                }
+                Ok(Err(e)) => eprintln!("Warning: watch error {}", e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            // Partially written files keep growing, removed files are dropped
+            pending.retain(|path, (size, changed)| match metadata(path) {
+                Ok(meta) => {
+                    if meta.len() != *size {
+                        *size = meta.len();
+                        *changed = Instant::now();
+                    }
+                    true
+                }
+                Err(_) => false,
+            });
+            if !pending.is_empty()
+                && pending
+                    .values()
+                    .all(|(_, changed)| changed.elapsed() >= STABLE_FOR)
+            {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,14 +1,59 @@
-use std::fs::metadata;
+use std::fs::{metadata, Metadata};
 use std::path::Path;
 
 #[cfg_attr(unix, path = "unix.rs")]
 #[cfg_attr(windows, path = "windows.rs")]
 mod inner;
 
-use inner::same_drive;
+use inner::{drive_id, file_id, same_drive};
 
 pub fn files_on_same_drive<P: AsRef<Path>>(file_a: P, file_b: P) -> std::io::Result<bool> {
     let meta_a = metadata(file_a)?;
     let meta_b = metadata(file_b)?;
     Ok(same_drive(meta_a, meta_b))
 }
+
+/// Identifier of the drive holding `path`, the device id on unix and the
+/// volume serial number on windows
+pub fn drive_identifier<P: AsRef<Path>>(path: P) -> std::io::Result<Option<u64>> {
+    Ok(drive_id(metadata(path)?))
+}
+
+/// Identity of the file or directory `meta` describes, the same for every path
+/// that reaches it through symlinks or hard links
+pub fn file_identity(meta: &Metadata) -> Option<(u64, u64)> {
+    file_id(meta)
+}
+
+/// Bytes available to this user on the drive holding `path`
+pub fn available_space<P: AsRef<Path>>(path: P) -> std::io::Result<u64> {
+    fs2::available_space(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn file_is_on_its_directory_drive() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let dir = temp.path();
+        let file = dir.join("video.mkv");
+        std::fs::write(&file, b"").unwrap();
+
+        let id = drive_identifier(dir).unwrap();
+        assert!(id.is_some());
+        assert_eq!(drive_identifier(&file).unwrap(), id);
+        assert!(files_on_same_drive(dir, &file).unwrap());
+        assert!(drive_identifier(dir.join("missing")).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn paths_on_c_are_on_the_same_drive() {
+        assert!(files_on_same_drive(Path::new("C:\\"), Path::new("C:\\Windows")).unwrap());
+        assert!(drive_identifier("C:\\").unwrap().is_some());
+    }
+}
@@ -3,3 +3,11 @@ use std::os::unix::fs::MetadataExt;
 pub fn same_drive<T: MetadataExt>(a: T, b: T) -> bool {
     a.dev() == b.dev()
 }
+
+pub fn drive_id<T: MetadataExt>(meta: T) -> Option<u64> {
+    Some(meta.dev())
+}
+
+pub fn file_id<T: MetadataExt>(meta: &T) -> Option<(u64, u64)> {
+    Some((meta.dev(), meta.ino()))
+}
@@ -1,7 +1,19 @@
 use std::os::windows::fs::MetadataExt;
 
+/// The serial number is only known for metadata read through a handle, as
+/// `std::fs::metadata` does but `DirEntry::metadata` doesn't, so an unknown
+/// volume is never treated as the same drive
 pub fn same_drive<T: MetadataExt>(a: T, b: T) -> bool {
-    a.volume_serial_number().is_some()
-        && b.volume_serial_number().is_some()
-        && a.volume_serial_number() == b.volume_serial_number()
+    match (a.volume_serial_number(), b.volume_serial_number()) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub fn drive_id<T: MetadataExt>(meta: T) -> Option<u64> {
+    meta.volume_serial_number().map(u64::from)
+}
+
+pub fn file_id<T: MetadataExt>(meta: &T) -> Option<(u64, u64)> {
+    Some((meta.volume_serial_number()?.into(), meta.file_index()?))
 }
@@ -1,10 +1,13 @@
-use std::fs::metadata;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fs::{metadata, read_dir, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 pub use imdb_index::Searcher;
-use imdb_index::{Index, MediaEntity, Query, Scored, SearchResults, TitleKind};
+use imdb_index::{Index, MediaEntity, Query, Rating, Scored, SearchResults, TitleKind};
+use serde::{Deserialize, Serialize};
 
-use crate::types::{GenericResult, VideoData};
+use crate::types::{Entity, Episode, GenericResult, ImdbKind, VideoData};
 
 pub fn open_if_exists_or_create_index<P1: AsRef<Path>, P2: AsRef<Path>>(
     data_dir: P1,
@@ -20,61 +23,521 @@ pub fn open_if_exists_or_create_index<P1: AsRef<Path>, P2: AsRef<Path>>(
     }
 }
 
+/// Open (or build) the index for the dataset directory, which should contain the
+/// IMDB TSV files (see `datasets/download.sh`)
+pub fn open_searcher<P: AsRef<Path>>(dataset_dir: P) -> GenericResult<Searcher> {
+    let dataset_dir = dataset_dir.as_ref();
+    if !dataset_dir.is_dir() {
+        return Err(format!("dataset directory {:?} does not exist", dataset_dir).into());
+    }
+    let index = open_if_exists_or_create_index(dataset_dir, dataset_dir.join("index"))?;
+    Ok(Searcher::new(index))
+}
+
+// Only one result is alive at a time, so boxing the episode saves nothing
+#[allow(clippy::large_enum_variant)]
 pub enum Results {
     Movie(MediaEntity),
     Episode(MediaEntity, MediaEntity),
 }
 
 fn score_by_rating(entity: &MediaEntity) -> f64 {
-    match entity.rating() {
+    votes_score(entity.rating())
+}
+
+/// Unrated titles, common for new or obscure series, score lowest
+fn votes_score(rating: Option<&Rating>) -> f64 {
+    match rating {
         Some(rating) => rating.votes.into(),
         None => 0.0,
     }
 }
 
-pub fn search_for_video(searcher: &mut Searcher, video: &VideoData) -> imdb_index::Result<Results> {
+/// Fetch an entity directly by its IMDB id, e.g. `tt0133093`
+pub fn lookup_by_id(searcher: &mut Searcher, id: &str) -> imdb_index::Result<Option<MediaEntity>> {
+    searcher.index().entity(id)
+}
+
+/// Look up a video whose file name contained its IMDB id, skipping the search
+fn lookup_video_by_id(
+    searcher: &mut Searcher,
+    video: &VideoData,
+) -> imdb_index::Result<Option<Results>> {
+    match video {
+        VideoData::Movie(
+            Entity {
+                imdb_id: Some(id), ..
+            },
+            _,
+        ) => Ok(lookup_by_id(searcher, id)?.map(Results::Movie)),
+        VideoData::Episode(
+            Episode {
+                imdb_id: Some(id), ..
+            },
+            _,
+        ) => {
+            let episode = match lookup_by_id(searcher, id)? {
+                Some(episode) => episode,
+                None => return Ok(None),
+            };
+            let series_id = match episode.episode() {
+                Some(details) => details.tvshow_id.clone(),
+                None => return Ok(None),
+            };
+            Ok(lookup_by_id(searcher, &series_id)?.map(|series| Results::Episode(series, episode)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn title_kind(kind: ImdbKind) -> TitleKind {
+    match kind {
+        ImdbKind::Movie => TitleKind::Movie,
+        ImdbKind::TvMovie => TitleKind::TVMovie,
+        ImdbKind::Short => TitleKind::Short,
+        ImdbKind::TvShort => TitleKind::TVShort,
+        ImdbKind::TvSpecial => TitleKind::TVSpecial,
+        ImdbKind::Video => TitleKind::Video,
+    }
+}
+
+/// Search for a movie's title limited to `kinds`, the defaults when empty
+fn movie_query(title: &str, kinds: &[ImdbKind]) -> Query {
+    let kinds = if kinds.is_empty() {
+        &ImdbKind::DEFAULT_MOVIE_KINDS[..]
+    } else {
+        kinds
+    };
+    kinds
+        .iter()
+        .fold(Query::new().name(title), |query, kind| {
+            query.kind(title_kind(*kind))
+        })
+        .votes_ge(0)
+}
+
+/// The highest scoring result, `None` when nothing matched
+fn best(results: SearchResults<MediaEntity>) -> Option<MediaEntity> {
+    results
+        .into_vec()
+        .into_iter()
+        .next()
+        .map(Scored::into_value)
+}
+
+/// Search cache written in the dataset directory by `SearchCache::open`
+const SEARCH_CACHE_NAME: &str = "search_cache.json";
+
+/// The newest modification time of the dataset's TSV files, in seconds
+/// since the unix epoch, which changes whenever the dataset is downloaded
+fn dataset_version(dataset_dir: &Path) -> std::io::Result<u64> {
+    let mut version = 0;
+    for entry in read_dir(dataset_dir)? {
+        let entry = entry?;
+        if entry
+            .path()
+            .extension()
+            .is_some_and(|extension| extension == "tsv")
+        {
+            let modified = entry
+                .metadata()?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            version = version.max(modified);
+        }
+    }
+    Ok(version)
+}
+
+/// The search cache as written to disk
+#[derive(Serialize, Deserialize)]
+struct SearchCacheFile {
+    /// The `dataset_version` the searches were made against
+    version: u64,
+    entries: BTreeMap<String, Option<String>>,
+}
+
+/// Ids found by earlier searches keyed by the normalised query, so repeated
+/// searches within and across runs skip the index. `None` records a search
+/// that found nothing.
+///
+/// Searches are only kept for the dataset they were made against, and the
+/// cache is saved when dropped if any were added.
+pub struct SearchCache {
+    path: PathBuf,
+    contents: SearchCacheFile,
+    changed: bool,
+}
+
+impl SearchCache {
+    /// Open the cache in the dataset directory, starting empty if there isn't
+    /// one yet or it was made against an older download
+    pub fn open<P: AsRef<Path>>(dataset_dir: P) -> GenericResult<Self> {
+        let dataset_dir = dataset_dir.as_ref();
+        let path = dataset_dir.join(SEARCH_CACHE_NAME);
+        let version = dataset_version(dataset_dir)?;
+        let empty = SearchCacheFile {
+            version,
+            entries: BTreeMap::new(),
+        };
+        let contents = match OpenOptions::new().read(true).open(&path) {
+            // Caches from older datasets, or that can't be read, are replaced
+            Ok(file) => match serde_json::from_reader::<_, SearchCacheFile>(file) {
+                Ok(contents) if contents.version == version => contents,
+                _ => empty,
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => empty,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            contents,
+            changed: false,
+        })
+    }
+
+    /// Write the cache if any searches were added since it was last written
+    pub fn save(&mut self) -> GenericResult<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let temp_path = self.path.with_extension("tmp");
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_path)?;
+        serde_json::to_writer(file, &self.contents)?;
+        std::fs::rename(&temp_path, &self.path)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+impl Drop for SearchCache {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            eprintln!("Warning: unable to save the IMDB search cache, {}", e);
+        }
+    }
+}
+
+/// Lowercase with single spaces, so titles differing only in case or spacing share a key
+fn normalize_title(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Cache key of a movie search, titles differing only in case or spacing share one
+fn movie_cache_key(title: &str, kinds: &[ImdbKind]) -> String {
+    format!("movie:{}:{:?}", normalize_title(title), kinds)
+}
+
+/// Cache key of a series search
+fn series_cache_key(title: &str) -> String {
+    format!("series:{}", normalize_title(title))
+}
+
+/// Look up a query in the cache, only running `search` on a miss, and
+/// re-fetching a cached id from the index
+fn cached_search(
+    searcher: &mut Searcher,
+    cache: &mut Option<&mut SearchCache>,
+    key: String,
+    search: impl FnOnce(&mut Searcher) -> imdb_index::Result<Option<MediaEntity>>,
+) -> imdb_index::Result<Option<MediaEntity>> {
+    if let Some(cached) = cache
+        .as_ref()
+        .and_then(|cache| cache.contents.entries.get(&key))
+    {
+        return match cached {
+            Some(id) => lookup_by_id(searcher, id),
+            None => Ok(None),
+        };
+    }
+    let entity = search(searcher)?;
+    if let Some(cache) = cache.as_mut() {
+        cache
+            .contents
+            .entries
+            .insert(key, entity.as_ref().map(|entity| entity.title().id.clone()));
+        cache.changed = true;
+    }
+    Ok(entity)
+}
+
+/// Find the IMDB entry for a video, `None` when the search has no results.
+///
+/// Searches are answered from `cache` when it has them, and added to it when
+/// it doesn't.
+pub fn search_for_video(
+    searcher: &mut Searcher,
+    video: &VideoData,
+    movie_kinds: &[ImdbKind],
+    mut cache: Option<&mut SearchCache>,
+) -> imdb_index::Result<Option<Results>> {
+    if let Some(results) = lookup_video_by_id(searcher, video)? {
+        return Ok(Some(results));
+    }
+
     match video {
         VideoData::Movie(movie, _) => {
-            let query = Query::new()
-                .name(&movie.title)
-                .kind(TitleKind::Movie)
-                .kind(TitleKind::TVMovie)
-                .kind(TitleKind::Short)
-                .kind(TitleKind::TVShort)
+            let key = movie_cache_key(&movie.title, movie_kinds);
+            let movie = cached_search(searcher, &mut cache, key, |searcher| {
+                let mut results = searcher.search(&movie_query(&movie.title, movie_kinds))?;
+                results.rescore(score_by_rating);
+                Ok(best(results))
+            })?;
+            Ok(movie.map(Results::Movie))
+        }
+        VideoData::Episode(episode, _) => {
+            let key = series_cache_key(&episode.series.title);
+            let series = cached_search(searcher, &mut cache, key, |searcher| {
+                let query = Query::new()
+                    .name(&episode.series.title)
+                    .votes_ge(0)
+                    .kind(TitleKind::TVSeries)
+                    .kind(TitleKind::TVMiniSeries);
+
+                let mut series_results = searcher.search(&query)?;
+                series_results.rescore(score_by_rating);
+                series_results.trim(1);
+                Ok(best(series_results))
+            })?;
+            let series = match series {
+                Some(series) => series,
+                None => return Ok(None),
+            };
+
+            let key = format!(
+                "episode:{}:{}:{}",
+                series.title().id,
+                episode.season,
+                episode.episode
+            );
+            let result = cached_search(searcher, &mut cache, key, |searcher| {
+                let query = Query::new()
+                    .kind(TitleKind::TVEpisode)
+                    .tvshow_id(&series.title().id)
+                    .episode_ge(episode.episode)
+                    .episode_le(episode.episode)
+                    .season_ge(episode.season)
+                    .season_le(episode.season);
+
+                Ok(best(searcher.search(&query)?))
+            })?;
+
+            Ok(result.map(|episode| Results::Episode(series, episode)))
+        }
+    }
+}
+
+/// A sample that any complete dataset can find, used by `self_test`
+const SELF_TEST_TITLE: &str = "The Matrix";
+const SELF_TEST_ID: &str = "tt0133093";
+
+/// Result of checking the IMDB index works, see `self_test`
+#[derive(Debug)]
+pub struct SelfTestReport {
+    /// Size of the index directory in bytes
+    pub index_size: u64,
+    /// `Err` with the reason each step failed
+    pub open: Result<(), String>,
+    pub search: Result<usize, String>,
+    pub lookup: Result<(), String>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.open.is_ok() && self.search.is_ok() && self.lookup.is_ok()
+    }
+}
+
+impl std::fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn status<T>(result: &Result<T, String>) -> String {
+            match result {
+                Ok(_) => "ok".to_string(),
+                Err(e) => format!("FAILED ({})", e),
+            }
+        }
+        writeln!(f, "  Index size:  {} bytes", self.index_size)?;
+        writeln!(f, "  Open index:  {}", status(&self.open))?;
+        match &self.search {
+            Ok(count) => writeln!(f, "  Search:      ok ({} results)", count)?,
+            Err(_) => writeln!(f, "  Search:      {}", status(&self.search))?,
+        }
+        writeln!(f, "  Lookup:      {}", status(&self.lookup))?;
+        write!(
+            f,
+            "IMDB self-test {}",
+            if self.passed() { "passed" } else { "FAILED" }
+        )
+    }
+}
+
+fn directory_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        size += if meta.is_dir() {
+            directory_size(&entry.path())?
+        } else {
+            meta.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Check the index opens and answers a known search and id lookup, without
+/// touching any videos
+pub fn self_test<P: AsRef<Path>>(dataset_dir: P) -> SelfTestReport {
+    let dataset_dir = dataset_dir.as_ref();
+    let mut report = SelfTestReport {
+        index_size: 0,
+        open: Ok(()),
+        search: Err("index not open".to_string()),
+        lookup: Err("index not open".to_string()),
+    };
+    let mut searcher = match open_searcher(dataset_dir) {
+        Ok(searcher) => searcher,
+        Err(e) => {
+            report.open = Err(e.to_string());
+            return report;
+        }
+    };
+    report.index_size = directory_size(&dataset_dir.join("index")).unwrap_or(0);
+
+    let query = Query::new()
+        .name(SELF_TEST_TITLE)
+        .kind(TitleKind::Movie)
+        .votes_ge(0);
+    report.search = match searcher.search(&query) {
+        Ok(results) if results.is_empty() => Err(format!("no results for {:?}", SELF_TEST_TITLE)),
+        Ok(results) => Ok(results.len()),
+        Err(e) => Err(e.to_string()),
+    };
+    report.lookup = match lookup_by_id(&mut searcher, SELF_TEST_ID) {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(format!("{} not found", SELF_TEST_ID)),
+        Err(e) => Err(e.to_string()),
+    };
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn unrated_titles_score_lowest() {
+        let rating = Rating {
+            id: "tt0113277".to_string(),
+            rating: 8.3,
+            votes: 700000,
+        };
+        assert_eq!(votes_score(None), 0.0);
+        assert_eq!(votes_score(Some(&rating)), 700000.0);
+    }
+
+    #[test]
+    fn no_results_is_none() {
+        assert!(best(SearchResults::new()).is_none());
+    }
+
+    #[test]
+    fn movie_query_kinds() {
+        let query = |kinds: &[TitleKind]| {
+            let query = kinds
+                .iter()
+                .fold(Query::new().name("Heat"), |query, kind| query.kind(*kind))
                 .votes_ge(0);
+            format!("{:?}", query)
+        };
+        assert_eq!(
+            format!("{:?}", movie_query("Heat", &[])),
+            query(&[
+                TitleKind::Movie,
+                TitleKind::TVMovie,
+                TitleKind::Short,
+                TitleKind::TVShort
+            ])
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                movie_query("Heat", &[ImdbKind::Movie, ImdbKind::Video])
+            ),
+            query(&[TitleKind::Movie, TitleKind::Video])
+        );
+        assert_ne!(
+            format!("{:?}", movie_query("Heat", &[ImdbKind::Movie])),
+            query(&[TitleKind::Movie, TitleKind::Short])
+        );
+        assert_eq!("tvmovie".parse(), Ok(ImdbKind::TvMovie));
+        assert!("documentary".parse::<ImdbKind>().is_err());
+    }
+
+    #[test]
+    fn search_cache_is_dropped_for_a_new_dataset() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let dataset_dir = temp.path();
+        let basics = dataset_dir.join("title.basics.tsv");
+        let set_modified = |secs| {
+            File::options()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&basics)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap()
+        };
+        set_modified(1_000_000);
 
-            let mut results = searcher.search(&query)?;
-            results.rescore(score_by_rating);
-            Ok(Results::Movie(
-                results.into_vec().swap_remove(0).into_value(),
-            ))
+        let kinds = [ImdbKind::Movie];
+        {
+            let mut cache = SearchCache::open(dataset_dir).unwrap();
+            cache.contents.entries.insert(
+                movie_cache_key("Heat", &kinds),
+                Some("tt0113277".to_string()),
+            );
+            cache.changed = true;
+            // Saved when dropped
         }
-        VideoData::Episode(episode, _) => {
-            let query = Query::new()
-                .name(&episode.series.title)
-                .votes_ge(0)
-                .kind(TitleKind::TVSeries)
-                .kind(TitleKind::TVMiniSeries);
-
-            let mut series_results = searcher.search(&query)?;
-            series_results.rescore(|s| s.rating().unwrap().votes.into());
-            series_results.trim(1);
-            let series = series_results.into_vec().swap_remove(0).into_value();
-
-            let query = Query::new()
-                .kind(TitleKind::TVEpisode)
-                .tvshow_id(&series.title().id)
-                .episode_ge(episode.episode)
-                .episode_le(episode.episode)
-                .season_ge(episode.season)
-                .season_le(episode.season);
-
-            let mut result = searcher.search(&query)?;
-
-            Ok(Results::Episode(
-                series,
-                result.into_vec().swap_remove(0).into_value(),
-            ))
+        let cache = SearchCache::open(dataset_dir).unwrap();
+        // The same title cased or spaced differently is the same search
+        for title in ["Heat", "HEAT", "  heat "] {
+            assert_eq!(
+                cache.contents.entries.get(&movie_cache_key(title, &kinds)),
+                Some(&Some("tt0113277".to_string())),
+                "{:?}",
+                title
+            );
         }
+        assert!(!cache
+            .contents
+            .entries
+            .contains_key(&movie_cache_key("Heat", &[ImdbKind::TvMovie])));
+        assert!(!cache
+            .contents
+            .entries
+            .contains_key(&series_cache_key("Heat")));
+        drop(cache);
+
+        set_modified(2_000_000);
+        let cache = SearchCache::open(dataset_dir).unwrap();
+        assert!(cache.contents.entries.is_empty());
+        drop(cache);
     }
 }
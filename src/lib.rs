@@ -0,0 +1,12 @@
+#![cfg_attr(windows, feature(windows_by_handle))]
+
+//! Parsing, tagging and naming of video files, used by the `not-sus-renamer`
+//! binary and usable on its own.
+
+pub mod file_drive;
+#[cfg(feature = "imdb")]
+pub mod imdb;
+pub mod magic;
+pub mod recursive_read_dir;
+pub mod summary;
+pub mod types;
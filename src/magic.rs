@@ -1,45 +1,303 @@
+use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::Read;
 use std::path::Path;
+use std::str::FromStr;
 
 use lazy_static::lazy_static;
 
-const FILE_MAGIC: [(&'static [u8], FileType); 2] = [
-    (&[0x1a, 0x45, 0xdf, 0xa3], FileType::MKV),
-    (
-        &[0x66, 0x74, 0x79, 0x70, 0x69, 0x73, 0x6f, 0x6d],
-        FileType::MP4,
-    ),
+/// Offset and bytes of one part of a signature
+type SignaturePart = (usize, &'static [u8]);
+
+/// Each signature's parts, the first whose parts all match wins
+const FILE_MAGIC: [(&[SignaturePart], FileType); 4] = [
+    // WebM shares this, it is told apart by the DocType in the EBML header
+    (&[(0, &[0x1a, 0x45, 0xdf, 0xa3])], FileType::MKV),
+    (&[(0, b"RIFF"), (8, b"AVI ")], FileType::AVI),
+    // The `ftyp` box comes after its 4 byte size, followed by the major brand
+    (&[(4, b"ftypqt  ")], FileType::MOV),
+    // Any other brand (isom, mp42, ...) is an MP4
+    (&[(4, b"ftyp")], FileType::MP4),
 ];
+/// Bytes read to find the EBML header's DocType, which is near the start
+const EBML_HEADER_SIZE: usize = 64;
+/// Extensions of files that should be videos, used to tell a broken download from other files
+const VIDEO_EXTENSIONS: [&str; 6] = ["mkv", "mp4", "m4v", "webm", "avi", "mov"];
+
+/// Files smaller than this cannot hold a complete video and are likely unfinished downloads
+const MINIMUM_FILE_SIZE: u64 = 1024;
+
 lazy_static! {
-    static ref SIGNATURE_SIZE: usize = FILE_MAGIC
-        .iter()
-        .fold(0, |acc, (sig, _)| usize::max(sig.len(), acc));
+    static ref SIGNATURE_SIZE: usize = FILE_MAGIC.iter().flat_map(|(parts, _)| parts.iter()).fold(
+        EBML_HEADER_SIZE,
+        |acc, (offset, sig)| usize::max(offset + sig.len(), acc)
+    );
+}
+
+/// Fill as much of `buf` as the reader has, as a single `read` may return less
+fn read_up_to<T: Read>(file: &mut T, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Whether an EBML header has the `webm` DocType
+fn is_webm_doc_type(header: &[u8]) -> bool {
+    let doc_type = header
+        .windows(2)
+        .position(|id| id == [0x42, 0x82])
+        .and_then(|i| {
+            // Doc types are short enough for a one byte size, which has its top bit set
+            let size = *header.get(i + 2)? as usize;
+            if size & 0x80 == 0 {
+                return None;
+            }
+            header.get(i + 3..i + 3 + (size & 0x7f))
+        });
+    doc_type == Some(b"webm".as_slice())
+}
+
+/// Whitespace and invisible characters that sometimes pad the end of a file name
+fn is_extension_padding(c: char) -> bool {
+    c.is_whitespace()
+        || c.is_control()
+        || matches!(c, '\u{200b}'..='\u{200f}' | '\u{2060}' | '\u{feff}')
+}
+
+/// Normalize an extension for comparison: strip padding and lowercase ASCII
+pub fn normalize_extension(extension: &str) -> String {
+    extension
+        .trim_matches(is_extension_padding)
+        .trim_start_matches('.')
+        .to_ascii_lowercase()
+}
+
+/// Split a file name into its stem and normalized extension
+pub fn split_extension(file_name: &str) -> (&str, String) {
+    let file_name = file_name.trim_end_matches(is_extension_padding);
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, normalize_extension(extension)),
+        _ => (file_name, String::new()),
+    }
+}
+
+/// Remove a video extension left in front of the real one, so the stem of
+/// `movie.mkv.mkv` or `show.s01e01.avi.mkv` doesn't keep the inner extension
+pub fn strip_inner_extension(stem: &str) -> &str {
+    let (inner_stem, inner_extension) = split_extension(stem);
+    if VIDEO_EXTENSIONS.contains(&inner_extension.as_str()) {
+        inner_stem
+    } else {
+        stem
+    }
+}
+
+pub fn has_video_extension<P: AsRef<Path>>(path: P) -> bool {
+    let file_name = match path.as_ref().file_name() {
+        Some(file_name) => file_name.to_string_lossy(),
+        None => return false,
+    };
+    let (_, extension) = split_extension(&file_name);
+    VIDEO_EXTENSIONS.contains(&extension.as_str())
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum FileType {
     Unknown,
+    /// Empty, tiny or zero-filled file, usually a download that hasn't finished
+    Incomplete,
     MKV,
+    /// Matroska with the `webm` DocType
+    WebM,
     MP4,
+    /// QuickTime, an MP4 with the `qt` brand
+    MOV,
+    AVI,
+}
+
+impl FromStr for FileType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match normalize_extension(s).as_str() {
+            "mkv" => Ok(FileType::MKV),
+            "webm" => Ok(FileType::WebM),
+            "mp4" => Ok(FileType::MP4),
+            "mov" => Ok(FileType::MOV),
+            "avi" => Ok(FileType::AVI),
+            _ => Err(format!(
+                "Unknown file type {:?}, expected mkv, webm, mp4, mov or avi",
+                s
+            )),
+        }
+    }
+}
+
+/// Parse a list like `.divx=avi,.mk3d=mkv` mapping extensions to the file
+/// type they are forced to, skipping magic detection for them
+pub fn parse_extension_map(map: &str) -> Result<HashMap<String, FileType>, String> {
+    map.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (extension, file_type) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected extension=type, found {:?}", entry))?;
+            Ok((normalize_extension(extension), file_type.trim().parse()?))
+        })
+        .collect()
 }
 
 impl FileType {
+    /// Whether the file is Matroska, which WebM is a subset of, so it can be tagged
+    pub fn is_matroska(&self) -> bool {
+        matches!(self, FileType::MKV | FileType::WebM)
+    }
+
+    /// Whether the file is made of ISO base media boxes like MP4 and MOV
+    pub fn is_iso_media(&self) -> bool {
+        matches!(self, FileType::MP4 | FileType::MOV)
+    }
+
+    /// Extension written for files of this type, and that `--fix-extension-only`
+    /// renames files to when their extension doesn't match
+    pub fn canonical_extension(&self) -> Option<&'static str> {
+        match self {
+            FileType::MKV => Some("mkv"),
+            FileType::WebM => Some("webm"),
+            FileType::MP4 => Some("mp4"),
+            FileType::MOV => Some("mov"),
+            FileType::AVI => Some("avi"),
+            FileType::Unknown | FileType::Incomplete => None,
+        }
+    }
+
+    /// Whether a file of this type may already have this extension, e.g.
+    /// WebM is Matroska so a `.webm` is left alone
+    pub fn matches_extension(&self, extension: &str) -> bool {
+        match self {
+            FileType::MKV => matches!(extension, "mkv" | "webm" | "mk3d"),
+            FileType::WebM => extension == "webm",
+            FileType::MP4 => matches!(extension, "mp4" | "m4v"),
+            FileType::MOV => matches!(extension, "mov" | "qt"),
+            FileType::AVI => extension == "avi",
+            FileType::Unknown | FileType::Incomplete => true,
+        }
+    }
+
     pub fn parse_file<T: Read>(mut file: T) -> std::io::Result<Self> {
         let mut buf = vec![0; *SIGNATURE_SIZE];
-        file.read(&mut buf)?;
+        let read = read_up_to(&mut file, &mut buf)?;
 
-        for (magic, file_type) in FILE_MAGIC {
-            if buf.starts_with(magic) {
+        let header = &buf[..read];
+        for (parts, file_type) in FILE_MAGIC {
+            if parts
+                .iter()
+                .all(|(offset, magic)| header.get(*offset..offset + magic.len()) == Some(*magic))
+            {
+                if file_type == FileType::MKV && is_webm_doc_type(header) {
+                    return Ok(FileType::WebM);
+                }
                 return Ok(file_type);
             }
         }
 
+        // Torrent clients preallocate files with zeros before any data arrives
+        if read == 0 || buf[..read].iter().all(|b| *b == 0) {
+            return Ok(FileType::Incomplete);
+        }
+
         Ok(FileType::Unknown)
     }
 
     pub fn from_path<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
-        let file = OpenOptions::new().read(true).open(path)?;
-        FileType::parse_file(file)
+        let file = OpenOptions::new().read(true).open(&path)?;
+        let file_type = if file.metadata()?.len() < MINIMUM_FILE_SIZE {
+            FileType::Incomplete
+        } else {
+            FileType::parse_file(file)?
+        };
+
+        // Small or blank subtitles, nfos etc. aren't unfinished videos
+        if file_type == FileType::Incomplete && !has_video_extension(&path) {
+            return Ok(FileType::Unknown);
+        }
+        Ok(file_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_compare_ignoring_case_and_padding() {
+        for extension in ["mkv", "MKV", ".Mkv", " mkv\u{200b}", "mkv\t\n"] {
+            assert_eq!(normalize_extension(extension), "mkv", "{:?}", extension);
+        }
+        assert_eq!(
+            split_extension("Movie.2010.MKV "),
+            ("Movie.2010", "mkv".to_string())
+        );
+        assert_eq!(
+            split_extension("Movie.Mp4\u{feff}"),
+            ("Movie", "mp4".to_string())
+        );
+        assert_eq!(split_extension(".hidden"), (".hidden", String::new()));
+        assert_eq!("MKV ".parse(), Ok(FileType::MKV));
+        assert!(has_video_extension("Movie.AVI"));
+        assert!(!has_video_extension("Movie.nfo"));
+    }
+
+    #[test]
+    fn inner_video_extension_is_stripped() {
+        assert_eq!(strip_inner_extension("movie.mkv"), "movie");
+        assert_eq!(strip_inner_extension("show.s01e01.AVI"), "show.s01e01");
+        // Only video extensions, so a title ending in a dotted word is kept
+        assert_eq!(strip_inner_extension("Mr.Robot"), "Mr.Robot");
+        assert_eq!(strip_inner_extension("movie"), "movie");
+    }
+
+    #[test]
+    fn detects_each_format() {
+        let ebml = |doc_type: &[u8]| {
+            let mut header = vec![0x1a, 0x45, 0xdf, 0xa3, 0x80 | (3 + doc_type.len() as u8)];
+            header.extend_from_slice(&[0x42, 0x82, 0x80 | doc_type.len() as u8]);
+            header.extend_from_slice(doc_type);
+            header
+        };
+        let parse = |data: &[u8]| FileType::parse_file(data).unwrap();
+
+        assert_eq!(parse(&ebml(b"matroska")), FileType::MKV);
+        assert_eq!(parse(&ebml(b"webm")), FileType::WebM);
+        assert_eq!(parse(b"RIFF\x10\0\0\0AVI LIST"), FileType::AVI);
+        assert_eq!(parse(b"\0\0\0\x14ftypqt  \0\0\0\0"), FileType::MOV);
+        assert_eq!(parse(b"\0\0\0\x18ftypisom\0\0\0\0"), FileType::MP4);
+        // A RIFF that isn't an AVI, e.g. a WAV
+        assert_eq!(parse(b"RIFF\x10\0\0\0WAVEfmt "), FileType::Unknown);
+        assert_eq!(parse(&[0; 128]), FileType::Incomplete);
+        assert_eq!(parse(b""), FileType::Incomplete);
+    }
+
+    #[test]
+    fn extension_map() {
+        let map = parse_extension_map(".divx=avi, .MK3D=mkv,xvid=AVI,").unwrap();
+        assert_eq!(
+            map,
+            HashMap::from([
+                ("divx".to_string(), FileType::AVI),
+                ("mk3d".to_string(), FileType::MKV),
+                ("xvid".to_string(), FileType::AVI),
+            ])
+        );
+        assert_eq!(parse_extension_map("").unwrap(), HashMap::new());
+        assert!(parse_extension_map(".divx").is_err());
+        assert!(parse_extension_map(".divx=wmv").is_err());
     }
 }
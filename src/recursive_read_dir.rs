@@ -1,46 +1,209 @@
-use std::fs::{read_dir, DirEntry, ReadDir};
-use std::path::Path;
+use std::collections::{HashSet, VecDeque};
+use std::fs::{metadata, read_dir, DirEntry, Metadata, ReadDir};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::file_drive::file_identity;
+
+/// Directories created by NAS and OS tools that never contain real videos
+const NOISE_NAMES: [&str; 2] = ["@eaDir", ".AppleDouble"];
+
+/// Directories holding the fragments of a ripped DVD or Blu-ray
+const DISC_NAMES: [&str; 2] = ["VIDEO_TS", "BDMV"];
+
+#[cfg(windows)]
+fn has_hidden_attribute(meta: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_meta: &Metadata) -> bool {
+    false
+}
+
+fn is_hidden(entry: &DirEntry, meta: &Metadata) -> bool {
+    entry.file_name().to_string_lossy().starts_with('.') || has_hidden_attribute(meta)
+}
+
+fn is_noise(entry: &DirEntry) -> bool {
+    let name = entry.file_name();
+    NOISE_NAMES.iter().any(|noise| name == *noise)
+}
+
+fn is_disc_directory(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| {
+        DISC_NAMES
+            .iter()
+            .any(|disc| name.to_string_lossy().eq_ignore_ascii_case(disc))
+    })
+}
+
+/// Whether the directory is the root of a ripped disc, i.e. contains `VIDEO_TS` or `BDMV`
+fn is_disc_root(path: &Path) -> bool {
+    DISC_NAMES.iter().any(|disc| path.join(disc).is_dir())
+}
+
+/// What to do with ripped disc folders found while walking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiscFolders {
+    /// Return the folder containing `VIDEO_TS` or `BDMV` as a single entry
+    Single,
+    /// Leave disc folders out entirely
+    #[default]
+    Skip,
+}
+
+impl FromStr for DiscFolders {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "single" => Ok(DiscFolders::Single),
+            "skip" => Ok(DiscFolders::Skip),
+            _ => Err(format!(
+                "Unknown disc folder handling {:?}, expected single or skip",
+                s
+            )),
+        }
+    }
+}
+
+/// Order directories are walked in when recursing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalkOrder {
+    /// Descend into each directory as soon as it is found
+    #[default]
+    DepthFirst,
+    /// Finish each directory before descending, surfacing shallow files first
+    BreadthFirst,
+}
+
+impl FromStr for WalkOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dfs" => Ok(WalkOrder::DepthFirst),
+            "bfs" => Ok(WalkOrder::BreadthFirst),
+            _ => Err(format!("Unknown walk order {:?}, expected dfs or bfs", s)),
+        }
+    }
+}
 
 pub struct RecursiveReadDir {
     recursive: bool,
-    entries: ReadDir,
-    dir_entry: Option<Box<RecursiveReadDir>>,
+    order: WalkOrder,
+    skip_hidden: bool,
+    disc_folders: DiscFolders,
+    /// Lowercased names of directories that are never descended into
+    no_recurse_into: Vec<String>,
+    /// Directories being read, innermost last
+    stack: Vec<ReadDir>,
+    /// Directories still to be read when walking breadth first
+    queue: VecDeque<PathBuf>,
+    /// Identities of directories already walked, so a symlink back up the
+    /// tree isn't followed forever
+    visited: HashSet<(u64, u64)>,
+    /// Canonical paths of directories already walked where there is no identity
+    visited_paths: HashSet<PathBuf>,
+}
+
+impl RecursiveReadDir {
+    pub fn with_order(mut self, order: WalkOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn with_skip_hidden(mut self, skip_hidden: bool) -> Self {
+        self.skip_hidden = skip_hidden;
+        self
+    }
+
+    pub fn with_disc_folders(mut self, disc_folders: DiscFolders) -> Self {
+        self.disc_folders = disc_folders;
+        self
+    }
+
+    /// Don't descend into directories with these names, ignoring case
+    pub fn with_no_recurse_into(mut self, names: &[String]) -> Self {
+        self.no_recurse_into = names.iter().map(|name| name.to_lowercase()).collect();
+        self
+    }
+
+    fn is_excluded_directory(&self, entry: &DirEntry) -> bool {
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        self.no_recurse_into.contains(&name)
+    }
+
+    /// Record a directory as walked, false if it already has been. `meta`
+    /// must follow symlinks so a link is known by the directory it points to
+    fn visit(&mut self, path: &Path, meta: &Metadata) -> bool {
+        match file_identity(meta) {
+            Some(identity) => self.visited.insert(identity),
+            // Canonical paths resolve symlinks, so a loop reaches the same path
+            None => match path.canonicalize() {
+                Ok(path) => self.visited_paths.insert(path),
+                Err(_) => false,
+            },
+        }
+    }
 }
 
 impl Iterator for RecursiveReadDir {
     type Item = DirEntry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(ref mut inner) = self.dir_entry {
-            if let Some(entry) = inner.next() {
-                return Some(entry);
-            }
-            self.dir_entry = None;
-        }
         loop {
-            if let Some(entry) = self.entries.next() {
-                if let Ok(entry) = entry {
-                    if let Ok(meta) = entry.metadata() {
-                        if meta.is_file() {
+            let entry = match self.stack.last_mut() {
+                Some(entries) => match entries.next() {
+                    Some(entry) => entry,
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                },
+                None => match self.queue.pop_front() {
+                    Some(directory) => {
+                        if let Ok(entries) = read_dir(directory) {
+                            self.stack.push(entries);
+                        }
+                        continue;
+                    }
+                    None => return None,
+                },
+            };
+            if let Ok(entry) = entry {
+                if is_noise(&entry) {
+                    continue;
+                }
+                // Followed so linked directories are walked and known by what they link to
+                if let Ok(meta) = metadata(entry.path()) {
+                    if self.skip_hidden && is_hidden(&entry, &meta) {
+                        continue;
+                    }
+                    if meta.is_file() {
+                        return Some(entry);
+                    } else if meta.is_dir() && is_disc_root(&entry.path()) {
+                        // The fragments inside are never videos on their own
+                        if self.disc_folders == DiscFolders::Single {
                             return Some(entry);
-                        } else if self.recursive && meta.is_dir() {
-                            if let Ok(entries) = read_dir(entry.path()) {
-                                let inner = RecursiveReadDir {
-                                    recursive: self.recursive,
-                                    entries,
-                                    dir_entry: None,
-                                };
-                                self.dir_entry = Some(Box::new(inner));
-                                return self.dir_entry.as_mut().unwrap().next();
-                            }
+                        }
+                    } else if meta.is_dir()
+                        && (is_disc_directory(&entry.path()) || self.is_excluded_directory(&entry))
+                    {
+                        continue;
+                    } else if self.recursive && meta.is_dir() && self.visit(&entry.path(), &meta) {
+                        if self.order == WalkOrder::BreadthFirst {
+                            self.queue.push_back(entry.path());
+                        } else if let Ok(entries) = read_dir(entry.path()) {
+                            self.stack.push(entries);
                         }
                     }
                 }
-            } else {
-                break;
             }
         }
-        None
     }
 }
 
@@ -48,9 +211,150 @@ pub fn read_dir_recursive<P: AsRef<Path>>(
     path: P,
     recursive: bool,
 ) -> std::io::Result<RecursiveReadDir> {
+    let path = path.as_ref();
+    let mut visited = HashSet::new();
+    let mut visited_paths = HashSet::new();
+    match file_identity(&metadata(path)?) {
+        Some(identity) => {
+            visited.insert(identity);
+        }
+        None => {
+            visited_paths.insert(path.canonicalize()?);
+        }
+    }
     Ok(RecursiveReadDir {
         recursive,
-        entries: read_dir(path)?,
-        dir_entry: None,
+        order: WalkOrder::default(),
+        skip_hidden: true,
+        disc_folders: DiscFolders::default(),
+        no_recurse_into: Vec::new(),
+        stack: vec![read_dir(path)?],
+        queue: VecDeque::new(),
+        visited,
+        visited_paths,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// A temporary directory holding empty files at these relative paths,
+    /// removed when dropped even if the test fails
+    fn tree(files: &[&str]) -> TempDir {
+        let root = TempDir::with_prefix("renamer-").unwrap();
+        for file in files {
+            let path = root.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        root
+    }
+
+    fn walk(walker: RecursiveReadDir) -> Vec<PathBuf> {
+        walker.map(|entry| entry.path()).collect()
+    }
+
+    #[test]
+    fn both_walk_orders_find_the_same_files() {
+        let dir = tree(&[
+            "top.mkv",
+            "a/one.mkv",
+            "a/b/two.mkv",
+            "c/three.mkv",
+            "c/d/e/four.mkv",
+        ]);
+        let root = dir.path();
+        let mut depth_first = walk(read_dir_recursive(root, true).unwrap());
+        let breadth_first = walk(
+            read_dir_recursive(root, true)
+                .unwrap()
+                .with_order(WalkOrder::BreadthFirst),
+        );
+        // Breadth first returns every file at one depth before going deeper
+        let depths: Vec<_> = breadth_first
+            .iter()
+            .map(|path| path.components().count())
+            .collect();
+        assert!(depths.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let mut breadth_first = breadth_first;
+        depth_first.sort();
+        breadth_first.sort();
+        assert_eq!(depth_first.len(), 5);
+        assert_eq!(depth_first, breadth_first);
+
+        let flat = walk(read_dir_recursive(root, false).unwrap());
+        assert_eq!(flat, [root.join("top.mkv")]);
+    }
+
+    #[test]
+    fn hidden_entries_are_skipped_unless_asked_for() {
+        let dir = tree(&[
+            "shown.mkv",
+            ".hidden.mkv",
+            ".Trash/trashed.mkv",
+            "@eaDir/thumb.mkv",
+            "show/.AppleDouble/fork.mkv",
+        ]);
+        let root = dir.path();
+        let names = |skip_hidden| {
+            let mut names: Vec<_> = read_dir_recursive(root, true)
+                .unwrap()
+                .with_skip_hidden(skip_hidden)
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(true), ["shown.mkv"]);
+        // NAS metadata directories are skipped even when hidden entries aren't
+        assert_eq!(names(false), [".hidden.mkv", "shown.mkv", "trashed.mkv"]);
+    }
+
+    #[test]
+    fn named_folders_are_not_walked() {
+        let dir = tree(&[
+            "Movie/Movie.mkv",
+            "Movie/Extras/Interview.mkv",
+            "Movie/extras/Trailer.mkv",
+            "Movie/Sample/Sample.mkv",
+            "Movie/Extras Cut/Movie.mkv",
+        ]);
+        let root = dir.path();
+        let mut names: Vec<_> = read_dir_recursive(root, true)
+            .unwrap()
+            .with_no_recurse_into(&["Extras".to_string(), "sample".to_string()])
+            .map(|entry| entry.path().strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                Path::new("Movie/Extras Cut/Movie.mkv"),
+                Path::new("Movie/Movie.mkv")
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop_is_walked_once() {
+        let dir = tree(&["inner/video.mkv"]);
+        let root = dir.path();
+        let inner = root.join("inner");
+        std::os::unix::fs::symlink(root, inner.join("back")).unwrap();
+        std::os::unix::fs::symlink(&inner, root.join("linked")).unwrap();
+
+        for order in [WalkOrder::DepthFirst, WalkOrder::BreadthFirst] {
+            let names: Vec<_> = read_dir_recursive(root, true)
+                .unwrap()
+                .with_order(order)
+                .map(|entry| entry.file_name())
+                .collect();
+            assert_eq!(names, ["video.mkv"]);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// What happened to one file, used to build the `Summary`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Renamed in place on the same drive
+    Renamed { bytes: u64 },
+    /// Copied, with or without removing the original
+    Copied { bytes: u64 },
+    /// Left alone, e.g. already processed, rejected or the destination exists
+    Skipped,
+    /// Only reported as this was a dry run
+    Planned,
+}
+
+/// Totals for one run over the source directory
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Summary {
+    pub seen: usize,
+    pub renamed: usize,
+    pub copied: usize,
+    pub skipped: usize,
+    pub errored: usize,
+    pub bytes_moved: u64,
+    pub elapsed: Duration,
+    /// Each file that failed and why
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+impl Summary {
+    pub fn record(&mut self, outcome: Outcome) {
+        self.seen += 1;
+        match outcome {
+            Outcome::Renamed { bytes } => {
+                self.renamed += 1;
+                self.bytes_moved += bytes;
+            }
+            Outcome::Copied { bytes } => {
+                self.copied += 1;
+                self.bytes_moved += bytes;
+            }
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Planned => {}
+        }
+    }
+
+    pub fn record_failure(&mut self, path: PathBuf, error: String) {
+        self.seen += 1;
+        self.errored += 1;
+        self.failures.push((path, error));
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Processed {} files in {:.1}s: {} renamed, {} copied, {} skipped, {} failed ({} bytes moved)",
+            self.seen,
+            self.elapsed.as_secs_f64(),
+            self.renamed,
+            self.copied,
+            self.skipped,
+            self.errored,
+            self.bytes_moved
+        )?;
+        for (path, error) in self.failures.iter() {
+            write!(f, "\n  {:?}: {}", path, error)?;
+        }
+        Ok(())
+    }
+}
@@ -2,10 +2,6 @@ use std::fs::OpenOptions;
 
 use webm_iterable::WebmIterator;
 
-mod imdb;
-pub mod magic;
-pub mod types;
-
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut file = OpenOptions::new()
         .read(true)
@@ -0,0 +1,288 @@
+/// Words commonly left in release names that are never part of a title
+const SCENE_TOKENS: [&str; 24] = [
+    "x264", "x265", "h264", "h265", "hevc", "avc", "xvid", "divx", "bluray", "brrip", "bdrip",
+    "webrip", "webdl", "web", "hdtv", "dvdrip", "aac", "ac3", "dts", "10bit", "hdr", "proper",
+    "repack", "internal",
+];
+
+/// Whether a word is release-group noise that can't be part of a title
+pub fn is_scene_token(word: &str) -> bool {
+    SCENE_TOKENS.iter().any(|t| t.eq_ignore_ascii_case(word))
+}
+
+/// Characters that are treated as word separators inside titles
+const SEPARATOR_CHARS: [char; 8] = ['_', '[', ']', '(', ')', '{', '}', '+'];
+
+/// A single step in cleaning a title parsed from a file name
+pub trait TitleCleaner {
+    fn clean(&self, raw: &str) -> String;
+}
+
+/// Removes release-group noise such as codecs and source tags
+#[derive(Debug, Clone)]
+pub struct TokenStripper {
+    pub tokens: Vec<String>,
+}
+
+impl Default for TokenStripper {
+    fn default() -> Self {
+        Self {
+            tokens: SCENE_TOKENS.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
+impl TitleCleaner for TokenStripper {
+    fn clean(&self, raw: &str) -> String {
+        raw.split(' ')
+            .filter(|word| !self.tokens.iter().any(|t| t.eq_ignore_ascii_case(word)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Replaces bracket and underscore characters with spaces
+#[derive(Debug, Clone, Default)]
+pub struct CharSanitizer;
+
+impl TitleCleaner for CharSanitizer {
+    fn clean(&self, raw: &str) -> String {
+        raw.chars()
+            .map(|c| if SEPARATOR_CHARS.contains(&c) { ' ' } else { c })
+            .collect()
+    }
+}
+
+/// Collapses runs of whitespace and trims both ends
+#[derive(Debug, Clone, Default)]
+pub struct WhitespaceCollapser;
+
+impl TitleCleaner for WhitespaceCollapser {
+    fn clean(&self, raw: &str) -> String {
+        raw.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Words left lowercase by `TitleCaser` unless they start the title
+const SMALL_WORDS: [&str; 16] = [
+    "a", "an", "and", "as", "at", "but", "by", "for", "in", "nor", "of", "on", "or", "the", "to",
+    "vs",
+];
+
+/// Whether a lowercase word is a roman numeral up to `xxxix`, longer ones
+/// are too easily real words
+fn is_roman_numeral(word: &str) -> bool {
+    let units = word.trim_start_matches('x');
+    !word.is_empty()
+        && word.len() - units.len() <= 3
+        && matches!(
+            units,
+            "" | "i" | "ii" | "iii" | "iv" | "v" | "vi" | "vii" | "viii" | "ix"
+        )
+}
+
+/// Title cases all lowercase or all uppercase titles, e.g. `the lord of the
+/// rings` becomes `The Lord of the Rings` and `star wars part ii` becomes
+/// `Star Wars Part II`. Mixed case titles are assumed to be written correctly
+/// and only have their whitespace collapsed.
+#[derive(Debug, Clone, Default)]
+pub struct TitleCaser;
+
+impl TitleCaser {
+    fn case_word(word: &str, is_first: bool) -> String {
+        let lower = word.to_lowercase();
+        if is_roman_numeral(&lower) {
+            return lower.to_uppercase();
+        }
+        if !is_first && SMALL_WORDS.contains(&lower.as_str()) {
+            return lower;
+        }
+        // Each part of `spider-man` is capitalized
+        lower
+            .split('-')
+            .map(|part| {
+                let mut chars = part.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+impl TitleCleaner for TitleCaser {
+    fn clean(&self, raw: &str) -> String {
+        let is_mixed_case =
+            raw.chars().any(char::is_lowercase) && raw.chars().any(char::is_uppercase);
+        raw.split_whitespace()
+            .enumerate()
+            .map(|(i, word)| {
+                if is_mixed_case {
+                    word.to_string()
+                } else {
+                    Self::case_word(word, i == 0)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Applies a list of cleaners in order
+#[derive(Default)]
+pub struct CleanerPipeline {
+    pub cleaners: Vec<Box<dyn TitleCleaner>>,
+}
+
+impl CleanerPipeline {
+    /// The pipeline used when parsing file names unless one is provided
+    pub fn standard() -> Self {
+        Self {
+            cleaners: vec![
+                Box::new(CharSanitizer),
+                Box::new(WhitespaceCollapser),
+                Box::new(TokenStripper::default()),
+                Box::new(WhitespaceCollapser),
+            ],
+        }
+    }
+
+    pub fn push<C: TitleCleaner + 'static>(&mut self, cleaner: C) {
+        self.cleaners.push(Box::new(cleaner));
+    }
+}
+
+impl TitleCleaner for CleanerPipeline {
+    fn clean(&self, raw: &str) -> String {
+        self.cleaners
+            .iter()
+            .fold(raw.to_string(), |title, cleaner| cleaner.clean(&title))
+    }
+}
+
+/// Characters that can't appear in a file name on at least one supported platform
+pub const ILLEGAL_NAME_CHARS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Make a title safe to use as part of a file name on every supported
+/// platform, so a library can be shared between them.
+///
+/// Colons become ` - `, slashes become `-`, other reserved and control
+/// characters are dropped, whitespace is collapsed and the trailing dots and
+/// spaces windows can't store are trimmed.
+pub fn sanitize_component(raw: &str) -> String {
+    let mut sanitized = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            ':' => sanitized.push_str(" - "),
+            '/' | '\\' => sanitized.push('-'),
+            c if c.is_control() || ILLEGAL_NAME_CHARS.contains(&c) => {}
+            c => sanitized.push(c),
+        }
+    }
+    WhitespaceCollapser
+        .clean(&sanitized)
+        .trim_end_matches(['.', ' '])
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_stripper_removes_scene_tokens_in_any_case() {
+        assert_eq!(
+            TokenStripper::default().clean("The Matrix BluRay x264 AAC"),
+            "The Matrix"
+        );
+        let custom = TokenStripper {
+            tokens: vec!["extended".to_string()],
+        };
+        assert_eq!(custom.clean("Aliens EXTENDED x264"), "Aliens x264");
+    }
+
+    #[test]
+    fn char_sanitizer_turns_separators_into_spaces() {
+        assert_eq!(
+            CharSanitizer.clean("The_Matrix [Remastered]+(Extras)"),
+            "The Matrix  Remastered   Extras "
+        );
+    }
+
+    #[test]
+    fn whitespace_collapser_collapses_and_trims() {
+        assert_eq!(
+            WhitespaceCollapser.clean("  The \t Matrix   Reloaded "),
+            "The Matrix Reloaded"
+        );
+    }
+
+    #[test]
+    fn standard_pipeline_cleans_in_order() {
+        assert_eq!(
+            CleanerPipeline::standard().clean("The_Matrix [x264]  WEB"),
+            "The Matrix"
+        );
+    }
+
+    #[test]
+    fn pipeline_runs_pushed_cleaners() {
+        struct Shout;
+        impl TitleCleaner for Shout {
+            fn clean(&self, raw: &str) -> String {
+                raw.to_uppercase()
+            }
+        }
+        let mut pipeline = CleanerPipeline::standard();
+        pipeline.push(Shout);
+        assert_eq!(pipeline.clean("the_matrix x264"), "THE MATRIX");
+        assert_eq!(CleanerPipeline::default().clean(" as is "), " as is ");
+    }
+
+    #[test]
+    fn title_caser_respects_small_words_and_numerals() {
+        for (raw, cased) in [
+            ("the lord of the rings", "The Lord of the Rings"),
+            ("star wars part ii", "Star Wars Part II"),
+            ("SOME SHOW", "Some Show"),
+            ("the  spider-man  of rome ", "The Spider-Man of Rome"),
+            // Correctly written titles are left alone
+            ("The Lord of the Rings", "The Lord of the Rings"),
+            ("iCarly", "iCarly"),
+        ] {
+            assert_eq!(TitleCaser.clean(raw), cased, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn roman_numerals_up_to_39() {
+        for numeral in ["i", "iv", "ix", "xiv", "xxxix"] {
+            assert!(is_roman_numeral(numeral), "{}", numeral);
+        }
+        for word in ["", "xxxx", "il", "mix", "vim"] {
+            assert!(!is_roman_numeral(word), "{}", word);
+        }
+    }
+
+    #[test]
+    fn sanitize_component_is_safe_on_unix_and_windows() {
+        for (title, sanitized) in [
+            ("Mission: Impossible", "Mission - Impossible"),
+            ("Face/Off", "Face-Off"),
+            ("AC\\DC", "AC-DC"),
+            ("What If...?", "What If"),
+            ("\"Quoted\" <Title>|*", "Quoted Title"),
+            ("Tab\tand\nnewline", "Tabandnewline"),
+            ("Trailing dots. . ", "Trailing dots"),
+            ("Plain Title", "Plain Title"),
+        ] {
+            let result = sanitize_component(title);
+            assert_eq!(result, sanitized, "{:?}", title);
+            // Nothing either platform rejects is left in
+            assert!(!result.contains(ILLEGAL_NAME_CHARS), "{:?}", result);
+            assert!(!result.ends_with(['.', ' ']), "{:?}", result);
+        }
+    }
+}
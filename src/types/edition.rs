@@ -0,0 +1,119 @@
+/// Editions recognised in file names, as they are written in Plex edition markers
+const DEFAULT_EDITIONS: [&str; 9] = [
+    "Directors Cut",
+    "Extended Cut",
+    "Extended Edition",
+    "Extended",
+    "Theatrical Cut",
+    "Theatrical",
+    "Unrated",
+    "Remastered",
+    "Special Edition",
+];
+
+/// Finds editions such as "Director's Cut" in the words of a file name
+#[derive(Debug, Clone)]
+pub struct EditionDetector {
+    /// Edition names, matched ignoring case and apostrophes
+    pub editions: Vec<String>,
+}
+
+impl Default for EditionDetector {
+    fn default() -> Self {
+        Self {
+            editions: DEFAULT_EDITIONS.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+fn normalize_word(word: &str) -> String {
+    word.chars()
+        .filter(|c| *c != '\'' && *c != '’')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+impl EditionDetector {
+    /// Every edition in `parts` with the index of its first word, longer
+    /// names are preferred so "Extended Cut" isn't found as "Extended".
+    /// The first word is always part of the title so it is never matched.
+    pub fn detect(&self, parts: &[&str]) -> Vec<(usize, String)> {
+        let parts: Vec<String> = parts.iter().map(|part| normalize_word(part)).collect();
+        let mut editions: Vec<(&String, Vec<String>)> = self
+            .editions
+            .iter()
+            .map(|edition| (edition, edition.split(' ').map(normalize_word).collect()))
+            .collect();
+        editions.sort_by_key(|(_, words)| std::cmp::Reverse(words.len()));
+
+        let mut found = Vec::new();
+        let mut i = 1;
+        while i < parts.len() {
+            let edition = editions
+                .iter()
+                .find(|(_, words)| parts[i..].starts_with(words));
+            match edition {
+                Some((name, words)) => {
+                    if !found.iter().any(|(_, found)| found == *name) {
+                        found.push((i, name.to_string()));
+                    }
+                    i += words.len();
+                }
+                None => i += 1,
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(name: &str) -> Vec<(usize, String)> {
+        let parts: Vec<&str> = name.split('.').collect();
+        EditionDetector::default().detect(&parts)
+    }
+
+    #[test]
+    fn common_forms() {
+        assert_eq!(
+            detect("Movie.2010.Director's.Cut.1080p"),
+            [(2, "Directors Cut".to_string())]
+        );
+        assert_eq!(
+            detect("Movie.2010.DIRECTORS.CUT"),
+            [(2, "Directors Cut".to_string())]
+        );
+        assert_eq!(
+            detect("Movie.Extended.Cut.2010"),
+            [(1, "Extended Cut".to_string())]
+        );
+        assert_eq!(detect("Movie.2010.Unrated"), [(2, "Unrated".to_string())]);
+        assert_eq!(detect("Movie.2010.1080p"), []);
+    }
+
+    #[test]
+    fn several_editions_in_one_name() {
+        assert_eq!(
+            detect("Movie.2010.Extended.Remastered.Extended"),
+            [(2, "Extended".to_string()), (3, "Remastered".to_string())]
+        );
+    }
+
+    #[test]
+    fn first_word_is_always_the_title() {
+        assert_eq!(detect("Unrated.2010"), []);
+    }
+
+    #[test]
+    fn editions_are_configurable() {
+        let detector = EditionDetector {
+            editions: vec!["Final Cut".to_string()],
+        };
+        assert_eq!(
+            detector.detect(&["Movie", "Final", "Cut", "Extended"]),
+            [(1, "Final Cut".to_string())]
+        );
+    }
+}
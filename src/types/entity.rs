@@ -1,8 +1,32 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Entity {
     pub title: String,
     pub release_year: u32,
     pub imdb_id: Option<String>,
+    pub tmdb_id: Option<String>,
+    /// Editions such as "Directors Cut", space separated when there are several
+    pub edition: Option<String>,
+}
+
+impl Entity {
+    /// Remove the release year from the title if it was left in as a word.
+    ///
+    /// Only the year stored in `release_year` is removed, so years that are
+    /// part of a title (e.g. "2001 A Space Odyssey") are kept. The first word
+    /// is never removed so titles like "1917" survive.
+    pub fn strip_release_year(&mut self) {
+        if self.release_year == 0 {
+            return;
+        }
+        let year = self.release_year.to_string();
+        let mut words: Vec<&str> = self.title.split(' ').collect();
+        if let Some(position) = words.iter().rposition(|word| *word == year) {
+            if position > 0 {
+                words.remove(position);
+                self.title = words.join(" ");
+            }
+        }
+    }
 }
 
 #[cfg(feature = "imdb")]
@@ -12,6 +36,50 @@ impl From<&imdb_index::MediaEntity> for Entity {
             title: entity.title().title.clone(),
             release_year: entity.title().start_year.unwrap_or(0),
             imdb_id: Some(entity.title().id.clone()),
+            tmdb_id: None,
+            edition: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(title: &str, release_year: u32) -> Entity {
+        Entity {
+            title: title.to_string(),
+            release_year,
+            imdb_id: None,
+            tmdb_id: None,
+            edition: None,
+        }
+    }
+
+    #[test]
+    fn strips_only_the_release_year() {
+        let mut movie = entity("Movie 2020", 2020);
+        movie.strip_release_year();
+        assert_eq!(movie.title, "Movie");
+
+        let mut odyssey = entity("2001 A Space Odyssey", 1968);
+        odyssey.strip_release_year();
+        assert_eq!(odyssey.title, "2001 A Space Odyssey");
+
+        // The last copy of the year is the one promoted to the release year
+        let mut repeated = entity("Blade Runner 2049 2049", 2049);
+        repeated.strip_release_year();
+        assert_eq!(repeated.title, "Blade Runner 2049");
+    }
+
+    #[test]
+    fn keeps_a_title_that_is_the_year() {
+        let mut movie = entity("1917", 1917);
+        movie.strip_release_year();
+        assert_eq!(movie.title, "1917");
+
+        let mut unknown = entity("Movie 0", 0);
+        unknown.strip_release_year();
+        assert_eq!(unknown.title, "Movie 0");
+    }
+}
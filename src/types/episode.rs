@@ -1,12 +1,46 @@
+use format_num::format_num;
+
 use super::Entity;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Episode {
     pub episode: u32,
+    /// The last episode of a multi-episode file like `S01E01-E02`
+    pub episode_end: Option<u32>,
     pub season: u32,
     pub title: String,
     pub imdb_id: Option<String>,
+    pub tmdb_id: Option<String>,
     pub series: Entity,
+    /// Digits the season was written with in the source name, 0 when unknown
+    pub season_digits: usize,
+    /// Digits the episode was written with in the source name, 0 when unknown
+    pub episode_digits: usize,
+}
+
+impl Episode {
+    /// Season and episode numbers as `S01E05` or `S01E05-E06`, padded to at
+    /// least two digits or as many as the source name used so `S001` stays `S001`
+    pub fn numbering(&self) -> String {
+        let episode_format = format!("0{}.0", self.episode_digits.max(2));
+        let mut numbering = format!(
+            "S{}E{}",
+            format_num!(&format!("0{}.0", self.season_digits.max(2)), self.season),
+            format_num!(&episode_format, self.episode)
+        );
+        if let Some(episode_end) = self.episode_end {
+            numbering.push_str(&format!("-E{}", format_num!(&episode_format, episode_end)));
+        }
+        numbering
+    }
+
+    /// The episode number written to tags, `1-2` for multi-episode files
+    pub fn episode_number(&self) -> String {
+        match self.episode_end {
+            Some(episode_end) => format!("{}-{}", self.episode, episode_end),
+            None => self.episode.to_string(),
+        }
+    }
 }
 
 #[cfg(feature = "imdb")]
@@ -21,15 +55,54 @@ impl TryFrom<(&imdb_index::MediaEntity, &imdb_index::MediaEntity)> for Episode {
                 episode: episode.episode.ok_or(
                     "Cannot create Episode from MediaEntity that does not contain episode.episode",
                 )?,
+                episode_end: None,
                 season: episode.season.ok_or(
                     "Cannot create Episode from MediaEntity that does not contain episode.season",
                 )?,
                 title: entities.0.title().title.clone(),
                 imdb_id: Some(episode.id.clone()),
+                tmdb_id: None,
                 series: Entity::from(entities.1),
+                season_digits: 0,
+                episode_digits: 0,
             })
         } else {
             Err("Cannot create Episode from MediaEntity that does not contain episode data")
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(season: u32, episode: u32, season_digits: usize, episode_digits: usize) -> Episode {
+        Episode {
+            episode,
+            episode_end: None,
+            season,
+            title: String::new(),
+            imdb_id: None,
+            tmdb_id: None,
+            series: Entity {
+                title: "Show".to_string(),
+                release_year: 0,
+                imdb_id: None,
+                tmdb_id: None,
+                edition: None,
+            },
+            season_digits,
+            episode_digits,
+        }
+    }
+
+    #[test]
+    fn numbering_is_padded_to_the_source_width() {
+        assert_eq!(episode(1, 1, 1, 1).numbering(), "S01E01");
+        assert_eq!(episode(1, 1, 0, 0).numbering(), "S01E01");
+        assert_eq!(episode(1, 1, 3, 3).numbering(), "S001E001");
+        assert_eq!(episode(12, 105, 2, 3).numbering(), "S12E105");
+        // A number wider than its padding is never cut short
+        assert_eq!(episode(2020, 1, 2, 2).numbering(), "S2020E01");
+    }
+}
@@ -1,104 +1,828 @@
 use std::fs::OpenOptions;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::path::Path;
+use std::str::FromStr;
 use std::time::Duration;
 
-use webm_iterable::{matroska_spec::MatroskaSpec, WebmIterator};
+use webm_iterable::{
+    matroska_spec::{Master, MatroskaSpec},
+    WebmIterator,
+};
 
+use crate::magic::FileType;
+
+use super::resolution::{frame_from_vertical, snap_resolution};
 use super::GenericResult;
 
-const STANDARD_RESOLUTIONS: [u64; 6] = [480, 720, 1080, 1440, 2160, 4320];
+/// Where resolution and duration are read from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetadataBackend {
+    /// Parse Matroska and MP4 files, everything else uses the file name
+    #[default]
+    Native,
+    /// Ask `mediainfo` about every file
+    MediaInfo,
+    /// Native for Matroska and MP4, `mediainfo` for other containers
+    Auto,
+}
+
+impl FromStr for MetadataBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let backend = match s {
+            "native" => MetadataBackend::Native,
+            "mediainfo" => MetadataBackend::MediaInfo,
+            "auto" => MetadataBackend::Auto,
+            _ => {
+                return Err(format!(
+                    "Unknown metadata backend {:?}, expected native, mediainfo or auto",
+                    s
+                ))
+            }
+        };
+        if cfg!(not(feature = "mediainfo")) && backend != MetadataBackend::Native {
+            return Err(format!(
+                "Metadata backend {:?} requires the mediainfo feature",
+                s
+            ));
+        }
+        Ok(backend)
+    }
+}
+
+/// `DisplayUnit` values, the others are centimeters, inches and unknown
+const DISPLAY_UNIT_PIXELS: u64 = 0;
+const DISPLAY_UNIT_ASPECT_RATIO: u64 = 3;
 
+/// The dimensions of a track, only video tracks have them
 #[derive(Default)]
-struct MatroskaData {
-    duration: Option<f64>,
+struct MatroskaDimensions {
     pixel_width: Option<u64>,
     pixel_height: Option<u64>,
     display_width: Option<u64>,
     display_height: Option<u64>,
+    /// What the display dimensions are measured in, pixels when missing
+    display_unit: Option<u64>,
+}
+
+impl MatroskaDimensions {
+    fn pixels(&self) -> Option<(u64, u64)> {
+        Some((self.pixel_width?, self.pixel_height?))
+    }
+
+    /// The size the track is shown at, which can differ from its pixels for
+    /// anamorphic video
+    fn displayed(&self) -> Option<(u64, u64)> {
+        let pixels = self.pixels()?;
+        Some(match (self.display_width, self.display_height) {
+            (Some(display_width), Some(display_height)) if display_height > 0 => {
+                match self.display_unit.unwrap_or(DISPLAY_UNIT_PIXELS) {
+                    DISPLAY_UNIT_PIXELS => (display_width, display_height),
+                    // Keep the pixel height and stretch the width to the ratio
+                    DISPLAY_UNIT_ASPECT_RATIO => (
+                        (pixels.1 * display_width + display_height / 2) / display_height,
+                        pixels.1,
+                    ),
+                    // Centimeters, inches or unknown say nothing about pixels
+                    _ => pixels,
+                }
+            }
+            _ => pixels,
+        })
+    }
+}
+
+#[derive(Default)]
+struct MatroskaData {
+    duration: Option<f64>,
+    /// Dimensions of each track
+    tracks: Vec<MatroskaDimensions>,
+    /// Kind, language and name of each track, in the same order as `tracks`
+    track_info: Vec<TrackInfo>,
+    muxing_app: Option<String>,
+    writing_app: Option<String>,
 }
 
 impl MatroskaData {
+    /// Everything has been found so reading can stop early, display
+    /// dimensions don't need checking as they are inside the Tracks
     fn is_complete(&self) -> bool {
-        self.duration.is_some() && self.pixel_height.is_some() && self.pixel_width.is_some()
+        self.duration.is_some() && self.has_resolution()
     }
 
-    fn build(self) -> Option<Metadata> {
-        if !self.is_complete() {
-            return None;
+    fn has_resolution(&self) -> bool {
+        !self.video_tracks().is_empty()
+    }
+
+    fn video_tracks(&self) -> Vec<(u64, u64)> {
+        self.tracks
+            .iter()
+            .filter_map(MatroskaDimensions::pixels)
+            .collect()
+    }
+
+    /// The dimensions of the current track, for files missing TrackEntry starts
+    fn current_track(&mut self) -> &mut MatroskaDimensions {
+        if self.tracks.is_empty() {
+            self.start_track();
         }
-        let resolution = if let (Some(display_width), Some(display_height)) =
-            (self.display_width, self.display_height)
-        {
-            (display_width, display_height)
-        } else {
-            (self.pixel_width.unwrap(), self.pixel_height.unwrap())
-        };
+        self.tracks.last_mut().unwrap()
+    }
+
+    fn current_track_info(&mut self) -> &mut TrackInfo {
+        if self.track_info.is_empty() {
+            self.start_track();
+        }
+        self.track_info.last_mut().unwrap()
+    }
+
+    fn start_track(&mut self) {
+        self.tracks.push(MatroskaDimensions::default());
+        self.track_info.push(TrackInfo::default());
+    }
+
+    /// Build metadata once the resolution is known, the duration is best-effort
+    fn build(self) -> Option<Metadata> {
+        let video_tracks = self.video_tracks();
+        // Extra video tracks are usually previews, so the largest is the video
+        let resolution = self
+            .tracks
+            .iter()
+            .filter_map(|track| Some((track.pixels()?, track)))
+            .max_by_key(|((width, height), _)| width * height)?
+            .1
+            .displayed()?;
         Some(Metadata {
             resolution,
-            length: Some(Duration::from_secs_f64(self.duration.unwrap())),
+            video_tracks,
+            tracks: self.track_info,
+            length: self.duration.map(Duration::from_secs_f64),
+            muxing_app: self.muxing_app,
+            writing_app: self.writing_app,
         })
     }
 }
 
+/// What a Matroska track holds, from its `TrackType`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+    Subtitle,
+    #[default]
+    Other,
+}
+
+impl From<u64> for TrackKind {
+    fn from(track_type: u64) -> Self {
+        match track_type {
+            1 => TrackKind::Video,
+            2 => TrackKind::Audio,
+            17 => TrackKind::Subtitle,
+            _ => TrackKind::Other,
+        }
+    }
+}
+
+/// A track as described by its TrackEntry, e.g. to tell commentary from the main audio
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackInfo {
+    pub kind: TrackKind,
+    pub language: Option<String>,
+    pub name: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Metadata {
     pub resolution: (u64, u64),
     pub length: Option<Duration>,
+    /// Pixel dimensions of every video track, only known for Matroska and MP4
+    pub video_tracks: Vec<(u64, u64)>,
+    /// Every track in the file, only known for Matroska and MP4
+    pub tracks: Vec<TrackInfo>,
+    /// Library that wrote the container, only known for Matroska
+    pub muxing_app: Option<String>,
+    /// Application that wrote the file, only known for Matroska
+    pub writing_app: Option<String>,
 }
 
 impl Metadata {
     pub fn from_matroska<P: AsRef<Path>>(path: P) -> GenericResult<Self> {
-        let mut file = OpenOptions::new().read(true).open(path)?;
-        let metadata = WebmIterator::new(&mut file, &[]);
+        let file = OpenOptions::new().read(true).open(path)?;
+        // webm-iterable can panic instead of erroring when a file ends part
+        // way through an element, which truncated downloads often do
+        catch_unwind(AssertUnwindSafe(|| Self::read_matroska(file)))
+            .unwrap_or_else(|_| Err("Matroska file is truncated".into()))
+    }
+
+    fn read_matroska<R: Read>(reader: R) -> GenericResult<Self> {
+        let metadata = WebmIterator::new(reader, &[]);
 
         let mut data = MatroskaData::default();
 
         for tag in metadata {
-            if let Ok(tag) = tag {
-                match tag {
+            // An error is returned again on every call, so reading stops at the first
+            match tag {
+                Err(_) => break,
+                Ok(tag) => match tag {
                     MatroskaSpec::Duration(duration) => data.duration = Some(duration),
-                    MatroskaSpec::PixelWidth(pixel_width) => data.pixel_width = Some(pixel_width),
+                    // Info and Tracks come before the first Cluster, so a file
+                    // without a Duration doesn't need to be read to the end
+                    MatroskaSpec::Cluster(Master::Start) if data.has_resolution() => break,
+                    // Every track is read so the largest video track can be picked
+                    MatroskaSpec::Tracks(Master::End) if data.is_complete() => break,
+                    MatroskaSpec::TrackEntry(Master::Start) => data.start_track(),
+                    MatroskaSpec::TrackType(track_type) => {
+                        data.current_track_info().kind = TrackKind::from(track_type)
+                    }
+                    MatroskaSpec::Language(language) => {
+                        data.current_track_info().language = Some(language)
+                    }
+                    MatroskaSpec::Name(name) => data.current_track_info().name = Some(name),
+                    MatroskaSpec::MuxingApp(muxing_app) => data.muxing_app = Some(muxing_app),
+                    MatroskaSpec::WritingApp(writing_app) => data.writing_app = Some(writing_app),
+                    MatroskaSpec::PixelWidth(pixel_width) => {
+                        data.current_track().pixel_width = Some(pixel_width)
+                    }
                     MatroskaSpec::PixelHeight(pixel_height) => {
-                        data.pixel_height = Some(pixel_height)
+                        data.current_track().pixel_height = Some(pixel_height)
                     }
                     MatroskaSpec::DisplayWidth(display_width) => {
-                        data.display_width = Some(display_width)
+                        data.current_track().display_width = Some(display_width)
                     }
                     MatroskaSpec::DisplayHeight(display_height) => {
-                        data.display_width = Some(display_height)
+                        data.current_track().display_height = Some(display_height)
+                    }
+                    MatroskaSpec::DisplayUnit(display_unit) => {
+                        data.current_track().display_unit = Some(display_unit)
                     }
                     _ => {}
+                },
+            }
+        }
+
+        data.build()
+            .ok_or_else(|| "Unable to extract metadata".into())
+    }
+
+    /// Read the largest video track and the duration from an MP4's `moov` box
+    pub fn from_mp4<P: AsRef<Path>>(path: P) -> GenericResult<Self> {
+        let mut file = OpenOptions::new().read(true).open(path)?;
+        let moov = read_mp4_moov(&mut file)?;
+
+        let mut duration = None;
+        let mut video_tracks = Vec::new();
+        let mut tracks = Vec::new();
+        for (kind, payload) in mp4_boxes(&moov) {
+            match &kind {
+                b"mvhd" => duration = mp4_movie_duration(payload),
+                b"trak" => {
+                    let mut dimensions = None;
+                    let mut info = TrackInfo::default();
+                    for (kind, payload) in mp4_boxes(payload) {
+                        match &kind {
+                            b"tkhd" => dimensions = mp4_track_dimensions(payload),
+                            b"mdia" => {
+                                for (kind, payload) in mp4_boxes(payload) {
+                                    match &kind {
+                                        b"hdlr" => info.kind = mp4_handler_kind(payload),
+                                        b"mdhd" => info.language = mp4_language(payload),
+                                        _ => {}
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    if info.kind == TrackKind::Video {
+                        video_tracks.extend(dimensions);
+                    }
+                    tracks.push(info);
                 }
-                if data.is_complete() {
-                    return Ok(data.build().unwrap());
-                }
+                _ => {}
             }
         }
 
-        Err("Unable to extract metadata".into())
+        // Extra video tracks are usually previews, so the largest is the video
+        let resolution = *video_tracks
+            .iter()
+            .max_by_key(|(width, height)| width * height)
+            .ok_or("MP4 has no video track with dimensions")?;
+        Ok(Self {
+            resolution,
+            length: duration,
+            video_tracks,
+            tracks,
+            muxing_app: None,
+            writing_app: None,
+        })
+    }
+
+    /// Read metadata with the chosen backend, falling back to the resolution
+    /// parsed from the file name when the backend fails
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        file_type: FileType,
+        backend: MetadataBackend,
+        file_name_resolution: Option<u64>,
+    ) -> Self {
+        let path = path.as_ref();
+        // None when the file name is the intended source
+        let result = match (backend, file_type) {
+            #[cfg(feature = "mediainfo")]
+            (MetadataBackend::MediaInfo, _) => Some(Self::from_mediainfo(path)),
+            (_, file_type) if file_type.is_matroska() => Some(Self::from_matroska(path)),
+            (_, file_type) if file_type.is_iso_media() => Some(Self::from_mp4(path)),
+            #[cfg(feature = "mediainfo")]
+            (MetadataBackend::Auto, _) => Some(Self::from_mediainfo(path)),
+            _ => None,
+        };
+        let file_name_metadata =
+            || Self::from_vertical_resolution(file_name_resolution.unwrap_or(0), None);
+        match result {
+            Some(Ok(metadata)) => metadata,
+            Some(Err(e)) => {
+                eprintln!(
+                    "Warning: unable to read metadata from {:?} ({}), using the file name",
+                    path, e
+                );
+                file_name_metadata()
+            }
+            None => file_name_metadata(),
+        }
+    }
+
+    /// Read metadata using the `mediainfo` command line tool
+    #[cfg(feature = "mediainfo")]
+    pub fn from_mediainfo<P: AsRef<Path>>(path: P) -> GenericResult<Self> {
+        let output = match std::process::Command::new("mediainfo")
+            .arg("--Output=JSON")
+            .arg(path.as_ref())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err("mediainfo is not installed".into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !output.status.success() {
+            return Err(format!("mediainfo exited with {}", output.status).into());
+        }
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+
+        // Numbers are usually strings but some versions write them unquoted
+        fn number(track: &serde_json::Value, field: &str) -> Option<f64> {
+            match track.get(field)? {
+                serde_json::Value::String(value) => value.trim().parse().ok(),
+                value => value.as_f64(),
+            }
+        }
+
+        let tracks = json
+            .pointer("/media/track")
+            .and_then(|tracks| tracks.as_array())
+            .ok_or("mediainfo output has no tracks")?;
+        let track_of_type = |kind: &str| {
+            tracks
+                .iter()
+                .find(|track| track.get("@type").and_then(|t| t.as_str()) == Some(kind))
+        };
+
+        let video = track_of_type("Video").ok_or("mediainfo found no video track")?;
+        let width = number(video, "Width").ok_or("mediainfo found no width")?;
+        let height = number(video, "Height").ok_or("mediainfo found no height")?;
+        let duration = track_of_type("General")
+            .and_then(|general| number(general, "Duration"))
+            .or_else(|| number(video, "Duration"))
+            .filter(|duration| duration.is_finite() && *duration >= 0.0);
+
+        Ok(Self {
+            resolution: (width as u64, height as u64),
+            length: duration.map(Duration::from_secs_f64),
+            video_tracks: Vec::new(),
+            tracks: Vec::new(),
+            muxing_app: None,
+            writing_app: None,
+        })
     }
 
     pub fn from_vertical_resolution(vertical_resolution: u64, length: Option<Duration>) -> Self {
         Self {
-            resolution: (vertical_resolution / 9 * 16, vertical_resolution),
+            resolution: frame_from_vertical(vertical_resolution),
             length,
+            video_tracks: Vec::new(),
+            tracks: Vec::new(),
+            muxing_app: None,
+            writing_app: None,
         }
     }
 
     pub fn get_resolution(&self) -> u64 {
-        let best_resolution = u64::max(self.resolution.0 / 16 * 9, self.resolution.1);
-        for i in 1..STANDARD_RESOLUTIONS.len() {
-            let lower = STANDARD_RESOLUTIONS[i - 1];
-            let higher = STANDARD_RESOLUTIONS[i];
-            if best_resolution >= lower && best_resolution <= higher {
-                if best_resolution - lower > higher - best_resolution {
-                    return higher;
-                } else {
-                    return lower;
-                }
+        snap_resolution(self.resolution)
+    }
+}
+
+/// Largest `moov` box read into memory, they are usually well under a megabyte
+const MP4_MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Find the `moov` box among the top level boxes, skipping over `mdat` without reading it
+pub(super) fn read_mp4_moov<R: Read + Seek>(reader: &mut R) -> GenericResult<Vec<u8>> {
+    loop {
+        let mut header = [0; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err("MP4 has no moov box".into())
+            }
+            Err(e) => return Err(e.into()),
+        }
+        // A size of 1 is followed by a 64-bit size, 0 runs to the end of the file
+        let (header_len, size) = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            0 => (8, None),
+            1 => {
+                let mut large_size = [0; 8];
+                reader.read_exact(&mut large_size)?;
+                (16, Some(u64::from_be_bytes(large_size)))
+            }
+            size => (8, Some(u64::from(size))),
+        };
+        let payload_len = match size {
+            Some(size) if size < header_len => return Err("Invalid MP4 box size".into()),
+            Some(size) => Some(size - header_len),
+            None => None,
+        };
+        if &header[4..8] == b"moov" {
+            if payload_len.unwrap_or(0) > MP4_MAX_MOOV_SIZE {
+                return Err("MP4 moov box is too large".into());
+            }
+            let mut moov = Vec::new();
+            reader
+                .take(payload_len.unwrap_or(MP4_MAX_MOOV_SIZE))
+                .read_to_end(&mut moov)?;
+            return Ok(moov);
+        }
+        match payload_len {
+            Some(payload_len) => {
+                reader.seek(SeekFrom::Current(
+                    i64::try_from(payload_len).map_err(|_| "Invalid MP4 box size")?,
+                ))?;
             }
+            None => return Err("MP4 has no moov box".into()),
         }
-        best_resolution
+    }
+}
+
+/// Split a buffer into the type and payload of each box, stopping at the first malformed one
+pub(super) fn mp4_boxes(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    while data.len() >= 8 {
+        let kind = data[4..8].try_into().unwrap();
+        let (header_len, size) = match be_u32(data, 0) {
+            Some(0) => (8, data.len()),
+            Some(1) => match be_u64(data, 8) {
+                Some(size) => (16, usize::try_from(size).unwrap_or(usize::MAX)),
+                None => break,
+            },
+            Some(size) => (8, size as usize),
+            None => break,
+        };
+        if size < header_len || size > data.len() {
+            break;
+        }
+        boxes.push((kind, &data[header_len..size]));
+        data = &data[size..];
+    }
+    boxes
+}
+
+pub(super) fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub(super) fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub(super) fn be_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Duration from an `mvhd` payload, whose layout depends on its version
+pub(super) fn mp4_movie_duration(mvhd: &[u8]) -> Option<Duration> {
+    let (timescale, duration) = match mvhd.first()? {
+        0 => (be_u32(mvhd, 12)?, u64::from(be_u32(mvhd, 16)?)),
+        1 => (be_u32(mvhd, 20)?, be_u64(mvhd, 24)?),
+        _ => return None,
+    };
+    // All ones means the duration is unknown
+    if timescale == 0 || duration == u64::MAX || duration == u64::from(u32::MAX) {
+        return None;
+    }
+    Some(Duration::from_secs_f64(
+        duration as f64 / f64::from(timescale),
+    ))
+}
+
+/// Width and height from a `tkhd` payload, stored as 16.16 fixed point
+pub(super) fn mp4_track_dimensions(tkhd: &[u8]) -> Option<(u64, u64)> {
+    let offset = match tkhd.first()? {
+        0 => 76,
+        1 => 88,
+        _ => return None,
+    };
+    let width = u64::from(be_u32(tkhd, offset)? >> 16);
+    let height = u64::from(be_u32(tkhd, offset + 4)? >> 16);
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Track kind from the handler type of an `hdlr` payload
+pub(super) fn mp4_handler_kind(hdlr: &[u8]) -> TrackKind {
+    match hdlr.get(8..12) {
+        Some(b"vide") => TrackKind::Video,
+        Some(b"soun") => TrackKind::Audio,
+        Some(b"sbtl" | b"subt" | b"text") => TrackKind::Subtitle,
+        _ => TrackKind::Other,
+    }
+}
+
+/// ISO 639-2 language from an `mdhd` payload, packed as three 5-bit letters
+pub(super) fn mp4_language(mdhd: &[u8]) -> Option<String> {
+    let offset = match mdhd.first()? {
+        0 => 20,
+        1 => 32,
+        _ => return None,
+    };
+    let packed = be_u16(mdhd, offset)?;
+    let language: String = [10, 5, 0]
+        .iter()
+        .map(|shift| char::from(((packed >> shift) & 0x1f) as u8 + 0x60))
+        .collect();
+    (language.chars().all(|c| c.is_ascii_lowercase()) && language != "und").then_some(language)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{Builder, TempPath};
+    use webm_iterable::WebmWriter;
+
+    use super::*;
+
+    /// A temporary path ending in `suffix`, removed when dropped
+    fn temp_path(name: &str, suffix: &str) -> TempPath {
+        Builder::new()
+            .prefix(&format!("renamer-{}-", name))
+            .suffix(suffix)
+            .tempfile()
+            .unwrap()
+            .into_temp_path()
+    }
+
+    /// Write a Matroska file made of `tags` to a fresh temporary path
+    fn matroska(name: &str, tags: &[MatroskaSpec]) -> TempPath {
+        let path = temp_path(name, ".mkv");
+        let mut file = std::fs::File::create(&path).unwrap();
+        let mut writer = WebmWriter::new(&mut file);
+        for tag in tags {
+            writer.write(tag).unwrap();
+        }
+        path
+    }
+
+    fn video_track(width: u64, height: u64) -> MatroskaSpec {
+        MatroskaSpec::TrackEntry(Master::Full(vec![
+            MatroskaSpec::TrackType(1),
+            MatroskaSpec::Video(Master::Full(vec![
+                MatroskaSpec::PixelWidth(width),
+                MatroskaSpec::PixelHeight(height),
+            ])),
+        ]))
+    }
+
+    #[test]
+    fn largest_of_two_video_tracks() {
+        let path = matroska(
+            "two-video",
+            &[
+                MatroskaSpec::Segment(Master::Start),
+                MatroskaSpec::Info(Master::Full(vec![MatroskaSpec::Duration(60000.0)])),
+                MatroskaSpec::Tracks(Master::Full(vec![
+                    video_track(640, 360),
+                    video_track(1920, 1080),
+                ])),
+                MatroskaSpec::Segment(Master::End),
+            ],
+        );
+        let metadata = Metadata::from_matroska(&path).unwrap();
+        assert_eq!(metadata.video_tracks, [(640, 360), (1920, 1080)]);
+        assert_eq!(metadata.resolution, (1920, 1080));
+        assert_eq!(metadata.tracks.len(), 2);
+    }
+
+    #[test]
+    fn display_dimensions_are_preferred() {
+        let path = matroska(
+            "display",
+            &[
+                MatroskaSpec::Segment(Master::Start),
+                MatroskaSpec::Tracks(Master::Full(vec![MatroskaSpec::TrackEntry(Master::Full(
+                    vec![
+                        MatroskaSpec::TrackType(1),
+                        MatroskaSpec::Video(Master::Full(vec![
+                            MatroskaSpec::PixelWidth(720),
+                            MatroskaSpec::PixelHeight(480),
+                            MatroskaSpec::DisplayWidth(854),
+                            MatroskaSpec::DisplayHeight(470),
+                        ])),
+                    ],
+                ))])),
+                MatroskaSpec::Segment(Master::End),
+            ],
+        );
+        let metadata = Metadata::from_matroska(&path).unwrap();
+        assert_eq!(metadata.resolution, (854, 470));
+        assert_eq!(metadata.video_tracks, [(720, 480)]);
+    }
+
+    #[test]
+    fn display_dimensions_of_the_largest_track() {
+        let track = |pixels: (u64, u64), display: (u64, u64)| {
+            MatroskaSpec::TrackEntry(Master::Full(vec![
+                MatroskaSpec::TrackType(1),
+                MatroskaSpec::Video(Master::Full(vec![
+                    MatroskaSpec::PixelWidth(pixels.0),
+                    MatroskaSpec::PixelHeight(pixels.1),
+                    MatroskaSpec::DisplayWidth(display.0),
+                    MatroskaSpec::DisplayHeight(display.1),
+                ])),
+            ]))
+        };
+        // A preview's display size applies only to the preview
+        for tracks in [
+            vec![
+                track((1440, 1080), (1920, 1080)),
+                track((320, 240), (426, 240)),
+            ],
+            vec![
+                track((320, 240), (426, 240)),
+                track((1440, 1080), (1920, 1080)),
+            ],
+        ] {
+            let path = matroska(
+                "display-tracks",
+                &[
+                    MatroskaSpec::Segment(Master::Start),
+                    MatroskaSpec::Tracks(Master::Full(tracks)),
+                    MatroskaSpec::Segment(Master::End),
+                ],
+            );
+            let metadata = Metadata::from_matroska(&path).unwrap();
+            assert_eq!(metadata.resolution, (1920, 1080));
+        }
+    }
+
+    #[test]
+    fn display_dimensions_by_unit() {
+        for (unit, display, resolution) in [
+            (None, (1440, 1080), (1440, 1080)),
+            (Some(0), (1440, 1080), (1440, 1080)),
+            // Centimeters say nothing about pixels
+            (Some(1), (30, 17), (1920, 1080)),
+            // An aspect ratio stretches the width to the pixel height
+            (Some(3), (4, 3), (1440, 1080)),
+        ] {
+            let mut video = vec![
+                MatroskaSpec::PixelWidth(1920),
+                MatroskaSpec::PixelHeight(1080),
+                MatroskaSpec::DisplayWidth(display.0),
+                MatroskaSpec::DisplayHeight(display.1),
+            ];
+            video.extend(unit.map(MatroskaSpec::DisplayUnit));
+            let path = matroska(
+                "display-unit",
+                &[
+                    MatroskaSpec::Segment(Master::Start),
+                    MatroskaSpec::Tracks(Master::Full(vec![MatroskaSpec::TrackEntry(
+                        Master::Full(vec![
+                            MatroskaSpec::TrackType(1),
+                            MatroskaSpec::Video(Master::Full(video)),
+                        ]),
+                    )])),
+                    MatroskaSpec::Segment(Master::End),
+                ],
+            );
+            let metadata = Metadata::from_matroska(&path).unwrap();
+            assert_eq!(metadata.resolution, resolution, "{:?}", unit);
+        }
+    }
+
+    #[test]
+    fn matroska_without_duration() {
+        let path = matroska(
+            "no-duration",
+            &[
+                MatroskaSpec::Segment(Master::Start),
+                MatroskaSpec::Info(Master::Full(vec![MatroskaSpec::TimecodeScale(1000000)])),
+                MatroskaSpec::Tracks(Master::Full(vec![video_track(1920, 1080)])),
+                MatroskaSpec::Cluster(Master::Full(vec![MatroskaSpec::Timecode(0)])),
+                MatroskaSpec::Segment(Master::End),
+            ],
+        );
+        let metadata = Metadata::from_matroska(&path).unwrap();
+        assert_eq!(metadata.resolution, (1920, 1080));
+        assert_eq!(metadata.length, None);
+    }
+
+    #[test]
+    fn truncated_matroska_is_an_error() {
+        let tags = [
+            MatroskaSpec::Segment(Master::Start),
+            MatroskaSpec::Info(Master::Full(vec![MatroskaSpec::TimecodeScale(1000000)])),
+            MatroskaSpec::Tracks(Master::Full(vec![video_track(1920, 1080)])),
+            MatroskaSpec::Segment(Master::End),
+        ];
+        let path = matroska("truncated", &tags);
+        let length = std::fs::metadata(&path).unwrap().len();
+        // Every cut inside the tracks, whether in an element's header or its data
+        for cut in 1..10 {
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.set_len(length - cut).unwrap();
+            assert!(Metadata::from_matroska(&path).is_err(), "{}", cut);
+        }
+    }
+
+    /// An ISO media box of `kind` holding `payload`
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = (8 + payload.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn mp4_track(handler: &[u8; 4], width: u32, height: u32) -> Vec<u8> {
+        let mut tkhd = vec![0; 84];
+        tkhd[76..80].copy_from_slice(&(width << 16).to_be_bytes());
+        tkhd[80..84].copy_from_slice(&(height << 16).to_be_bytes());
+        let mut hdlr = vec![0; 24];
+        hdlr[8..12].copy_from_slice(handler);
+        // "eng" packed as three 5-bit letters
+        let mut mdhd = vec![0; 24];
+        mdhd[20..22].copy_from_slice(&((5 << 10) | (14 << 5) | 7u16).to_be_bytes());
+        let mdia = [mp4_box(b"mdhd", &mdhd), mp4_box(b"hdlr", &hdlr)].concat();
+        mp4_box(
+            b"trak",
+            &[mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &mdia)].concat(),
+        )
+    }
+
+    #[test]
+    fn mp4_resolution_from_its_tracks() {
+        // 90 seconds at a timescale of 1000
+        let mut mvhd = vec![0; 20];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&90000u32.to_be_bytes());
+        let moov = [
+            mp4_box(b"mvhd", &mvhd),
+            mp4_track(b"vide", 320, 180),
+            mp4_track(b"vide", 1280, 720),
+            mp4_track(b"soun", 0, 0),
+        ]
+        .concat();
+        // The moov box comes after the media data, which is skipped
+        let data = [
+            mp4_box(b"ftyp", b"isom\0\0\0\0"),
+            mp4_box(b"mdat", &[0; 64]),
+            mp4_box(b"moov", &moov),
+        ]
+        .concat();
+        let path = temp_path("moov", ".mp4");
+        std::fs::write(&path, data).unwrap();
+
+        let metadata = Metadata::from_mp4(&path).unwrap();
+        assert_eq!(metadata.get_resolution(), 720);
+        assert_eq!(metadata.resolution, (1280, 720));
+        assert_eq!(metadata.video_tracks, [(320, 180), (1280, 720)]);
+        assert_eq!(metadata.length, Some(Duration::from_secs(90)));
+        assert_eq!(metadata.tracks[2].kind, TrackKind::Audio);
+        assert_eq!(metadata.tracks[2].language.as_deref(), Some("eng"));
+    }
+
+    #[test]
+    fn mp4_without_moov_uses_the_file_name() {
+        let path = temp_path("no-moov", ".mp4");
+        std::fs::write(&path, mp4_box(b"ftyp", b"isom\0\0\0\0")).unwrap();
+
+        assert!(Metadata::from_mp4(&path).is_err());
+        let metadata =
+            Metadata::from_path(&path, FileType::MP4, MetadataBackend::Native, Some(1080));
+        assert_eq!(metadata.get_resolution(), 1080);
     }
 }
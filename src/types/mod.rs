@@ -1,11 +1,27 @@
+pub mod cleaner;
+pub mod edition;
 pub mod entity;
 pub mod episode;
 pub mod metadata;
+#[cfg(feature = "remux")]
+pub mod remux;
+pub mod resolution;
+pub mod subtitle;
+pub mod template;
+pub mod title_source;
 pub mod video;
 
+pub use cleaner::*;
+pub use edition::*;
 pub use entity::*;
 pub use episode::*;
 pub use metadata::*;
+#[cfg(feature = "remux")]
+pub use remux::*;
+pub use resolution::*;
+pub use subtitle::*;
+pub use template::*;
+pub use title_source::*;
 pub use video::*;
 
 pub type GenericResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
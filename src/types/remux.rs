@@ -0,0 +1,737 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use webm_iterable::{
+    matroska_spec::{Master, MatroskaSpec},
+    WebmWriter,
+};
+
+use super::metadata::{
+    be_u16, be_u32, be_u64, mp4_boxes, mp4_handler_kind, mp4_language, mp4_movie_duration,
+    mp4_track_dimensions, read_mp4_moov, TrackKind,
+};
+use super::GenericResult;
+
+/// Matroska timestamps are written in milliseconds
+const TIMECODE_SCALE: u64 = 1_000_000;
+/// A new cluster is started at the first keyframe after this many milliseconds
+const CLUSTER_LENGTH: i64 = 5_000;
+/// Largest single frame read into memory
+const MAX_SAMPLE_SIZE: u32 = 64 * 1024 * 1024;
+/// Most samples a track may have, hours of 60fps video
+const MAX_SAMPLES: usize = 10_000_000;
+
+/// One frame of a track, where it is in the MP4 and when it is shown
+struct Sample {
+    offset: u64,
+    size: u32,
+    /// Decode time in milliseconds, which orders the blocks
+    decode: i64,
+    /// Presentation time in milliseconds, which the blocks are stamped with
+    presentation: i64,
+    keyframe: bool,
+}
+
+/// A track to be copied into the Matroska file
+struct Track {
+    kind: TrackKind,
+    codec_id: &'static str,
+    codec_private: Vec<u8>,
+    language: Option<String>,
+    pixel_dimensions: (u64, u64),
+    display_dimensions: Option<(u64, u64)>,
+    sampling_frequency: f64,
+    channels: u64,
+    /// Frame duration in nanoseconds when every frame is the same length
+    default_duration: Option<u64>,
+    samples: Vec<Sample>,
+}
+
+/// Copy the H.264 video and AAC audio of an MP4 into a Matroska file without
+/// re-encoding them. Subtitle and other tracks are dropped, and any other
+/// audio or video codec is an error so nothing is silently lost.
+pub fn remux_mp4_to_matroska<P1: AsRef<Path>, P2: AsRef<Path>>(
+    from: P1,
+    to: P2,
+) -> GenericResult<()> {
+    let mut input = OpenOptions::new().read(true).open(from)?;
+    let moov = read_mp4_moov(&mut input)?;
+
+    let mut duration = None;
+    let mut tracks = Vec::new();
+    for (kind, payload) in mp4_boxes(&moov) {
+        match &kind {
+            b"mvhd" => duration = mp4_movie_duration(payload),
+            b"trak" => tracks.extend(read_track(payload)?),
+            _ => {}
+        }
+    }
+    if tracks.is_empty() {
+        return Err("MP4 has no audio or video track to remux".into());
+    }
+    // Blocks are written with a one byte track number
+    if tracks.len() > 126 {
+        return Err("MP4 has too many tracks to remux".into());
+    }
+
+    let mut output = BufWriter::new(File::create(to)?);
+    write_matroska(&mut input, &mut output, &tracks, duration)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Read a `trak` payload, returning `None` for tracks which aren't audio or video
+fn read_track(trak: &[u8]) -> GenericResult<Option<Track>> {
+    let mut display_dimensions = None;
+    let mut edit_start = 0;
+    let mut mdia = None;
+    for (kind, payload) in mp4_boxes(trak) {
+        match &kind {
+            b"tkhd" => display_dimensions = mp4_track_dimensions(payload),
+            b"edts" => edit_start = mp4_edit_start(payload),
+            b"mdia" => mdia = Some(payload),
+            _ => {}
+        }
+    }
+    let mdia = mdia.ok_or("MP4 track has no mdia box")?;
+
+    let mut kind = TrackKind::Other;
+    let mut timescale = None;
+    let mut language = None;
+    let mut stbl = None;
+    for (box_kind, payload) in mp4_boxes(mdia) {
+        match &box_kind {
+            b"hdlr" => kind = mp4_handler_kind(payload),
+            b"mdhd" => {
+                timescale = match payload.first() {
+                    Some(0) => be_u32(payload, 12),
+                    Some(1) => be_u32(payload, 20),
+                    _ => None,
+                };
+                language = mp4_language(payload);
+            }
+            b"minf" => {
+                stbl = mp4_boxes(payload)
+                    .into_iter()
+                    .find(|(kind, _)| kind == b"stbl")
+                    .map(|(_, payload)| payload)
+            }
+            _ => {}
+        }
+    }
+    if !matches!(kind, TrackKind::Video | TrackKind::Audio) {
+        return Ok(None);
+    }
+    let timescale = timescale
+        .filter(|timescale| *timescale > 0)
+        .ok_or("MP4 track has no timescale")?;
+    let stbl = stbl.ok_or("MP4 track has no sample table")?;
+
+    let mut track = Track {
+        kind,
+        codec_id: "",
+        codec_private: Vec::new(),
+        language,
+        pixel_dimensions: (0, 0),
+        display_dimensions: None,
+        sampling_frequency: 0.0,
+        channels: 0,
+        default_duration: None,
+        samples: Vec::new(),
+    };
+    let mut table = SampleTable::default();
+    for (box_kind, payload) in mp4_boxes(stbl) {
+        match &box_kind {
+            b"stsd" => read_sample_description(&mut track, payload)?,
+            b"stts" => {
+                table.decode_deltas = mp4_table(payload, 8, |entry| {
+                    Some((be_u32(entry, 0)?, be_u32(entry, 4)?))
+                })
+            }
+            b"ctts" => {
+                let signed = payload.first() == Some(&1);
+                table.composition_offsets = mp4_table(payload, 8, |entry| {
+                    let offset = be_u32(entry, 4)?;
+                    let offset = if signed {
+                        i64::from(offset as i32)
+                    } else {
+                        i64::from(offset)
+                    };
+                    Some((be_u32(entry, 0)?, offset))
+                })
+            }
+            b"stsc" => {
+                table.chunk_runs = mp4_table(payload, 12, |entry| {
+                    Some((be_u32(entry, 0)?, be_u32(entry, 4)?))
+                })
+            }
+            b"stsz" => {
+                let constant = be_u32(payload, 4).unwrap_or(0);
+                let count = be_u32(payload, 8).unwrap_or(0) as usize;
+                table.sizes = if constant > 0 {
+                    vec![constant; count.min(MAX_SAMPLES)]
+                } else {
+                    // The sizes follow the count, a field later than other tables
+                    mp4_table(payload.get(4..).unwrap_or_default(), 4, |entry| {
+                        be_u32(entry, 0)
+                    })
+                };
+            }
+            b"stco" => {
+                table.chunk_offsets = mp4_table(payload, 4, |entry| be_u32(entry, 0).map(u64::from))
+            }
+            b"co64" => table.chunk_offsets = mp4_table(payload, 8, |entry| be_u64(entry, 0)),
+            b"stss" => table.keyframes = Some(mp4_table(payload, 4, |entry| be_u32(entry, 0))),
+            _ => {}
+        }
+    }
+    if track.codec_id.is_empty() {
+        return Err("MP4 track has no sample description".into());
+    }
+    if track.kind == TrackKind::Video {
+        // The track header holds the size it's shown at, which can differ from
+        // the coded size for anamorphic video
+        track.display_dimensions =
+            display_dimensions.filter(|dimensions| *dimensions != track.pixel_dimensions);
+        if let [(_, delta)] = table.decode_deltas[..] {
+            track.default_duration = Some(u64::from(delta) * 1_000_000_000 / u64::from(timescale));
+        }
+    }
+    track.samples = table.samples(timescale, edit_start)?;
+    Ok(Some(track))
+}
+
+/// The tables of a `stbl` box which place and time each sample
+#[derive(Default)]
+struct SampleTable {
+    /// Runs of samples with the same decode duration, as (count, duration)
+    decode_deltas: Vec<(u32, u32)>,
+    /// Runs of samples shown the same time after they're decoded, as (count, offset)
+    composition_offsets: Vec<(u32, i64)>,
+    /// The chunks from which each chunk has the same number of samples, as
+    /// (first chunk, samples per chunk)
+    chunk_runs: Vec<(u32, u32)>,
+    sizes: Vec<u32>,
+    chunk_offsets: Vec<u64>,
+    /// Sample numbers of the keyframes, with every sample a keyframe when missing
+    keyframes: Option<Vec<u32>>,
+}
+
+impl SampleTable {
+    /// Lay out every sample, with times in milliseconds starting at `edit_start`
+    fn samples(&self, timescale: u32, edit_start: i64) -> GenericResult<Vec<Sample>> {
+        let to_millis = |time: i64| time * 1000 / i64::from(timescale);
+
+        let mut offsets = Vec::with_capacity(self.sizes.len());
+        let mut sizes = self.sizes.iter();
+        'chunks: for (i, chunk_offset) in self.chunk_offsets.iter().enumerate() {
+            let chunk = i as u32 + 1;
+            let per_chunk = self
+                .chunk_runs
+                .iter()
+                .take_while(|(first, _)| *first <= chunk)
+                .last()
+                .map(|(_, per_chunk)| *per_chunk)
+                .unwrap_or(0);
+            let mut offset = *chunk_offset;
+            for _ in 0..per_chunk {
+                let Some(size) = sizes.next() else {
+                    break 'chunks;
+                };
+                offsets.push((offset, *size));
+                offset += u64::from(*size);
+            }
+        }
+        if offsets.len() != self.sizes.len() {
+            return Err("MP4 sample table doesn't cover every sample".into());
+        }
+
+        let deltas = self
+            .decode_deltas
+            .iter()
+            .flat_map(|(count, delta)| std::iter::repeat_n(*delta, *count as usize));
+        let mut composition = self
+            .composition_offsets
+            .iter()
+            .flat_map(|(count, offset)| std::iter::repeat_n(*offset, *count as usize));
+        let mut samples = Vec::with_capacity(offsets.len());
+        let mut decode = 0_i64;
+        for (i, ((offset, size), delta)) in offsets.into_iter().zip(deltas).enumerate() {
+            if size > MAX_SAMPLE_SIZE {
+                return Err("MP4 sample is too large".into());
+            }
+            let presentation = decode + composition.next().unwrap_or(0);
+            samples.push(Sample {
+                offset,
+                size,
+                decode: to_millis(decode - edit_start),
+                presentation: to_millis(presentation - edit_start),
+                keyframe: self
+                    .keyframes
+                    .as_ref()
+                    .is_none_or(|keyframes| keyframes.binary_search(&(i as u32 + 1)).is_ok()),
+            });
+            decode += i64::from(delta);
+        }
+        Ok(samples)
+    }
+}
+
+/// Read the fixed size entries of a full box's table, which starts with its count
+fn mp4_table<T>(payload: &[u8], entry_size: usize, read: impl Fn(&[u8]) -> Option<T>) -> Vec<T> {
+    let count = be_u32(payload, 4).unwrap_or(0) as usize;
+    payload
+        .get(8..)
+        .unwrap_or_default()
+        .chunks_exact(entry_size)
+        .take(count.min(MAX_SAMPLES))
+        .map_while(read)
+        .collect()
+}
+
+/// Media time the first edit of an `edts` box starts at, which players begin from
+fn mp4_edit_start(edts: &[u8]) -> i64 {
+    let Some((_, elst)) = mp4_boxes(edts)
+        .into_iter()
+        .find(|(kind, _)| kind == b"elst")
+    else {
+        return 0;
+    };
+    let entries = if elst.first() == Some(&1) {
+        mp4_table(elst, 20, |entry| be_u64(entry, 8).map(|time| time as i64))
+    } else {
+        mp4_table(elst, 12, |entry| {
+            be_u32(entry, 4).map(|time| i64::from(time as i32))
+        })
+    };
+    // -1 is an empty edit, a gap before the media starts
+    entries.into_iter().find(|time| *time >= 0).unwrap_or(0)
+}
+
+/// Fill in the codec of a track from the first entry of its `stsd` box
+fn read_sample_description(track: &mut Track, stsd: &[u8]) -> GenericResult<()> {
+    let (kind, entry) = mp4_boxes(stsd.get(8..).unwrap_or_default())
+        .into_iter()
+        .next()
+        .ok_or("MP4 track has no sample description")?;
+    match &kind {
+        b"avc1" | b"avc3" => {
+            track.pixel_dimensions = (
+                u64::from(be_u16(entry, 24).ok_or("Invalid MP4 video sample entry")?),
+                u64::from(be_u16(entry, 26).ok_or("Invalid MP4 video sample entry")?),
+            );
+            let (_, avcc) = mp4_boxes(entry.get(78..).unwrap_or_default())
+                .into_iter()
+                .find(|(kind, _)| kind == b"avcC")
+                .ok_or("MP4 H.264 track has no avcC box")?;
+            track.codec_id = "V_MPEG4/ISO/AVC";
+            track.codec_private = avcc.to_vec();
+        }
+        b"mp4a" => {
+            let children = match be_u16(entry, 8) {
+                Some(0) => {
+                    track.channels = u64::from(be_u16(entry, 16).unwrap_or(0));
+                    track.sampling_frequency = f64::from(be_u32(entry, 24).unwrap_or(0) >> 16);
+                    28
+                }
+                Some(1) => {
+                    track.channels = u64::from(be_u16(entry, 16).unwrap_or(0));
+                    track.sampling_frequency = f64::from(be_u32(entry, 24).unwrap_or(0) >> 16);
+                    44
+                }
+                // Version 2 moves the rate and channels to make room for rates above 65535
+                Some(2) => {
+                    track.sampling_frequency = f64::from_bits(be_u64(entry, 32).unwrap_or(0));
+                    track.channels = u64::from(be_u32(entry, 40).unwrap_or(0));
+                    64
+                }
+                _ => return Err("Invalid MP4 audio sample entry".into()),
+            };
+            let (_, esds) = mp4_boxes(entry.get(children..).unwrap_or_default())
+                .into_iter()
+                .find(|(kind, _)| kind == b"esds")
+                .ok_or("MP4 audio track has no esds box")?;
+            track.codec_id = "A_AAC";
+            track.codec_private = aac_specific_config(esds.get(4..).unwrap_or_default())
+                .ok_or("MP4 audio track isn't AAC")?
+                .to_vec();
+        }
+        _ => {
+            return Err(format!(
+                "Can't remux {} tracks, only H.264 video and AAC audio",
+                String::from_utf8_lossy(&kind)
+            )
+            .into())
+        }
+    }
+    Ok(())
+}
+
+/// Split an MPEG-4 descriptor into its tag, payload and whatever follows it
+fn mp4_descriptor(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (tag, mut rest) = data.split_first()?;
+    // The size is stored 7 bits per byte, with the top bit set on all but the last
+    let mut size = 0_usize;
+    for _ in 0..4 {
+        let (byte, after) = rest.split_first()?;
+        rest = after;
+        size = (size << 7) | usize::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let payload = rest.get(..size)?;
+    Some((*tag, payload, &rest[size..]))
+}
+
+/// Find the AudioSpecificConfig in the descriptors of an `esds` box, which is
+/// what Matroska stores as an AAC track's codec private data
+fn aac_specific_config(descriptors: &[u8]) -> Option<&[u8]> {
+    let (tag, es, _) = mp4_descriptor(descriptors)?;
+    if tag != 0x03 {
+        return None;
+    }
+    let flags = *es.get(2)?;
+    let mut start = 3;
+    if flags & 0x80 != 0 {
+        start += 2;
+    }
+    if flags & 0x40 != 0 {
+        start += 1 + usize::from(*es.get(start)?);
+    }
+    if flags & 0x20 != 0 {
+        start += 2;
+    }
+    let mut rest = es.get(start..)?;
+    while let Some((tag, payload, after)) = mp4_descriptor(rest) {
+        rest = after;
+        if tag != 0x04 {
+            continue;
+        }
+        // MPEG-4 audio, or one of the MPEG-2 AAC profiles
+        if !matches!(payload.first()?, 0x40 | 0x66..=0x68) {
+            return None;
+        }
+        let (tag, config, _) = mp4_descriptor(payload.get(13..)?)?;
+        return (tag == 0x05).then_some(config);
+    }
+    None
+}
+
+/// Write the tracks as Matroska, reading each frame from the MP4 as it's needed
+fn write_matroska<R: Read + Seek, W: Write + Seek>(
+    input: &mut R,
+    output: &mut W,
+    tracks: &[Track],
+    duration: Option<std::time::Duration>,
+) -> GenericResult<()> {
+    WebmWriter::new(&mut *output).write(&MatroskaSpec::Ebml(Master::Full(vec![
+        MatroskaSpec::EbmlVersion(1),
+        MatroskaSpec::EbmlReadVersion(1),
+        MatroskaSpec::EbmlMaxIdLength(4),
+        MatroskaSpec::EbmlMaxSizeLength(8),
+        MatroskaSpec::DocType("matroska".to_string()),
+        MatroskaSpec::DocTypeVersion(4),
+        MatroskaSpec::DocTypeReadVersion(2),
+    ])))?;
+    // The writer keeps a master's children in memory until it ends, so the
+    // segment header is written by hand and its size filled in at the end
+    output.write_all(&[0x18, 0x53, 0x80, 0x67])?;
+    let size_position = output.stream_position()?;
+    output.write_all(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff])?;
+    let segment_start = output.stream_position()?;
+
+    let mut writer = WebmWriter::new(&mut *output);
+    let mut info = vec![
+        MatroskaSpec::TimecodeScale(TIMECODE_SCALE),
+        MatroskaSpec::MuxingApp(env!("CARGO_PKG_NAME").to_string()),
+        MatroskaSpec::WritingApp(env!("CARGO_PKG_NAME").to_string()),
+    ];
+    if let Some(duration) = duration {
+        info.push(MatroskaSpec::Duration(duration.as_secs_f64() * 1000.0));
+    }
+    writer.write(&MatroskaSpec::Info(Master::Full(info)))?;
+    writer.write(&MatroskaSpec::Tracks(Master::Full(
+        tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| track_entry(i as u64 + 1, track))
+            .collect(),
+    )))?;
+
+    // Interleave the tracks in the order they're decoded
+    let mut order: Vec<(usize, usize)> = tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(t, track)| (0..track.samples.len()).map(move |s| (t, s)))
+        .collect();
+    order.sort_by_key(|(t, s)| tracks[*t].samples[*s].decode);
+    let has_video = tracks.iter().any(|track| track.kind == TrackKind::Video);
+
+    let mut cluster: Vec<MatroskaSpec> = Vec::new();
+    let mut cluster_time = 0_i64;
+    for (t, s) in order {
+        let track = &tracks[t];
+        let sample = &track.samples[s];
+        let relative = sample.presentation - cluster_time;
+        let starts_cluster = if has_video {
+            track.kind == TrackKind::Video && sample.keyframe
+        } else {
+            true
+        };
+        if cluster.is_empty()
+            || (starts_cluster && relative >= CLUSTER_LENGTH)
+            || i16::try_from(relative).is_err()
+        {
+            if !cluster.is_empty() {
+                writer.write(&MatroskaSpec::Cluster(Master::Full(std::mem::take(
+                    &mut cluster,
+                ))))?;
+            }
+            cluster_time = sample.presentation.max(0);
+            cluster.push(MatroskaSpec::Timecode(cluster_time as u64));
+        }
+        let relative = i16::try_from(sample.presentation - cluster_time)
+            .map_err(|_| "MP4 sample is too far from its cluster")?;
+
+        let mut block = Vec::with_capacity(4 + sample.size as usize);
+        block.push(0x80 | (t as u8 + 1));
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if sample.keyframe { 0x80 } else { 0 });
+        let header_len = block.len();
+        block.resize(header_len + sample.size as usize, 0);
+        input.seek(SeekFrom::Start(sample.offset))?;
+        input.read_exact(&mut block[header_len..])?;
+        cluster.push(MatroskaSpec::SimpleBlock(block));
+    }
+    if !cluster.is_empty() {
+        writer.write(&MatroskaSpec::Cluster(Master::Full(cluster)))?;
+    }
+    drop(writer);
+
+    let segment_size = output.stream_position()? - segment_start;
+    output.seek(SeekFrom::Start(size_position))?;
+    let mut size = segment_size.to_be_bytes();
+    size[0] = 0x01;
+    output.write_all(&size)?;
+    output.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+/// The `TrackEntry` describing a track
+fn track_entry(number: u64, track: &Track) -> MatroskaSpec {
+    let mut entry = vec![
+        MatroskaSpec::TrackNumber(number),
+        MatroskaSpec::TrackUid(number),
+        MatroskaSpec::TrackType(match track.kind {
+            TrackKind::Video => 1,
+            _ => 2,
+        }),
+        MatroskaSpec::FlagLacing(0),
+        MatroskaSpec::CodecId(track.codec_id.to_string()),
+        MatroskaSpec::CodecPrivate(track.codec_private.clone()),
+        MatroskaSpec::Language(track.language.clone().unwrap_or_else(|| "und".to_string())),
+    ];
+    if let Some(default_duration) = track.default_duration {
+        entry.push(MatroskaSpec::DefaultDuration(default_duration));
+    }
+    if track.kind == TrackKind::Video {
+        let (width, height) = track.pixel_dimensions;
+        let mut video = vec![
+            MatroskaSpec::PixelWidth(width),
+            MatroskaSpec::PixelHeight(height),
+        ];
+        if let Some((width, height)) = track.display_dimensions {
+            video.push(MatroskaSpec::DisplayWidth(width));
+            video.push(MatroskaSpec::DisplayHeight(height));
+        }
+        entry.push(MatroskaSpec::Video(Master::Full(video)));
+    } else {
+        entry.push(MatroskaSpec::Audio(Master::Full(vec![
+            MatroskaSpec::SamplingFrequency(track.sampling_frequency),
+            MatroskaSpec::Channels(track.channels),
+        ])));
+    }
+    MatroskaSpec::TrackEntry(Master::Full(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tempfile::TempDir;
+    use webm_iterable::WebmIterator;
+
+    use super::super::Metadata;
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = (8 + payload.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// A full box table of `entries`, each already encoded
+    fn mp4_table_box(kind: &[u8; 4], entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut payload = vec![0; 4];
+        payload.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        payload.extend(entries.concat());
+        mp4_box(kind, &payload)
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+
+    /// A track whose samples of `sizes` are all in one chunk at `offset`,
+    /// each lasting `delta` of `timescale`
+    #[allow(clippy::too_many_arguments)]
+    fn mp4_track(
+        handler: &[u8; 4],
+        timescale: u32,
+        sample_entry: Vec<u8>,
+        offset: u32,
+        sizes: &[u32],
+        delta: u32,
+        display: (u32, u32),
+    ) -> Vec<u8> {
+        let mut tkhd = vec![0; 84];
+        tkhd[76..80].copy_from_slice(&(display.0 << 16).to_be_bytes());
+        tkhd[80..84].copy_from_slice(&(display.1 << 16).to_be_bytes());
+        let mut mdhd = vec![0; 24];
+        mdhd[12..16].copy_from_slice(&timescale.to_be_bytes());
+        // "eng" packed as three 5-bit letters
+        mdhd[20..22].copy_from_slice(&((5 << 10) | (14 << 5) | 7u16).to_be_bytes());
+        let mut hdlr = vec![0; 24];
+        hdlr[8..12].copy_from_slice(handler);
+
+        let mut stsd = u32s(&[0, 1]);
+        stsd.extend(sample_entry);
+        let mut stsz = u32s(&[0, 0, sizes.len() as u32]);
+        stsz.extend(u32s(sizes));
+        let mut stbl = [
+            mp4_box(b"stsd", &stsd),
+            mp4_table_box(b"stts", &[u32s(&[sizes.len() as u32, delta])]),
+            mp4_table_box(b"stsc", &[u32s(&[1, sizes.len() as u32, 1])]),
+            mp4_box(b"stsz", &stsz),
+            mp4_table_box(b"stco", &[u32s(&[offset])]),
+        ]
+        .concat();
+        // Only the first video frame is a keyframe
+        if handler == b"vide" {
+            stbl.extend(mp4_table_box(b"stss", &[u32s(&[1])]));
+        }
+        let minf = mp4_box(b"stbl", &stbl);
+        let mdia = [
+            mp4_box(b"mdhd", &mdhd),
+            mp4_box(b"hdlr", &hdlr),
+            mp4_box(b"minf", &minf),
+        ]
+        .concat();
+        mp4_box(
+            b"trak",
+            &[mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &mdia)].concat(),
+        )
+    }
+
+    fn avc1(width: u16, height: u16) -> Vec<u8> {
+        let mut entry = vec![0; 78];
+        entry[24..26].copy_from_slice(&width.to_be_bytes());
+        entry[26..28].copy_from_slice(&height.to_be_bytes());
+        entry.extend(mp4_box(b"avcC", &[1, 0x64, 0, 0x28]));
+        mp4_box(b"avc1", &entry)
+    }
+
+    fn mp4a() -> Vec<u8> {
+        let mut entry = vec![0; 28];
+        entry[16..18].copy_from_slice(&2u16.to_be_bytes());
+        entry[24..28].copy_from_slice(&(48000u32 << 16).to_be_bytes());
+        // An ES descriptor holding a decoder config for MPEG-4 audio, which
+        // holds the AudioSpecificConfig
+        let mut decoder_config = vec![0x40, 0x15];
+        decoder_config.extend([0; 11]);
+        decoder_config.extend([0x05, 2, 0x11, 0x90]);
+        let mut es = vec![0, 1, 0, 0x04, decoder_config.len() as u8];
+        es.extend(decoder_config);
+        let mut esds = vec![0, 0, 0, 0, 0x03, es.len() as u8];
+        esds.extend(es);
+        entry.extend(mp4_box(b"esds", &esds));
+        mp4_box(b"mp4a", &entry)
+    }
+
+    /// Write an MP4 with two H.264 frames and one AAC frame into `dir`
+    fn write_mp4(dir: &Path, video_entry: Vec<u8>) -> PathBuf {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let media = b"keydeltaaac";
+        // The samples start after the ftyp and mdat headers
+        let start = ftyp.len() as u32 + 8;
+        let mut mvhd = vec![0; 20];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&80u32.to_be_bytes());
+        let moov = [
+            mp4_box(b"mvhd", &mvhd),
+            mp4_track(b"vide", 1000, video_entry, start, &[3, 5], 40, (1920, 1080)),
+            mp4_track(b"soun", 48000, mp4a(), start + 8, &[3], 1024, (0, 0)),
+        ]
+        .concat();
+        let data = [ftyp, mp4_box(b"mdat", media), mp4_box(b"moov", &moov)].concat();
+        let path = dir.join("source.mp4");
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn h264_and_aac_are_copied() {
+        let dir = TempDir::with_prefix("renamer-").unwrap();
+        let source = write_mp4(dir.path(), avc1(1440, 1080));
+        let remuxed = dir.path().join("remuxed.mkv");
+        remux_mp4_to_matroska(&source, &remuxed).unwrap();
+
+        let mut codecs = Vec::new();
+        let mut blocks = Vec::new();
+        let mut display_width = None;
+        let file = std::fs::File::open(&remuxed).unwrap();
+        for tag in WebmIterator::new(file, &[]) {
+            match tag.unwrap() {
+                MatroskaSpec::CodecId(id) => codecs.push(id),
+                MatroskaSpec::CodecPrivate(data) => codecs.push(format!("{:x?}", data)),
+                MatroskaSpec::DisplayWidth(width) => display_width = Some(width),
+                MatroskaSpec::SimpleBlock(data) => blocks.push(data),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            codecs,
+            ["V_MPEG4/ISO/AVC", "[1, 64, 0, 28]", "A_AAC", "[11, 90]"]
+        );
+        assert_eq!(display_width, Some(1920));
+        // Track, timestamp relative to the cluster, flags, then the frame
+        assert_eq!(
+            blocks,
+            [
+                [&[0x81, 0, 0, 0x80][..], b"key"].concat(),
+                [&[0x82, 0, 0, 0x80][..], b"aac"].concat(),
+                [&[0x81, 0, 40, 0][..], b"delta"].concat(),
+            ]
+        );
+
+        let metadata = Metadata::from_matroska(&remuxed).unwrap();
+        assert_eq!(metadata.resolution.1, 1080);
+    }
+
+    #[test]
+    fn other_codecs_are_an_error() {
+        let mut hevc = avc1(1920, 1080);
+        hevc[4..8].copy_from_slice(b"hvc1");
+        let dir = TempDir::with_prefix("renamer-").unwrap();
+        let source = write_mp4(dir.path(), hevc);
+        let remuxed = dir.path().join("remuxed.mkv");
+        let error = remux_mp4_to_matroska(&source, &remuxed).unwrap_err();
+        assert!(error.to_string().contains("hvc1"));
+    }
+}
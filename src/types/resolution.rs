@@ -0,0 +1,74 @@
+/// Vertical resolutions that measured resolutions are snapped to
+pub const STANDARD_RESOLUTIONS: [u64; 8] = [240, 360, 480, 720, 1080, 1440, 2160, 4320];
+
+/// A 16:9 frame with the given vertical resolution
+pub fn frame_from_vertical(vertical_resolution: u64) -> (u64, u64) {
+    (vertical_resolution / 9 * 16, vertical_resolution)
+}
+
+/// The nearest standard vertical resolution for a frame, treating wide frames
+/// by the height they would have at 16:9. Portrait frames are measured as if
+/// turned on their side, values outside the standard range are clamped to the
+/// smallest or largest and an unknown `0` stays `0`.
+pub fn snap_resolution((width, height): (u64, u64)) -> u64 {
+    let (width, height) = if height > width {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let best_resolution = u64::max(width / 16 * 9, height);
+    if best_resolution == 0 {
+        return 0;
+    }
+    STANDARD_RESOLUTIONS
+        .iter()
+        .copied()
+        // Ties go to the lower resolution
+        .min_by_key(|standard| standard.abs_diff(best_resolution))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_common_frames() {
+        for (frame, resolution) in [
+            ((1920, 1080), 1080),
+            ((1280, 720), 720),
+            ((3840, 2160), 2160),
+            ((720, 576), 480),
+            ((1440, 1080), 1080),
+            // Scope releases are measured by the height they would have at 16:9
+            ((1920, 800), 1080),
+            ((3840, 1600), 2160),
+        ] {
+            assert_eq!(snap_resolution(frame), resolution, "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn out_of_range_and_portrait_frames() {
+        for (frame, resolution) in [
+            ((640, 360), 360),
+            ((15360, 8640), 4320),
+            ((160, 90), 240),
+            // Phone video is measured on its side
+            ((1080, 1920), 1080),
+            ((720, 1280), 720),
+            ((1080, 1080), 1080),
+            ((0, 0), 0),
+        ] {
+            assert_eq!(snap_resolution(frame), resolution, "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn frames_from_vertical_snap_back() {
+        for resolution in STANDARD_RESOLUTIONS {
+            assert_eq!(snap_resolution(frame_from_vertical(resolution)), resolution);
+        }
+        assert_eq!(frame_from_vertical(1080), (1920, 1080));
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::read_dir;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use webm_iterable::{
+    matroska_spec::{Master, MatroskaSpec},
+    WebmWriter,
+};
+
+use crate::magic::split_extension;
+
+use super::GenericResult;
+
+/// Default Matroska TimestampScale, in nanoseconds
+pub const DEFAULT_TIMESTAMP_SCALE: u64 = 1_000_000;
+const SUBTITLE_TRACK_TYPE: u64 = 0x11;
+
+const SUBTITLE_EXTENSIONS: [&str; 3] = ["srt", "ass", "ssa"];
+
+/// Artwork and metadata that media servers expect named after the video
+const COMPANION_EXTENSIONS: [&str; 5] = ["nfo", "jpg", "jpeg", "png", "tbn"];
+
+/// Two letter language codes mapped to the ISO 639-2 codes Matroska expects
+const LANGUAGE_CODES: [(&str, &str); 16] = [
+    ("en", "eng"),
+    ("es", "spa"),
+    ("fr", "fre"),
+    ("de", "ger"),
+    ("it", "ita"),
+    ("pt", "por"),
+    ("nl", "dut"),
+    ("ru", "rus"),
+    ("ja", "jpn"),
+    ("ko", "kor"),
+    ("zh", "chi"),
+    ("ar", "ara"),
+    ("sv", "swe"),
+    ("no", "nor"),
+    ("da", "dan"),
+    ("pl", "pol"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    Ass,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubtitleEvent {
+    pub start: Duration,
+    pub end: Duration,
+    /// Block payload as stored in Matroska
+    pub payload: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Subtitle {
+    pub path: PathBuf,
+    pub format: SubtitleFormat,
+    /// ISO 639-2 language code, "und" when it couldn't be inferred
+    pub language: String,
+    /// Script header for ASS subtitles
+    pub codec_private: Option<String>,
+    /// From a `.forced` qualifier, only shown when the audio is foreign
+    pub forced: bool,
+    /// From a `.default` qualifier
+    pub default: bool,
+    pub events: Vec<SubtitleEvent>,
+}
+
+impl Subtitle {
+    /// Find subtitle files next to a video that share its file stem, e.g.
+    /// `Movie.mkv` has `Movie.srt` and `Movie.en.srt`
+    pub fn find_for_video<P: AsRef<Path>>(video: P) -> std::io::Result<Vec<PathBuf>> {
+        find_named_after(video.as_ref(), &SUBTITLE_EXTENSIONS, &['.'])
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P, video: P) -> GenericResult<Self> {
+        let path = path.as_ref();
+        let name = path.file_name().ok_or("Not a file")?.to_string_lossy();
+        let (stem, extension) = split_extension(&name);
+        let video_name = video
+            .as_ref()
+            .file_name()
+            .ok_or("Not a file")?
+            .to_string_lossy();
+        let (video_stem, _) = split_extension(&video_name);
+
+        let qualifiers = stem.strip_prefix(video_stem).unwrap_or("");
+        let language = qualifiers
+            .split('.')
+            .find_map(language_code)
+            .unwrap_or_else(|| "und".to_string());
+        let has_qualifier = |flag: &str| {
+            qualifiers
+                .split('.')
+                .any(|qualifier| qualifier.eq_ignore_ascii_case(flag))
+        };
+
+        let text = std::fs::read_to_string(path)?;
+        let text = text.trim_start_matches('\u{feff}');
+        let (format, codec_private, events) = match extension.as_str() {
+            "srt" => (SubtitleFormat::Srt, None, parse_srt(text)),
+            "ass" | "ssa" => {
+                let (header, events) = parse_ass(text);
+                (SubtitleFormat::Ass, Some(header), events)
+            }
+            _ => return Err(format!("Unsupported subtitle format {:?}", extension).into()),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            language,
+            codec_private,
+            forced: has_qualifier("forced"),
+            default: has_qualifier("default"),
+            events,
+        })
+    }
+
+    pub fn codec_id(&self) -> &'static str {
+        match self.format {
+            SubtitleFormat::Srt => "S_TEXT/UTF8",
+            SubtitleFormat::Ass => "S_TEXT/ASS",
+        }
+    }
+}
+
+/// Files next to `video` with one of `extensions` whose stem is the video's
+/// stem, or starts with it followed by one of `separators`
+fn find_named_after(
+    video: &Path,
+    extensions: &[&str],
+    separators: &[char],
+) -> std::io::Result<Vec<PathBuf>> {
+    let video_name = match video.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return Ok(Vec::new()),
+    };
+    let (video_stem, _) = split_extension(&video_name);
+    let parent = match video.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut found = Vec::new();
+    for entry in read_dir(parent)? {
+        let path = entry?.path();
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let (stem, extension) = split_extension(&name);
+        if extensions.contains(&extension.as_str())
+            && stem
+                .strip_prefix(video_stem)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(separators))
+        {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Find artwork and NFOs next to a video that share its file stem, e.g.
+/// `Movie.mkv` has `Movie.nfo`, `Movie.jpg` and `Movie-poster.jpg`
+pub fn find_companions<P: AsRef<Path>>(video: P) -> std::io::Result<Vec<PathBuf>> {
+    find_named_after(video.as_ref(), &COMPANION_EXTENSIONS, &['.', '-'])
+}
+
+/// The qualifiers and extension of a subtitle or companion after the video's
+/// stem, e.g. `.en.forced.srt` for `Movie.en.forced.srt` or `-poster.jpg` for
+/// `Movie-poster.jpg` next to `Movie.mkv`
+pub fn sidecar_suffix<P: AsRef<Path>>(subtitle: P, video: P) -> Option<String> {
+    let name = subtitle.as_ref().file_name()?.to_string_lossy().to_string();
+    let video_name = video.as_ref().file_name()?.to_string_lossy().to_string();
+    let (video_stem, _) = split_extension(&video_name);
+    name.strip_prefix(video_stem)
+        .filter(|suffix| suffix.starts_with(['.', '-']))
+        .map(|suffix| suffix.to_string())
+}
+
+/// Turn a file name qualifier like `en` or `pt-BR` into a Matroska language code
+fn language_code(qualifier: &str) -> Option<String> {
+    let language = qualifier.split('-').next()?.to_ascii_lowercase();
+    if !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    match language.len() {
+        2 => LANGUAGE_CODES
+            .iter()
+            .find(|(short, _)| *short == language)
+            .map(|(_, long)| long.to_string()),
+        3 => Some(language),
+        _ => None,
+    }
+}
+
+/// Parse `HH:MM:SS,mmm` (SRT) or `H:MM:SS.cc` (ASS) timestamps
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut parts = timestamp.trim().split(':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let (seconds, fraction) = parts.next()?.split_once(&[',', '.'][..])?;
+    let seconds: u64 = seconds.parse().ok()?;
+    let fraction_value: u64 = fraction.parse().ok()?;
+    let millis = match fraction.len() {
+        1 => fraction_value * 100,
+        2 => fraction_value * 10,
+        3 => fraction_value,
+        _ => return None,
+    };
+    Some(Duration::from_millis(
+        ((hours * 60 + minutes) * 60 + seconds) * 1000 + millis,
+    ))
+}
+
+fn parse_srt(text: &str) -> Vec<SubtitleEvent> {
+    let mut events = Vec::new();
+    let text = text.replace("\r\n", "\n");
+    for cue in text.split("\n\n") {
+        let mut lines = cue.lines().skip_while(|line| !line.contains("-->"));
+        let timing = match lines.next() {
+            Some(timing) => timing,
+            None => continue,
+        };
+        let (start, end) = match timing.split_once("-->") {
+            Some((start, end)) => (start, end.split_whitespace().next().unwrap_or("")),
+            None => continue,
+        };
+        if let (Some(start), Some(end)) = (parse_timestamp(start), parse_timestamp(end)) {
+            events.push(SubtitleEvent {
+                start,
+                end,
+                payload: lines.collect::<Vec<_>>().join("\n"),
+            });
+        }
+    }
+    events
+}
+
+/// Split an ASS script into its header (stored as codec private data) and
+/// events converted to the Matroska block layout
+fn parse_ass(text: &str) -> (String, Vec<SubtitleEvent>) {
+    let mut header = String::new();
+    let mut events = Vec::new();
+    let mut in_events = false;
+
+    for line in text.lines() {
+        if line.trim().eq_ignore_ascii_case("[Events]") {
+            in_events = true;
+        }
+        if !in_events {
+            header.push_str(line);
+            header.push('\n');
+            continue;
+        }
+        let dialogue = match line.strip_prefix("Dialogue:") {
+            Some(dialogue) => dialogue.trim_start(),
+            None => continue,
+        };
+        // Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+        let fields: Vec<&str> = dialogue.splitn(10, ',').collect();
+        if fields.len() != 10 {
+            continue;
+        }
+        if let (Some(start), Some(end)) = (parse_timestamp(fields[1]), parse_timestamp(fields[2])) {
+            // ReadOrder, Layer, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+            let payload = format!("{},{},{}", events.len(), fields[0], fields[3..].join(","));
+            events.push(SubtitleEvent {
+                start,
+                end,
+                payload,
+            });
+        }
+    }
+    (header, events)
+}
+
+/// Muxes subtitle events into the clusters of a Matroska file as it is rewritten
+pub(crate) struct SubtitleMuxer<'a> {
+    subtitles: &'a [Subtitle],
+    /// Events of every subtitle sorted by start time, with the index of their subtitle
+    events: Vec<(usize, &'a SubtitleEvent)>,
+    next_event: usize,
+    track_numbers: Vec<u64>,
+    pub timestamp_scale: u64,
+}
+
+impl<'a> SubtitleMuxer<'a> {
+    pub fn new(subtitles: &'a [Subtitle]) -> Self {
+        let mut events: Vec<_> = subtitles
+            .iter()
+            .enumerate()
+            .flat_map(|(i, subtitle)| subtitle.events.iter().map(move |event| (i, event)))
+            .collect();
+        events.sort_by_key(|(_, event)| event.start);
+        Self {
+            subtitles,
+            events,
+            next_event: 0,
+            track_numbers: Vec::new(),
+            timestamp_scale: DEFAULT_TIMESTAMP_SCALE,
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        !self.track_numbers.is_empty() && self.next_event < self.events.len()
+    }
+
+    fn ticks(&self, duration: Duration) -> u64 {
+        (duration.as_nanos() / u128::from(self.timestamp_scale.max(1))) as u64
+    }
+
+    /// Write a TrackEntry for each subtitle, numbered after the existing tracks
+    pub fn write_tracks<T: Write>(
+        &mut self,
+        writer: &mut WebmWriter<T>,
+        max_track_number: u64,
+    ) -> GenericResult<()> {
+        for (i, subtitle) in self.subtitles.iter().enumerate() {
+            let track_number = max_track_number + 1 + i as u64;
+            // Block headers are written with a single byte track number
+            if track_number > 126 {
+                return Err("Too many tracks to embed subtitles".into());
+            }
+            let mut hasher = DefaultHasher::new();
+            subtitle.path.hash(&mut hasher);
+
+            let mut entry = vec![
+                MatroskaSpec::TrackNumber(track_number),
+                MatroskaSpec::TrackUid(hasher.finish() | 1),
+                MatroskaSpec::TrackType(SUBTITLE_TRACK_TYPE),
+                MatroskaSpec::FlagLacing(0),
+                MatroskaSpec::CodecId(subtitle.codec_id().to_string()),
+                MatroskaSpec::Language(subtitle.language.clone()),
+                // FlagDefault is 1 when missing so it is always written
+                MatroskaSpec::FlagDefault(u64::from(subtitle.default)),
+                MatroskaSpec::FlagForced(u64::from(subtitle.forced)),
+            ];
+            if let Some(codec_private) = &subtitle.codec_private {
+                entry.push(MatroskaSpec::CodecPrivate(
+                    codec_private.as_bytes().to_vec(),
+                ));
+            }
+            writer.write(&MatroskaSpec::TrackEntry(Master::Full(entry)))?;
+            self.track_numbers.push(track_number);
+        }
+        Ok(())
+    }
+
+    fn write_block_group<T: Write>(
+        &self,
+        writer: &mut WebmWriter<T>,
+        cluster_timestamp: u64,
+        (subtitle, event): (usize, &SubtitleEvent),
+    ) -> GenericResult<()> {
+        let start = self.ticks(event.start) as i64;
+        let relative = (start - cluster_timestamp as i64).clamp(i16::MIN.into(), i16::MAX.into());
+
+        let mut block = vec![0x80 | self.track_numbers[subtitle] as u8];
+        block.extend_from_slice(&(relative as i16).to_be_bytes());
+        // No lacing or other flags
+        block.push(0);
+        block.extend_from_slice(event.payload.as_bytes());
+
+        writer.write(&MatroskaSpec::BlockGroup(Master::Full(vec![
+            MatroskaSpec::Block(block),
+            MatroskaSpec::BlockDuration(self.ticks(event.end.saturating_sub(event.start))),
+        ])))?;
+        Ok(())
+    }
+
+    /// Write every event whose timestamp can be stored relative to this cluster
+    pub fn write_into_cluster<T: Write>(
+        &mut self,
+        writer: &mut WebmWriter<T>,
+        cluster_timestamp: u64,
+    ) -> GenericResult<()> {
+        while self.is_pending() {
+            let event = self.events[self.next_event];
+            if self.ticks(event.1.start) > cluster_timestamp + i16::MAX as u64 {
+                break;
+            }
+            self.write_block_group(writer, cluster_timestamp, event)?;
+            self.next_event += 1;
+        }
+        Ok(())
+    }
+
+    /// Write events that come after the last cluster of the file in clusters of their own
+    pub fn write_remaining<T: Write>(&mut self, writer: &mut WebmWriter<T>) -> GenericResult<()> {
+        while self.is_pending() {
+            let cluster_timestamp = self.ticks(self.events[self.next_event].1.start);
+            writer.write(&MatroskaSpec::Cluster(Master::Start))?;
+            writer.write(&MatroskaSpec::Timecode(cluster_timestamp))?;
+            self.write_into_cluster(writer, cluster_timestamp)?;
+            writer.write(&MatroskaSpec::Cluster(Master::End))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn sidecar_suffix_keeps_every_qualifier() {
+        for (subtitle, suffix) in [
+            ("Movie.en.srt", ".en.srt"),
+            ("Movie.en.forced.srt", ".en.forced.srt"),
+            ("Movie.pt-BR.sdh.ass", ".pt-BR.sdh.ass"),
+            ("Movie.srt", ".srt"),
+        ] {
+            assert_eq!(
+                sidecar_suffix(Path::new(subtitle), Path::new("Movie.mkv")).as_deref(),
+                Some(suffix)
+            );
+        }
+        // A longer name that only starts with the stem isn't a sidecar
+        assert_eq!(
+            sidecar_suffix(Path::new("Movie2.srt"), Path::new("Movie.mkv")),
+            None
+        );
+    }
+
+    #[test]
+    fn companions_get_the_new_stem() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let dir = temp.path();
+        let video = dir.join("Show.S01E05.720p.mkv");
+        for name in [
+            "Show.S01E05.720p.mkv",
+            "Show.S01E05.720p.srt",
+            "Show.S01E05.720p.en.srt",
+            "Show.S01E05.720p.nfo",
+            "Show.S01E05.720p-poster.jpg",
+            "Show.S01E06.720p.srt",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let subtitles = Subtitle::find_for_video(&video).unwrap();
+        let companions = find_companions(&video).unwrap();
+        let renamed = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| format!("Show-S01E05-720p{}", sidecar_suffix(path, &video).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            renamed(&subtitles),
+            ["Show-S01E05-720p.en.srt", "Show-S01E05-720p.srt"]
+        );
+        assert_eq!(
+            renamed(&companions),
+            ["Show-S01E05-720p-poster.jpg", "Show-S01E05-720p.nfo"]
+        );
+    }
+
+    #[test]
+    fn qualifiers_set_language_and_flags() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let dir = temp.path();
+        let video = dir.join("Movie.mkv");
+        let path = dir.join("Movie.en.forced.srt");
+        std::fs::write(&path, "1\n00:00:01,000 --> 00:00:02,500\nHello\n").unwrap();
+
+        let subtitle = Subtitle::from_path(&path, &video).unwrap();
+        assert_eq!(subtitle.language, "eng");
+        assert!(subtitle.forced);
+        assert!(!subtitle.default);
+        assert_eq!(subtitle.events.len(), 1);
+    }
+}
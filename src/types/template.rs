@@ -0,0 +1,453 @@
+use std::str::FromStr;
+
+use format_num::format_num;
+
+use super::{sanitize_component, Entity, Episode, Metadata};
+
+/// Which kind of video a template names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateKind {
+    Movie,
+    Episode,
+}
+
+/// A value that can be substituted into a name template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// Movie title or series title
+    Title,
+    /// Release year of the movie, empty when unknown
+    Year,
+    /// Edition of the movie, e.g. `Director's Cut`, empty when unknown
+    Edition,
+    /// Release year of the series, empty when unknown
+    SeriesYear,
+    Season,
+    Episode,
+    EpisodeTitle,
+    /// Season and episode as `S01E05`
+    Numbering,
+    /// Vertical resolution without the `p`
+    Resolution,
+    /// The `--section-separator`
+    Separator,
+    /// The file extension, appended after a `.` when not used
+    Extension,
+}
+
+impl Placeholder {
+    /// Whether the placeholder has a value for this kind of video
+    pub fn is_valid_for(&self, kind: TemplateKind) -> bool {
+        match self {
+            Placeholder::Title
+            | Placeholder::Resolution
+            | Placeholder::Separator
+            | Placeholder::Extension => true,
+            Placeholder::Year | Placeholder::Edition => kind == TemplateKind::Movie,
+            Placeholder::SeriesYear
+            | Placeholder::Season
+            | Placeholder::Episode
+            | Placeholder::EpisodeTitle
+            | Placeholder::Numbering => kind == TemplateKind::Episode,
+        }
+    }
+
+    /// Whether the placeholder is a number that can be zero padded, e.g. `{season:02}`
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Placeholder::Year
+                | Placeholder::SeriesYear
+                | Placeholder::Season
+                | Placeholder::Episode
+                | Placeholder::Resolution
+        )
+    }
+}
+
+impl FromStr for Placeholder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "title" => Ok(Placeholder::Title),
+            "year" => Ok(Placeholder::Year),
+            "edition" => Ok(Placeholder::Edition),
+            "series_year" => Ok(Placeholder::SeriesYear),
+            "season" => Ok(Placeholder::Season),
+            "episode" => Ok(Placeholder::Episode),
+            "episode_title" => Ok(Placeholder::EpisodeTitle),
+            "numbering" => Ok(Placeholder::Numbering),
+            "resolution" => Ok(Placeholder::Resolution),
+            "sep" => Ok(Placeholder::Separator),
+            "ext" => Ok(Placeholder::Extension),
+            _ => Err(format!(
+                "Unknown placeholder {{{}}}, expected title, year, edition, series_year, season, episode, episode_title, numbering, resolution, sep or ext",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Literal(String),
+    /// A placeholder and the width to zero pad it to
+    Placeholder(Placeholder, Option<usize>),
+    /// A `/` or `\\` ending a directory name
+    Directory,
+}
+
+/// A file name pattern such as `{title} ({year}){sep}{resolution}p`.
+///
+/// Numbers can be zero padded with `{season:02}`, the extension is appended
+/// unless `{ext}` is used, and `{{`/`}}` write literal braces. Directories are
+/// separated with `/`, e.g. `{title} ({series_year})/Season {season}/{numbering}`,
+/// and are created inside the destination as needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl NameTemplate {
+    /// Parse a template, rejecting placeholders that don't apply to `kind`
+    pub fn parse(template: &str, kind: TemplateKind) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    if chars.as_str().starts_with('{') {
+                        chars.next();
+                        literal.push('{');
+                        continue;
+                    }
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed {{ in template {:?}", template))?;
+                    let (name, width) = match rest[..end].split_once(':') {
+                        Some((name, width)) => (name, Some(width)),
+                        None => (&rest[..end], None),
+                    };
+                    let placeholder: Placeholder = name.parse()?;
+                    if !placeholder.is_valid_for(kind) {
+                        return Err(format!(
+                            "{{{}}} can't be used in a {} template",
+                            name,
+                            match kind {
+                                TemplateKind::Movie => "movie",
+                                TemplateKind::Episode => "episode",
+                            }
+                        ));
+                    }
+                    let width = match width {
+                        Some(width) if placeholder.is_numeric() && width.starts_with('0') => {
+                            Some(width.parse().map_err(|_| {
+                                format!("Invalid width {:?} for {{{}}}", width, name)
+                            })?)
+                        }
+                        Some(width) => {
+                            return Err(format!(
+                                "Invalid width {:?} for {{{}}}, expected a number padded like {{season:02}}",
+                                width, name
+                            ))
+                        }
+                        None => None,
+                    };
+                    chars = rest[end + 1..].chars();
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(TemplatePart::Placeholder(placeholder, width));
+                }
+                '}' => {
+                    if !chars.as_str().starts_with('}') {
+                        return Err(format!("Unmatched }} in template {:?}", template));
+                    }
+                    chars.next();
+                    literal.push('}');
+                }
+                '/' | '\\' => {
+                    if literal.is_empty()
+                        && matches!(parts.last(), None | Some(TemplatePart::Directory))
+                    {
+                        return Err(format!(
+                            "Template {:?} has an empty directory name, names are relative to the destination",
+                            template
+                        ));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(TemplatePart::Directory);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+        if matches!(parts.last(), Some(TemplatePart::Directory)) {
+            return Err(format!("Template {:?} must end with a file name", template));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Fill in the template, giving a path relative to the destination with
+    /// directories separated by `/`.
+    ///
+    /// Brackets around a placeholder with no value are left out along with the
+    /// space before them, so an unknown year gives `Title` not `Title ()`.
+    /// Directories that come out empty, `.` or `..` are left out so the name
+    /// always stays inside the destination.
+    fn render(
+        &self,
+        separator: &str,
+        extension: &str,
+        value: impl Fn(Placeholder, Option<usize>) -> String,
+    ) -> String {
+        let mut directories = Vec::new();
+        let mut name = String::new();
+        let mut has_extension = false;
+        let mut skip_close = false;
+        for (i, part) in self.parts.iter().enumerate() {
+            match part {
+                TemplatePart::Literal(literal) if skip_close => {
+                    name.push_str(&literal[1..]);
+                    skip_close = false;
+                }
+                TemplatePart::Literal(literal) => name.push_str(literal),
+                TemplatePart::Directory => directories.push(std::mem::take(&mut name)),
+                TemplatePart::Placeholder(Placeholder::Separator, _) => name.push_str(separator),
+                TemplatePart::Placeholder(Placeholder::Extension, _) => {
+                    name.push_str(extension);
+                    has_extension = true;
+                }
+                TemplatePart::Placeholder(placeholder, width) => {
+                    let value = sanitize_component(&value(*placeholder, *width));
+                    if value.is_empty() {
+                        let close = match name.chars().last() {
+                            Some('(') => Some(')'),
+                            Some('[') => Some(']'),
+                            _ => None,
+                        };
+                        if let (Some(close), Some(TemplatePart::Literal(next))) =
+                            (close, self.parts.get(i + 1))
+                        {
+                            if next.starts_with(close) {
+                                name.pop();
+                                name.truncate(name.trim_end_matches(' ').len());
+                                skip_close = true;
+                            }
+                        }
+                    }
+                    match width {
+                        // Unknown numbers such as a missing year stay empty
+                        Some(width) if !value.is_empty() => {
+                            name.push_str(&format!("{:0>width$}", value, width = width))
+                        }
+                        _ => name.push_str(&value),
+                    }
+                }
+            }
+        }
+        if !has_extension {
+            name.push('.');
+            name.push_str(extension);
+        }
+        directories.retain(|directory| !matches!(directory.as_str(), "" | "." | ".."));
+        directories.push(name);
+        directories.join("/")
+    }
+
+    /// Fill in the template for a movie
+    pub fn render_movie(
+        &self,
+        movie: &Entity,
+        meta: &Metadata,
+        separator: &str,
+        extension: &str,
+    ) -> String {
+        self.render(separator, extension, |placeholder, _| match placeholder {
+            Placeholder::Title => movie.title.clone(),
+            Placeholder::Year => year(movie.release_year),
+            Placeholder::Edition => movie.edition.clone().unwrap_or_default(),
+            Placeholder::Resolution => meta.get_resolution().to_string(),
+            _ => String::new(),
+        })
+    }
+
+    /// Fill in the template for an episode
+    pub fn render_episode(
+        &self,
+        episode: &Episode,
+        meta: &Metadata,
+        separator: &str,
+        extension: &str,
+    ) -> String {
+        self.render(
+            separator,
+            extension,
+            |placeholder, width| match placeholder {
+                Placeholder::Title => episode.series.title.clone(),
+                Placeholder::SeriesYear => year(episode.series.release_year),
+                // Without a width numbers are padded like the original file name
+                Placeholder::Season if width.is_some() => episode.season.to_string(),
+                Placeholder::Season => format_num!(
+                    &format!("0{}.0", episode.season_digits.max(2)),
+                    episode.season
+                ),
+                Placeholder::Episode if width.is_some() => episode.episode.to_string(),
+                Placeholder::Episode => format_num!(
+                    &format!("0{}.0", episode.episode_digits.max(2)),
+                    episode.episode
+                ),
+                Placeholder::EpisodeTitle => episode.title.clone(),
+                Placeholder::Numbering => episode.numbering(),
+                Placeholder::Resolution => meta.get_resolution().to_string(),
+                _ => String::new(),
+            },
+        )
+    }
+}
+
+/// An unknown year is left empty rather than written as `0`
+fn year(year: u32) -> String {
+    if year == 0 {
+        String::new()
+    } else {
+        year.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie() -> Entity {
+        Entity {
+            title: "Some Movie".to_string(),
+            release_year: 2010,
+            imdb_id: None,
+            tmdb_id: None,
+            edition: Some("Directors Cut".to_string()),
+        }
+    }
+
+    fn episode() -> Episode {
+        Episode {
+            episode: 5,
+            episode_end: None,
+            season: 1,
+            title: "Pilot".to_string(),
+            imdb_id: None,
+            tmdb_id: None,
+            series: Entity {
+                edition: None,
+                release_year: 0,
+                ..movie()
+            },
+            season_digits: 1,
+            episode_digits: 3,
+        }
+    }
+
+    fn render_movie(template: &str) -> String {
+        NameTemplate::parse(template, TemplateKind::Movie)
+            .unwrap()
+            .render_movie(
+                &movie(),
+                &Metadata::from_vertical_resolution(1080, None),
+                " - ",
+                "mkv",
+            )
+    }
+
+    fn render_episode(template: &str) -> String {
+        NameTemplate::parse(template, TemplateKind::Episode)
+            .unwrap()
+            .render_episode(
+                &episode(),
+                &Metadata::from_vertical_resolution(720, None),
+                " - ",
+                "mkv",
+            )
+    }
+
+    #[test]
+    fn movie_templates() {
+        assert_eq!(
+            render_movie("{title} ({year}){sep}{resolution}p"),
+            "Some Movie (2010) - 1080p.mkv"
+        );
+        assert_eq!(
+            render_movie("{title} {{edition-{edition}}}"),
+            "Some Movie {edition-Directors Cut}.mkv"
+        );
+    }
+
+    #[test]
+    fn episode_templates() {
+        assert_eq!(
+            render_episode("{title}{sep}{numbering}{sep}{episode_title}"),
+            "Some Movie - S01E005 - Pilot.mkv"
+        );
+        // An unknown series year is left out along with its brackets
+        assert_eq!(
+            render_episode("{title} ({series_year}){sep}{numbering}"),
+            "Some Movie - S01E005.mkv"
+        );
+        assert_eq!(render_episode("{title} [{series_year}]"), "Some Movie.mkv");
+    }
+
+    #[test]
+    fn directories() {
+        assert_eq!(
+            render_episode("{title}/Season {season:02}/{title} - {numbering}"),
+            "Some Movie/Season 01/Some Movie - S01E005.mkv"
+        );
+        assert_eq!(
+            render_movie("{title} ({year})\\{title}"),
+            "Some Movie (2010)/Some Movie.mkv"
+        );
+        // A directory with no value is left out rather than naming the root
+        assert_eq!(render_episode("{series_year}/{title}"), "Some Movie.mkv");
+        assert_eq!(render_movie("../{title}"), "Some Movie.mkv");
+        for template in ["/{title}", "{title}//{year}", "{title}/"] {
+            assert!(NameTemplate::parse(template, TemplateKind::Movie).is_err());
+        }
+    }
+
+    #[test]
+    fn padded_widths() {
+        // Without a width numbers are padded like the source name
+        assert_eq!(render_episode("{season}x{episode}"), "01x005.mkv");
+        assert_eq!(render_episode("{season:03}x{episode:02}"), "001x05.mkv");
+        assert_eq!(render_movie("{resolution:05}"), "01080.mkv");
+        assert!(NameTemplate::parse("{title:02}", TemplateKind::Movie).is_err());
+        assert!(NameTemplate::parse("{season:2}", TemplateKind::Episode).is_err());
+    }
+
+    #[test]
+    fn extension_placement() {
+        assert_eq!(render_movie("{title}.{ext}"), "Some Movie.mkv");
+        assert_eq!(render_movie("{title}.{ext}.part"), "Some Movie.mkv.part");
+        assert_eq!(render_movie("{title}"), "Some Movie.mkv");
+    }
+
+    #[test]
+    fn placeholders_are_checked_per_kind() {
+        for template in ["{season}", "{episode}", "{numbering}", "{series_year}"] {
+            assert!(NameTemplate::parse(template, TemplateKind::Movie).is_err());
+            assert!(NameTemplate::parse(template, TemplateKind::Episode).is_ok());
+        }
+        for template in ["{year}", "{edition}"] {
+            assert!(NameTemplate::parse(template, TemplateKind::Movie).is_ok());
+            assert!(NameTemplate::parse(template, TemplateKind::Episode).is_err());
+        }
+        for template in ["{unknown}", "{title", "title}"] {
+            assert!(NameTemplate::parse(template, TemplateKind::Movie).is_err());
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+
+use super::CleanerPipeline;
+use super::TitleCleaner;
+
+lazy_static! {
+    /// Names given by rippers and cameras rather than people
+    static ref JUNK_TITLE: Regex = RegexBuilder::new(r"^(t|title|track|ep|episode)\s*\d+$|^\d+$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    /// Directories like "Season 1" or "S01" that sit between a show and its files
+    static ref SEASON_DIRECTORY: Regex = RegexBuilder::new(r"^(season\s*\d+|s\d+|specials)$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    /// A release year on its own or in brackets, e.g. "2010" or "(2010)"
+    static ref FOLDER_YEAR: Regex = Regex::new(r"^[(\[]?((?:19|20)\d{2})[)\]]?$").unwrap();
+    /// Release details that follow the title in folder names
+    static ref FOLDER_QUALITY: Regex = RegexBuilder::new(r"^\d{3,4}p$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+}
+
+/// Where the title of a video is taken from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TitleSource {
+    /// Parse the title from the file name
+    #[default]
+    File,
+    /// Use the name of the directory containing the file, skipping season directories
+    Dir,
+    /// Use the file name unless it looks like junk, then fall back to the directory
+    Auto,
+}
+
+impl FromStr for TitleSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(TitleSource::File),
+            "dir" => Ok(TitleSource::Dir),
+            "auto" => Ok(TitleSource::Auto),
+            _ => Err(format!(
+                "Unknown title source {:?}, expected file, dir or auto",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether a title parsed from a file name is meaningless, e.g. "t01",
+/// "episode3" or entirely numeric
+pub fn is_junk_title(title: &str) -> bool {
+    let title = title.trim();
+    title.is_empty() || JUNK_TITLE.is_match(title)
+}
+
+/// The title from the directory a video is in, using the grandparent when
+/// the parent is a season directory
+pub fn directory_title(path: &Path) -> Option<String> {
+    let cleaner = CleanerPipeline::standard();
+    path.ancestors()
+        .skip(1)
+        .take(2)
+        .filter_map(|dir| dir.file_name())
+        .map(|name| cleaner.clean(&name.to_string_lossy().replace('.', " ")))
+        .find(|name| !name.is_empty() && !SEASON_DIRECTORY.is_match(name))
+}
+
+/// The title and release year from a movie's own folder, e.g.
+/// `Inception (2010)` or `Inception.2010.1080p`. The year is 0 when missing.
+pub fn movie_folder_title(path: &Path) -> Option<(String, u32)> {
+    let folder = path
+        .parent()?
+        .file_name()?
+        .to_string_lossy()
+        .replace(['.', '_'], " ");
+    let words: Vec<&str> = folder.split_whitespace().collect();
+
+    let mut title_end = words.len();
+    let mut release_year = 0;
+    // The first word is always title so folders like "1917 (2019)" work
+    for (i, word) in words.iter().enumerate().skip(1) {
+        if let Some(captures) = FOLDER_YEAR.captures(word) {
+            release_year = captures[1].parse().unwrap_or(0);
+            title_end = i;
+            break;
+        }
+        if FOLDER_QUALITY.is_match(word) {
+            title_end = i;
+            break;
+        }
+    }
+
+    let title = CleanerPipeline::standard().clean(&words[..title_end].join(" "));
+    (!title.is_empty()).then_some((title, release_year))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn junk_titles() {
+        for title in ["", "t01", "Title 3", "episode3", "EP 12", "12345"] {
+            assert!(is_junk_title(title), "{:?}", title);
+        }
+        for title in ["Heat", "The 100", "Episode One"] {
+            assert!(!is_junk_title(title), "{:?}", title);
+        }
+    }
+
+    #[test]
+    fn directory_title_skips_season_directories() {
+        assert_eq!(
+            directory_title(Path::new("Show Name/Season 1/t01.mkv")).as_deref(),
+            Some("Show Name")
+        );
+        assert_eq!(
+            directory_title(Path::new("Show.Name/S02/t01.mkv")).as_deref(),
+            Some("Show Name")
+        );
+        assert_eq!(
+            directory_title(Path::new("Movie Name/t01.mkv")).as_deref(),
+            Some("Movie Name")
+        );
+        assert_eq!(directory_title(Path::new("t01.mkv")), None);
+    }
+
+    #[test]
+    fn movie_folder_title_reads_the_year() {
+        let folder = |path| movie_folder_title(Path::new(path));
+        assert_eq!(
+            folder("Inception (2010)/inception.mkv"),
+            Some(("Inception".to_string(), 2010))
+        );
+        assert_eq!(
+            folder("Inception.2010.1080p/movie.mkv"),
+            Some(("Inception".to_string(), 2010))
+        );
+        assert_eq!(
+            folder("1917 (2019)/movie.mkv"),
+            Some(("1917".to_string(), 2019))
+        );
+        assert_eq!(
+            folder("Heat 1080p/movie.mkv"),
+            Some(("Heat".to_string(), 0))
+        );
+        assert_eq!(folder("movie.mkv"), None);
+    }
+}
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::str::FromStr;
 
-use format_num::format_num;
 use lazy_static::lazy_static;
 use regex::{Regex, RegexBuilder};
 use webm_iterable::{
@@ -10,12 +11,23 @@ use webm_iterable::{
     WebmIterator, WebmWriter,
 };
 
-use crate::magic::FileType;
+use crate::magic::{split_extension, strip_inner_extension, FileType};
 
+use super::is_scene_token;
+use super::sanitize_component;
+use super::CleanerPipeline;
+use super::EditionDetector;
 use super::Entity;
 use super::Episode;
 use super::GenericResult;
 use super::Metadata;
+use super::MetadataBackend;
+use super::NameTemplate;
+use super::Subtitle;
+use super::SubtitleMuxer;
+use super::TitleCaser;
+use super::TitleCleaner;
+use super::{directory_title, is_junk_title, movie_folder_title, TitleSource};
 
 #[derive(Debug, Clone)]
 pub struct Video {
@@ -23,6 +35,215 @@ pub struct Video {
     pub file_type: FileType,
     pub file_extension: String,
     pub info: VideoData,
+    /// How the IMDB id is written in tags
+    pub imdb_tag_format: ImdbTagFormat,
+    /// Where the file was found relative to the source directory, written to
+    /// the `ORIGINAL_PATH` tag unless the file already has one
+    pub original_path: Option<String>,
+}
+
+/// How an IMDB id such as `tt1234567` is stored in the `IMDB` tag
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImdbTagFormat {
+    /// `tt1234567`
+    #[default]
+    Id,
+    /// `1234567`
+    Number,
+    /// `https://www.imdb.com/title/tt1234567/`
+    Url,
+}
+
+impl ImdbTagFormat {
+    pub fn format(&self, imdb_id: &str) -> String {
+        match self {
+            ImdbTagFormat::Id => imdb_id.to_string(),
+            ImdbTagFormat::Number => imdb_id.trim_start_matches("tt").to_string(),
+            ImdbTagFormat::Url => format!("https://www.imdb.com/title/{}/", imdb_id),
+        }
+    }
+}
+
+/// How a file name was split and which words were recognised, for `--dump-parse`
+#[derive(Debug, Clone, Default)]
+pub struct ParseTrace {
+    pub parts: Vec<String>,
+    /// Index of each recognised word and what it was recognised as
+    pub matches: Vec<(usize, &'static str)>,
+    /// The title is `parts[..title_end]`
+    pub title_end: usize,
+    /// The episode title is `parts[episode_title_start..episode_title_end]`,
+    /// from just past the numbering to the first word that can't be in a title
+    pub episode_title_start: usize,
+    pub episode_title_end: usize,
+    pub title: String,
+    pub episode_title: Option<String>,
+}
+
+impl fmt::Display for ParseTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "  Parts:             ")?;
+        for (i, part) in self.parts.iter().enumerate() {
+            write!(f, "[{} {:?}] ", i, part)?;
+        }
+        writeln!(f)?;
+        write!(f, "  Matches:           ")?;
+        for (i, kind) in self.matches.iter() {
+            write!(f, "[{} {}] ", i, kind)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  Title end:         {}", self.title_end)?;
+        writeln!(
+            f,
+            "  Episode words:     {}..{}",
+            self.episode_title_start, self.episode_title_end
+        )?;
+        writeln!(f, "  Title:             {:?}", self.title)?;
+        write!(f, "  Episode title:     {:?}", self.episode_title)
+    }
+}
+
+/// Outcome of reading a number out of a regex capture group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsedNumber<T> {
+    /// The pattern didn't match, so there was no number to read
+    NoMatch,
+    /// The pattern matched but the number doesn't fit in `T`
+    OutOfRange,
+    Value {
+        value: T,
+        digits: usize,
+    },
+}
+
+impl<T: FromStr> ParsedNumber<T> {
+    /// Parse capture group `group`, if the pattern matched at all
+    pub fn from_captures(captures: Option<regex::Captures>, group: usize) -> Self {
+        let digits = match captures.as_ref().and_then(|c| c.get(group)) {
+            Some(digits) => digits.as_str(),
+            None => return ParsedNumber::NoMatch,
+        };
+        // The patterns only capture ASCII digits, so the only way to fail is overflow
+        match digits.parse::<T>() {
+            Ok(value) => ParsedNumber::Value {
+                value,
+                digits: digits.len(),
+            },
+            Err(_) => ParsedNumber::OutOfRange,
+        }
+    }
+}
+
+/// Choices for how output file names are written
+#[derive(Debug, Clone)]
+pub struct NameFormat {
+    /// Add the series start year after the title of episodes, e.g. `Show (2020)`
+    pub series_year: bool,
+    /// Add the release year after the title of movies, e.g. `Movie (2010)`
+    pub movie_year: bool,
+    /// Written between the title, episode numbering and resolution
+    pub section_separator: String,
+    /// Replaces the built-in movie name when set
+    pub movie_template: Option<NameTemplate>,
+    /// Replaces the built-in episode name when set
+    pub episode_template: Option<NameTemplate>,
+}
+
+impl Default for NameFormat {
+    fn default() -> Self {
+        Self {
+            series_year: false,
+            movie_year: false,
+            section_separator: "-".to_string(),
+            movie_template: None,
+            episode_template: None,
+        }
+    }
+}
+
+/// When to ask IMDB about a video
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImdbWhen {
+    /// Only when the title or release year is missing
+    Missing,
+    /// When the title is missing, junk, a single word or lacks a release year
+    Ambiguous,
+    /// For every video
+    #[default]
+    Always,
+}
+
+impl FromStr for ImdbWhen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "missing" => Ok(ImdbWhen::Missing),
+            "ambiguous" => Ok(ImdbWhen::Ambiguous),
+            "always" => Ok(ImdbWhen::Always),
+            _ => Err(format!(
+                "Unknown IMDB mode {:?}, expected missing, ambiguous or always",
+                s
+            )),
+        }
+    }
+}
+
+/// Kinds of IMDB title a movie can be matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImdbKind {
+    Movie,
+    TvMovie,
+    Short,
+    TvShort,
+    TvSpecial,
+    /// Direct to video releases, including music videos
+    Video,
+}
+
+impl ImdbKind {
+    /// Used when `--imdb-kinds` isn't given
+    pub const DEFAULT_MOVIE_KINDS: [ImdbKind; 4] = [
+        ImdbKind::Movie,
+        ImdbKind::TvMovie,
+        ImdbKind::Short,
+        ImdbKind::TvShort,
+    ];
+}
+
+impl FromStr for ImdbKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "movie" => Ok(ImdbKind::Movie),
+            "tvmovie" => Ok(ImdbKind::TvMovie),
+            "short" => Ok(ImdbKind::Short),
+            "tvshort" => Ok(ImdbKind::TvShort),
+            "tvspecial" => Ok(ImdbKind::TvSpecial),
+            "video" => Ok(ImdbKind::Video),
+            _ => Err(format!(
+                "Unknown IMDB kind {:?}, expected movie, tvmovie, short, tvshort, tvspecial or video",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for ImdbTagFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(ImdbTagFormat::Id),
+            "number" => Ok(ImdbTagFormat::Number),
+            "url" => Ok(ImdbTagFormat::Url),
+            _ => Err(format!(
+                "Unknown IMDB tag format {:?}, expected id, number or url",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -31,16 +252,51 @@ pub enum VideoData {
     Movie(Entity, Metadata),
 }
 
+/// Masters the matroska rewriter reads whole, everything else (clusters,
+/// attachments, cues) streams through element by element. Only add small
+/// elements here as each one is held in memory until it ends. Single binary
+/// leaves such as an attachment's FileData are always read whole.
+const BUFFERED_MASTERS: [MatroskaSpec; 1] = [MatroskaSpec::SimpleTag(Master::Start)];
+
+// Patterns are anchored to the start of a token so titles like "Se7en" or
+// "24" aren't mistaken for season or episode numbers
 lazy_static! {
-    static ref SEASON: Regex = RegexBuilder::new(r"s(\d+)")
+    static ref SEASON: Regex = RegexBuilder::new(r"^s(\d+)(e\d+)*(-e?\d+)?$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    static ref EPISODE: Regex = RegexBuilder::new(r"^(s\d+)?e(\d+)")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    /// The last episode of `S01E01E02` or `S01E01-02`, the digits have to be
+    /// separated so `E0102` isn't split
+    static ref EPISODE_RANGE_END: Regex = RegexBuilder::new(r"^(?:s\d+)?e\d+(?:-e?|e)(\d+)$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    /// The `E02` split from `S01E01-E02` on the dash
+    static ref EPISODE_CONTINUATION: Regex = RegexBuilder::new(r"^e(\d+)$")
         .case_insensitive(true)
         .build()
         .unwrap();
-    static ref EPISODE: Regex = RegexBuilder::new(r"e(\d+)")
+    /// `1x02`, with few enough digits that `1920x1080` isn't matched
+    static ref CROSS_NUMBERING: Regex = RegexBuilder::new(r"^(\d{1,2})x(\d{1,3})$")
         .case_insensitive(true)
         .build()
         .unwrap();
-    static ref QUALITY: Regex = RegexBuilder::new(r"(\d{3,})p")
+    static ref QUALITY: Regex = RegexBuilder::new(r"^(\d{3,})p$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    /// `{tmdb-12345}` is split on the dash so the id is the following word
+    static ref TMDB_ID_PREFIX: Regex = RegexBuilder::new(r"^[{\[(]?tmdb(id)?$")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    static ref YEAR: Regex = Regex::new(r"^[(\[]?((?:19|20)\d{2})[)\]]?$").unwrap();
+    static ref TMDB_ID_NUMBER: Regex = Regex::new(r"^(\d+)[}\])]?$").unwrap();
+    static ref IMDB_ID_TOKEN: Regex = RegexBuilder::new(r"^tt\d{7,8}$")
         .case_insensitive(true)
         .build()
         .unwrap();
@@ -48,75 +304,404 @@ lazy_static! {
 
 const TITLE: &str = "TITLE";
 const DATE_RELEASED: &str = "DATE_RELEASED";
-const COMMENT: &str = "COMMENT";
 const IMDB_ID: &str = "IMDB";
+const TMDB_ID: &str = "TMDB";
 const EPISODE_NUMBER: &str = "EPISODE";
 const SEASON_NUMBER: &str = "SEASON";
+const ORIGINAL_PATH: &str = "ORIGINAL_PATH";
+
+/// Split a file name into words on dots, spaces and dashes, keeping dashes
+/// between digits so titles like "9-1-1" stay together
+fn split_file_name(file_name: &str) -> Vec<&str> {
+    let bytes = file_name.as_bytes();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (i, c) in file_name.char_indices() {
+        let is_separator = match c {
+            '.' | ' ' => true,
+            '-' => {
+                !(i > 0
+                    && bytes[i - 1].is_ascii_digit()
+                    && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+            }
+            _ => false,
+        };
+        if is_separator {
+            parts.push(&file_name[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&file_name[start..]);
+    parts
+}
+
+/// An unknown year is left empty so the file's own date is kept
+fn release_date(year: u32) -> String {
+    if year == 0 {
+        String::new()
+    } else {
+        year.to_string()
+    }
+}
+
+/// Strip a leading BOM and trailing nulls that some muxers leave in tag strings
+fn sanitize_tag_string(value: &str) -> String {
+    value
+        .trim_start_matches('\u{feff}')
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+fn sanitize_tag(tag: MatroskaSpec) -> MatroskaSpec {
+    match tag {
+        MatroskaSpec::TagString(value) => MatroskaSpec::TagString(sanitize_tag_string(&value)),
+        MatroskaSpec::Title(value) => MatroskaSpec::Title(sanitize_tag_string(&value)),
+        MatroskaSpec::SimpleTag(Master::Full(children)) => MatroskaSpec::SimpleTag(Master::Full(
+            children.into_iter().map(sanitize_tag).collect(),
+        )),
+        tag => tag,
+    }
+}
+
+/// Tags already in a Matroska file, see `read_matroska_tags`
+#[derive(Debug, Clone, Default)]
+pub struct ExistingTags {
+    /// Title from the Info element
+    pub title: Option<String>,
+    /// Name and value of each SimpleTag, nested tags are named `PARENT/CHILD`
+    pub tags: Vec<(String, String)>,
+}
+
+fn collect_simple_tag(
+    children: &[MatroskaSpec],
+    parent: Option<&str>,
+    tags: &mut Vec<(String, String)>,
+) {
+    let name = children.iter().find_map(|child| match child {
+        MatroskaSpec::TagName(name) => Some(match parent {
+            Some(parent) => format!("{}/{}", parent, name),
+            None => name.clone(),
+        }),
+        _ => None,
+    });
+    let name = match name {
+        Some(name) => name,
+        None => return,
+    };
+    for child in children {
+        match child {
+            MatroskaSpec::TagString(value) => tags.push((name.clone(), sanitize_tag_string(value))),
+            MatroskaSpec::SimpleTag(Master::Full(nested)) => {
+                collect_simple_tag(nested, Some(&name), tags)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Read the title and every tag from a Matroska file without changing it
+pub fn read_matroska_tags<F: Read>(from: &mut F) -> GenericResult<ExistingTags> {
+    let mut existing = ExistingTags::default();
+    let mut in_info = false;
+    for tag in WebmIterator::new(from, &BUFFERED_MASTERS) {
+        match tag? {
+            MatroskaSpec::Info(Master::Start) => in_info = true,
+            MatroskaSpec::Info(Master::End) => in_info = false,
+            MatroskaSpec::Title(title) if in_info && existing.title.is_none() => {
+                existing.title = Some(sanitize_tag_string(&title))
+            }
+            MatroskaSpec::SimpleTag(Master::Full(children)) => {
+                collect_simple_tag(&children, None, &mut existing.tags)
+            }
+            // Tags can be at the end of the file so the whole file is read
+            _ => {}
+        }
+    }
+    Ok(existing)
+}
+
+/// Write a Tag holding our tags, empty values are left out
+fn write_tag_entries<T: Write>(
+    writer: &mut WebmWriter<T>,
+    tags: &HashMap<String, String>,
+) -> GenericResult<()> {
+    writer.write(&MatroskaSpec::Tag(Master::Start))?;
+    writer.write(&MatroskaSpec::Targets(Master::Full(vec![])))?;
+    for (k, v) in tags.iter() {
+        if !v.is_empty() {
+            writer.write(&MatroskaSpec::SimpleTag(Master::Start))?;
+            writer.write(&MatroskaSpec::TagName(k.to_string()))?;
+            writer.write(&MatroskaSpec::TagString(sanitize_tag_string(v)))?;
+            writer.write(&MatroskaSpec::SimpleTag(Master::End))?;
+        }
+    }
+    writer.write(&MatroskaSpec::Tag(Master::End))?;
+    Ok(())
+}
 
 impl Video {
     pub fn from_path(path: PathBuf, file_type: FileType) -> GenericResult<Self> {
+        Self::from_path_with_backend(path, file_type, MetadataBackend::default())
+    }
+
+    pub fn from_path_with_backend(
+        path: PathBuf,
+        file_type: FileType,
+        backend: MetadataBackend,
+    ) -> GenericResult<Self> {
+        Self::from_path_with_cleaner(
+            path,
+            file_type,
+            &CleanerPipeline::standard(),
+            &EditionDetector::default(),
+            backend,
+        )
+    }
+
+    pub fn from_path_with_cleaner(
+        path: PathBuf,
+        file_type: FileType,
+        cleaner: &dyn TitleCleaner,
+        editions: &EditionDetector,
+        backend: MetadataBackend,
+    ) -> GenericResult<Self> {
+        Self::parse(path, file_type, cleaner, editions, backend, None)
+    }
+
+    /// Parse a video while recording how its file name was tokenized
+    pub fn from_path_traced(
+        path: PathBuf,
+        file_type: FileType,
+        backend: MetadataBackend,
+    ) -> GenericResult<(Self, ParseTrace)> {
+        let mut trace = ParseTrace::default();
+        let video = Self::parse(
+            path,
+            file_type,
+            &CleanerPipeline::standard(),
+            &EditionDetector::default(),
+            backend,
+            Some(&mut trace),
+        )?;
+        Ok((video, trace))
+    }
+
+    fn parse(
+        path: PathBuf,
+        file_type: FileType,
+        cleaner: &dyn TitleCleaner,
+        editions: &EditionDetector,
+        backend: MetadataBackend,
+        trace: Option<&mut ParseTrace>,
+    ) -> GenericResult<Self> {
         let file_name = path.file_name().ok_or("Not a file")?.to_string_lossy();
-        let mut file_name_parts: Vec<&str> = file_name.split(&['.', ' ', '-'][..]).collect();
-        let file_extension = file_name_parts
-            .remove(file_name_parts.len() - 1)
-            .to_string();
+        let (file_stem, file_extension) = split_extension(&file_name);
+        let file_name_parts = split_file_name(strip_inner_extension(file_stem));
 
         let mut title_end = file_name_parts.len();
-        let mut episode_title_end = title_end;
+        // Just past the season and episode numbers, where an episode title starts
+        let mut numbering_end = None;
+        // Words that can't be part of an episode title, which ends at the first after the numbering
+        let mut markers = Vec::new();
         let mut season = None;
+        let mut season_digits = 0;
         let mut episode = None;
+        let mut episode_digits = 0;
+        let mut episode_end = None;
+        // The first `1x02` style numbering, only used without `SxxExx`
+        let mut cross_numbering = None;
+        let mut years = Vec::new();
         let mut quality = None;
+        let mut imdb_id = None;
+        let mut tmdb_id = None;
+        let mut matches = Vec::new();
         for i in 0..file_name_parts.len() {
             let part = file_name_parts[i];
 
-            if let Some(captures) = SEASON.captures(part) {
-                if let Ok(n) = u32::from_str_radix(captures.get(1).unwrap().as_str(), 10) {
-                    season = Some(n);
+            // `E02` straight after `S01E01` ends a range rather than replacing the episode
+            if let (Some(first), true) = (episode, numbering_end == Some(i)) {
+                if let ParsedNumber::Value { value, .. } =
+                    ParsedNumber::<u32>::from_captures(EPISODE_CONTINUATION.captures(part), 1)
+                {
+                    episode_end = (value > first).then_some(value);
+                    numbering_end = Some(i + 1);
+                    matches.push((i, "last episode"));
+                    continue;
+                }
+            }
+
+            match ParsedNumber::<u32>::from_captures(SEASON.captures(part), 1) {
+                ParsedNumber::Value { value, digits } => {
+                    season = Some(value);
+                    season_digits = digits;
                     title_end = usize::min(i, title_end);
+                    numbering_end = Some(i + 1);
+                    matches.push((i, "season"));
                 }
+                ParsedNumber::OutOfRange => matches.push((i, "season out of range")),
+                ParsedNumber::NoMatch => {}
             }
 
-            if let Some(captures) = EPISODE.captures(part) {
-                if let Ok(n) = u32::from_str_radix(captures.get(1).unwrap().as_str(), 10) {
-                    episode = Some(n);
+            match ParsedNumber::<u32>::from_captures(EPISODE.captures(part), 2) {
+                ParsedNumber::Value { value, digits } => {
+                    episode = Some(value);
+                    episode_digits = digits;
+                    episode_end = match ParsedNumber::<u32>::from_captures(
+                        EPISODE_RANGE_END.captures(part),
+                        1,
+                    ) {
+                        ParsedNumber::Value { value: end, .. } => (end > value).then_some(end),
+                        _ => None,
+                    };
                     title_end = usize::min(i, title_end);
+                    numbering_end = Some(i + 1);
+                    matches.push((i, "episode"));
                 }
+                ParsedNumber::OutOfRange => matches.push((i, "episode out of range")),
+                ParsedNumber::NoMatch => {}
             }
 
-            if let Some(captures) = QUALITY.captures(part) {
-                if let Ok(n) = u64::from_str_radix(captures.get(1).unwrap().as_str(), 10) {
-                    quality = Some(n);
+            if cross_numbering.is_none() && CROSS_NUMBERING.is_match(part) {
+                cross_numbering = Some(i);
+            }
+
+            // The first word is always title so names like "1917.mkv" work
+            if i > 0 && YEAR.is_match(part) {
+                years.push(i);
+            }
+
+            match ParsedNumber::<u64>::from_captures(QUALITY.captures(part), 1) {
+                ParsedNumber::Value { value, .. } => {
+                    quality = Some(value);
                     title_end = usize::min(i, title_end);
-                    episode_title_end = usize::min(i, episode_title_end);
+                    markers.push(i);
+                    matches.push((i, "quality"));
                 }
+                ParsedNumber::OutOfRange => matches.push((i, "quality out of range")),
+                ParsedNumber::NoMatch => {}
+            }
+
+            if IMDB_ID_TOKEN.is_match(part) {
+                imdb_id = Some(part.to_lowercase());
+                title_end = usize::min(i, title_end);
+                markers.push(i);
+                matches.push((i, "imdb id"));
+            }
+
+            if TMDB_ID_PREFIX.is_match(part) {
+                if let Some(captures) = file_name_parts
+                    .get(i + 1)
+                    .and_then(|number| TMDB_ID_NUMBER.captures(number))
+                {
+                    tmdb_id = Some(captures[1].to_string());
+                    title_end = usize::min(i, title_end);
+                    markers.push(i);
+                    matches.push((i, "tmdb id"));
+                }
+            }
+
+            if is_scene_token(part) {
+                markers.push(i);
             }
         }
 
-        let title = file_name_parts[..title_end].join(" ");
-        let episode_title = if usize::checked_sub(episode_title_end, title_end).unwrap_or(0) > 1 {
-            Some(file_name_parts[title_end + 1..episode_title_end].join(" "))
-        } else {
-            None
-        };
+        if let Some(i) = cross_numbering {
+            if season.is_none() && episode.is_none() {
+                if let Some(captures) = CROSS_NUMBERING.captures(file_name_parts[i]) {
+                    // Both numbers have too few digits to overflow
+                    season = captures[1].parse().ok();
+                    season_digits = captures[1].len();
+                    episode = captures[2].parse().ok();
+                    episode_digits = captures[2].len();
+                    title_end = usize::min(i, title_end);
+                    numbering_end = Some(i + 1);
+                    matches.push((i, "season and episode"));
+                }
+            } else {
+                // `SxxExx` wins, the other numbering still ends an episode title
+                markers.push(i);
+            }
+        }
 
-        let metadata = if file_type == FileType::MKV {
-            Metadata::from_matroska(&path)?
-        } else {
-            Metadata::from_vertical_resolution(quality.unwrap_or(0), None)
+        // The last year before any numbering wins, so years that are part of
+        // the title like "Blade Runner 2049 2017" stay in it
+        let release_year = match years
+            .into_iter()
+            .rfind(|i| numbering_end.is_none_or(|end| *i < end))
+        {
+            Some(i) => {
+                title_end = usize::min(i, title_end);
+                markers.push(i);
+                matches.push((i, "year"));
+                YEAR.captures(file_name_parts[i])
+                    .and_then(|captures| captures[1].parse().ok())
+                    .unwrap_or(0)
+            }
+            None => 0,
         };
 
+        let found_editions = editions.detect(&file_name_parts);
+        if let Some((i, _)) = found_editions.first() {
+            title_end = usize::min(*i, title_end);
+        }
+        markers.extend(found_editions.iter().map(|(i, _)| *i));
+        matches.extend(found_editions.iter().map(|(i, _)| (*i, "edition")));
+        let edition = (!found_editions.is_empty()).then(|| {
+            found_editions
+                .into_iter()
+                .map(|(_, edition)| edition)
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+
+        let title = cleaner.clean(&file_name_parts[..title_end].join(" "));
+        let episode_title_start = numbering_end.unwrap_or(file_name_parts.len());
+        let episode_title_end = markers
+            .into_iter()
+            .filter(|i| *i >= episode_title_start)
+            .min()
+            .unwrap_or(file_name_parts.len());
+        let episode_title = (episode_title_end > episode_title_start)
+            .then(|| {
+                cleaner.clean(&file_name_parts[episode_title_start..episode_title_end].join(" "))
+            })
+            .filter(|episode_title| !episode_title.is_empty());
+
+        if let Some(trace) = trace {
+            *trace = ParseTrace {
+                parts: file_name_parts
+                    .iter()
+                    .map(|part| part.to_string())
+                    .collect(),
+                matches,
+                title_end,
+                episode_title_start,
+                episode_title_end,
+                title: title.clone(),
+                episode_title: episode_title.clone(),
+            };
+        }
+
+        let metadata = Metadata::from_path(&path, file_type, backend, quality);
+
         let info = if let Some(episode) = episode {
             VideoData::Episode(
                 Episode {
                     episode,
+                    episode_end,
                     season: season.unwrap_or(1),
                     title: episode_title.unwrap_or(String::new()),
-                    imdb_id: None,
+                    imdb_id,
+                    tmdb_id,
                     series: Entity {
                         title,
-                        release_year: 0,
+                        release_year,
                         imdb_id: None,
+                        tmdb_id: None,
+                        edition: None,
                     },
+                    season_digits,
+                    episode_digits,
                 },
                 metadata,
             )
@@ -124,8 +709,10 @@ impl Video {
             VideoData::Movie(
                 Entity {
                     title,
-                    release_year: 0,
-                    imdb_id: None,
+                    release_year,
+                    imdb_id,
+                    tmdb_id,
+                    edition,
                 },
                 metadata,
             )
@@ -136,42 +723,192 @@ impl Video {
             file_type,
             path,
             info,
+            imdb_tag_format: ImdbTagFormat::default(),
+            original_path: None,
         })
     }
 
-    pub fn generate_file_name(&self) -> String {
+    pub fn metadata(&self) -> &Metadata {
+        match &self.info {
+            VideoData::Episode(_, meta) | VideoData::Movie(_, meta) => meta,
+        }
+    }
+
+    /// The IMDB id of the movie or episode, when known
+    pub fn imdb_id(&self) -> Option<&str> {
         match &self.info {
-            VideoData::Episode(episode, meta) => {
+            VideoData::Movie(movie, _) => movie.imdb_id.as_deref(),
+            VideoData::Episode(episode, _) => episode.imdb_id.as_deref(),
+        }
+    }
+
+    pub fn generate_file_name(&self) -> String {
+        self.generate_file_name_with(&NameFormat::default())
+    }
+
+    pub fn generate_file_name_with(&self, format: &NameFormat) -> String {
+        match (&self.info, &format.episode_template, &format.movie_template) {
+            (VideoData::Episode(episode, meta), Some(template), _) => template.render_episode(
+                episode,
+                meta,
+                &format.section_separator,
+                &self.file_extension,
+            ),
+            (VideoData::Movie(movie, meta), _, Some(template)) => {
+                template.render_movie(movie, meta, &format.section_separator, &self.file_extension)
+            }
+            (VideoData::Episode(episode, meta), _, _) => {
+                // An unknown year is left out rather than written as "(0)"
+                let series_year = if format.series_year && episode.series.release_year != 0 {
+                    format!(" ({})", episode.series.release_year)
+                } else {
+                    String::new()
+                };
                 format!(
-                    "{}-S{}E{}-{}p.{}",
-                    episode.series.title,
-                    format_num!("02.0", episode.season),
-                    format_num!("02.0", episode.episode),
+                    "{}{}{separator}{}{separator}{}p.{}",
+                    sanitize_component(&episode.series.title),
+                    series_year,
+                    episode.numbering(),
                     meta.get_resolution(),
-                    self.file_extension
+                    self.file_extension,
+                    separator = format.section_separator
                 )
             }
-            VideoData::Movie(movie, meta) => format!(
-                "{}-{}p.{}",
-                movie.title,
+            (VideoData::Movie(movie, meta), _, _) => format!(
+                "{}{}{}{}{}p.{}",
+                sanitize_component(&movie.title),
+                if format.movie_year && movie.release_year != 0 {
+                    format!(" ({})", movie.release_year)
+                } else {
+                    String::new()
+                },
+                movie
+                    .edition
+                    .as_ref()
+                    .map(|edition| format!(" {{edition-{}}}", sanitize_component(edition)))
+                    .unwrap_or_default(),
+                format.section_separator,
                 meta.get_resolution(),
                 self.file_extension
             ),
         }
     }
 
+    /// Whether the parsed information is poor enough to need an IMDB lookup
+    pub fn needs_imdb_lookup(&self, when: ImdbWhen) -> bool {
+        let entity = match &self.info {
+            VideoData::Episode(episode, _) => &episode.series,
+            VideoData::Movie(movie, _) => movie,
+        };
+        let is_missing = entity.title.is_empty() || entity.release_year == 0;
+        match when {
+            ImdbWhen::Always => true,
+            ImdbWhen::Missing => is_missing,
+            ImdbWhen::Ambiguous => {
+                is_missing || is_junk_title(&entity.title) || !entity.title.contains(' ')
+            }
+        }
+    }
+
+    /// Replace the parsed title (the series title for episodes) with one from
+    /// the containing directory when the source asks for it
+    pub fn apply_title_source(&mut self, source: TitleSource) {
+        let title = match &mut self.info {
+            VideoData::Episode(episode, _) => &mut episode.series.title,
+            VideoData::Movie(movie, _) => &mut movie.title,
+        };
+        let use_directory = match source {
+            TitleSource::File => false,
+            TitleSource::Dir => true,
+            TitleSource::Auto => is_junk_title(title),
+        };
+        if use_directory {
+            if let Some(directory_title) = directory_title(&self.path) {
+                *title = directory_title;
+            }
+        }
+    }
+
+    /// Take a movie's title and year from the folder it is in, for movies
+    /// that are the only video in their folder
+    pub fn apply_movie_folder_title(&mut self) {
+        if let VideoData::Movie(movie, _) = &mut self.info {
+            if let Some((title, release_year)) = movie_folder_title(&self.path) {
+                movie.title = title;
+                if release_year != 0 {
+                    movie.release_year = release_year;
+                }
+            }
+        }
+    }
+
+    /// Replace the parsed information with an explicit series episode
+    pub fn set_episode(&mut self, series: &str, season: u32, episode: u32) {
+        let meta = self.metadata().clone();
+        self.info = VideoData::Episode(
+            Episode {
+                episode,
+                episode_end: None,
+                season,
+                title: String::new(),
+                imdb_id: None,
+                tmdb_id: None,
+                series: Entity {
+                    title: series.to_string(),
+                    release_year: 0,
+                    imdb_id: None,
+                    tmdb_id: None,
+                    edition: None,
+                },
+                season_digits: 0,
+                episode_digits: 0,
+            },
+            meta,
+        );
+    }
+
+    /// Title case the movie, series and episode titles, see `TitleCaser`
+    pub fn normalize_title_case(&mut self) {
+        match &mut self.info {
+            VideoData::Movie(movie, _) => movie.title = TitleCaser.clean(&movie.title),
+            VideoData::Episode(episode, _) => {
+                episode.series.title = TitleCaser.clean(&episode.series.title);
+                episode.title = TitleCaser.clean(&episode.title);
+            }
+        }
+    }
+
+    /// Remove the parsed release year from the movie or series title
+    pub fn strip_year_from_title(&mut self) {
+        match &mut self.info {
+            VideoData::Movie(movie, _) => movie.strip_release_year(),
+            VideoData::Episode(episode, _) => episode.series.strip_release_year(),
+        }
+    }
+
     #[cfg(feature = "imdb")]
     pub fn update_from_imdb(&mut self, entity: &crate::imdb::Results) -> GenericResult<()> {
         let mut res = Ok(());
         self.info = match (self.info.clone(), entity) {
-            (VideoData::Movie(_, meta), crate::imdb::Results::Movie(entity)) => {
-                VideoData::Movie(entity.into(), meta)
+            (VideoData::Movie(old_entity, meta), crate::imdb::Results::Movie(entity)) => {
+                // IMDB doesn't know which edition this file is
+                let mut entity: Entity = entity.into();
+                entity.edition = old_entity.edition;
+                entity.tmdb_id = old_entity.tmdb_id;
+                VideoData::Movie(entity, meta)
             }
             (
                 VideoData::Episode(old_entity, meta),
                 crate::imdb::Results::Episode(series, episode),
-            ) => match (episode, series).try_into() {
-                Ok(ep) => VideoData::Episode(ep, meta),
+            ) => match Episode::try_from((episode, series)) {
+                Ok(mut ep) => {
+                    ep.tmdb_id = old_entity.tmdb_id.clone();
+                    ep.season_digits = old_entity.season_digits;
+                    ep.episode_digits = old_entity.episode_digits;
+                    // IMDB only knows the first episode of the file
+                    ep.episode_end = old_entity.episode_end;
+                    VideoData::Episode(ep, meta)
+                }
                 Err(e) => {
                     res = Err(e.into());
                     VideoData::Episode(old_entity, meta)
@@ -182,13 +919,73 @@ impl Video {
         res
     }
 
+    /// The simple tags written by `insert_into_matroska`, empty values are not written.
+    ///
+    /// These replace the file's own values, every other tag in the file (e.g.
+    /// `COMMENT`) is kept as it is.
+    pub fn compute_tags(&self) -> HashMap<String, String> {
+        let mut tags = HashMap::new();
+        match &self.info {
+            VideoData::Movie(ent, _) => {
+                tags.insert(TITLE.to_string(), ent.title.clone());
+                tags.insert(DATE_RELEASED.to_string(), release_date(ent.release_year));
+                if let Some(imdb_id) = ent.imdb_id.as_ref() {
+                    tags.insert(IMDB_ID.to_string(), self.imdb_tag_format.format(imdb_id));
+                }
+                if let Some(tmdb_id) = ent.tmdb_id.as_ref() {
+                    tags.insert(TMDB_ID.to_string(), tmdb_id.clone());
+                }
+            }
+            VideoData::Episode(ep, _) => {
+                tags.insert(TITLE.to_string(), ep.series.title.clone());
+                tags.insert(
+                    DATE_RELEASED.to_string(),
+                    release_date(ep.series.release_year),
+                );
+                tags.insert(SEASON_NUMBER.to_string(), ep.season.to_string());
+                tags.insert(EPISODE_NUMBER.to_string(), ep.episode_number());
+                if let Some(imdb_id) = ep.imdb_id.as_ref() {
+                    tags.insert(IMDB_ID.to_string(), self.imdb_tag_format.format(imdb_id));
+                }
+                if let Some(tmdb_id) = ep.tmdb_id.as_ref() {
+                    tags.insert(TMDB_ID.to_string(), tmdb_id.clone());
+                }
+            }
+        }
+        tags
+    }
+
+    /// Remux an H.264/AAC MP4 into a Matroska file at `to`, copying the
+    /// streams without re-encoding, after which this is treated as an MKV
+    #[cfg(feature = "remux")]
+    pub fn remux_to_matroska<P: AsRef<std::path::Path>>(&mut self, to: P) -> GenericResult<()> {
+        let to = to.as_ref();
+        if let Err(e) = super::remux::remux_mp4_to_matroska(&self.path, to) {
+            let _ = std::fs::remove_file(to);
+            return Err(e);
+        }
+        self.file_type = FileType::MKV;
+        self.file_extension = "mkv".to_string();
+        Ok(())
+    }
+
     pub fn insert_into_matroska<F: Read, T: Write>(
         &self,
         from: &mut F,
         to: &mut T,
+    ) -> GenericResult<()> {
+        self.insert_into_matroska_with_subtitles(from, to, &[])
+    }
+
+    /// Rewrite the file with our tags, also adding each subtitle as a new track
+    pub fn insert_into_matroska_with_subtitles<F: Read, T: Write>(
+        &self,
+        from: &mut F,
+        to: &mut T,
+        subtitles: &[Subtitle],
     ) -> GenericResult<()> {
         // FIXME: Make more modular with less code repetition
-        let reader = WebmIterator::new(from, &[MatroskaSpec::SimpleTag(Master::Start)]);
+        let reader = WebmIterator::new(from, &BUFFERED_MASTERS);
         let mut writer = WebmWriter::new(to);
 
         // Output sections
@@ -199,48 +996,70 @@ impl Video {
         let mut in_info = false;
         let mut in_tags = false;
         let mut in_tag = false;
+        let mut in_cluster = false;
 
-        // Have to store numbers in upper scope
-        let mut release_year: Option<String> = None;
-        let mut season_number: Option<String> = None;
-        let mut episode_number: Option<String> = None;
+        // Subtitle muxing state
+        let mut muxer = SubtitleMuxer::new(subtitles);
+        let mut max_track_number = 0;
+        let mut cluster_seen = false;
 
-        let mut tags: HashMap<&str, &str> = HashMap::new();
-        tags.insert(COMMENT, "");
-        let title = MatroskaSpec::Title(match &self.info {
-            VideoData::Movie(ent, _) => {
-                tags.insert(TITLE, &ent.title);
-                release_year = Some(ent.release_year.to_string());
-                tags.insert(DATE_RELEASED, release_year.as_ref().unwrap());
-                if let Some(imdb_id) = ent.imdb_id.as_ref() {
-                    tags.insert(IMDB_ID, imdb_id);
-                }
+        let tags = self.compute_tags();
+        // An ORIGINAL_PATH already in the file is from an earlier flattening, so keep it
+        let mut original_path_seen = false;
+        let tags_with_original_path = |original_path_seen: bool| {
+            let mut tags = tags.clone();
+            if let (Some(original_path), false) = (&self.original_path, original_path_seen) {
+                tags.insert(ORIGINAL_PATH.to_string(), original_path.clone());
+            }
+            tags
+        };
+        let title = sanitize_tag(MatroskaSpec::Title(match &self.info {
+            VideoData::Movie(ent, _) => ent.title.clone(),
+            VideoData::Episode(ep, _) => ep.title.clone(),
+        }));
 
-                ent.title.clone()
+        for tag in reader {
+            let tag = tag?;
+            // Elements webm-iterable doesn't know, e.g. from a newer
+            // DocTypeVersion, are read as raw bytes and copied as they are
+            if let MatroskaSpec::RawTag(_, _) = &tag {
+                writer.write(&tag)?;
+                continue;
             }
-            VideoData::Episode(ep, _) => {
-                tags.insert(TITLE, &ep.series.title);
-                release_year = Some(ep.series.release_year.to_string());
-                tags.insert(DATE_RELEASED, release_year.as_ref().unwrap());
-                season_number = Some(ep.season.to_string());
-                tags.insert(SEASON_NUMBER, season_number.as_ref().unwrap());
-                episode_number = Some(ep.episode.to_string());
-                tags.insert(EPISODE_NUMBER, episode_number.as_ref().unwrap());
-                if let Some(imdb_id) = ep.imdb_id.as_ref() {
-                    tags.insert(IMDB_ID, imdb_id);
+            match &tag {
+                MatroskaSpec::TimecodeScale(scale) => muxer.timestamp_scale = *scale,
+                MatroskaSpec::TrackNumber(number) => {
+                    max_track_number = u64::max(max_track_number, *number)
                 }
-
-                ep.title.clone()
+                MatroskaSpec::Tracks(Master::End) if !subtitles.is_empty() => {
+                    muxer.write_tracks(&mut writer, max_track_number)?
+                }
+                MatroskaSpec::Cluster(Master::Start) => {
+                    in_cluster = true;
+                    cluster_seen = true;
+                }
+                MatroskaSpec::Cluster(Master::End) => in_cluster = false,
+                MatroskaSpec::Chapters(Master::Start)
+                | MatroskaSpec::Cues(Master::Start)
+                | MatroskaSpec::Attachments(Master::Start)
+                | MatroskaSpec::Tags(Master::Start)
+                | MatroskaSpec::Segment(Master::End)
+                    if cluster_seen =>
+                {
+                    muxer.write_remaining(&mut writer)?
+                }
+                _ => {}
             }
-        });
 
-        for tag in reader {
-            let tag = tag?;
             if let MatroskaSpec::Info(mode) = &tag {
                 in_info = match mode {
                     Master::Start => true,
                     Master::End => {
-                        writer.write(&title)?;
+                        // Only the first Info gets our title, any Title in
+                        // later (invalid) Info elements is still stripped
+                        if !info_written {
+                            writer.write(&title)?;
+                        }
                         info_written = true;
                         false
                     }
@@ -250,13 +1069,14 @@ impl Video {
                 continue;
             }
 
+            // Top level elements can come in any order, so an Info is only
+            // made up once one can no longer appear before the clusters.
+            // Making one earlier would leave two Info elements when the file
+            // has Tracks or Tags first.
             match &tag {
-                MatroskaSpec::Tracks(Master::Start)
-                | MatroskaSpec::Chapters(Master::Start)
-                | MatroskaSpec::Cluster(Master::Start)
+                MatroskaSpec::Cluster(Master::Start)
                 | MatroskaSpec::Cues(Master::Start)
-                | MatroskaSpec::Attachments(Master::Start)
-                | MatroskaSpec::Tags(Master::Start)
+                | MatroskaSpec::Segment(Master::End)
                     if !info_written =>
                 {
                     writer.write(&MatroskaSpec::Info(Master::Full(vec![title.clone()])))?;
@@ -264,22 +1084,22 @@ impl Video {
                 }
                 _ => {}
             }
+            // Tags written after the loop would be outside the Segment
+            if let (MatroskaSpec::Segment(Master::End), false) = (&tag, tags_written) {
+                writer.write(&MatroskaSpec::Tags(Master::Start))?;
+                write_tag_entries(&mut writer, &tags_with_original_path(original_path_seen))?;
+                writer.write(&MatroskaSpec::Tags(Master::End))?;
+                tags_written = true;
+            }
 
             if let MatroskaSpec::Tags(mode) = &tag {
                 in_tags = match mode {
                     Master::Start => true,
                     Master::End => {
-                        writer.write(&MatroskaSpec::Tag(Master::Start))?;
-                        writer.write(&MatroskaSpec::Targets(Master::Full(vec![])))?;
-                        for (k, v) in tags.iter() {
-                            if v.len() > 0 {
-                                writer.write(&MatroskaSpec::SimpleTag(Master::Start))?;
-                                writer.write(&MatroskaSpec::TagName(k.to_string()))?;
-                                writer.write(&MatroskaSpec::TagString(v.to_string()))?;
-                                writer.write(&MatroskaSpec::SimpleTag(Master::End))?;
-                            }
-                        }
-                        writer.write(&MatroskaSpec::Tag(Master::End))?;
+                        write_tag_entries(
+                            &mut writer,
+                            &tags_with_original_path(original_path_seen),
+                        )?;
                         tags_written = true;
                         false
                     }
@@ -304,17 +1124,21 @@ impl Video {
                             Some(MatroskaSpec::TagName(name)),
                             Some(MatroskaSpec::TagString(_value)),
                         ) = (
-                            tag_data.iter().find(|t| match &t {
-                                MatroskaSpec::TagName(_) => true,
-                                _ => false,
-                            }),
-                            tag_data.iter().find(|t| match &t {
-                                MatroskaSpec::TagString(_) => true,
-                                _ => false,
-                            }),
+                            tag_data
+                                .iter()
+                                .find(|t| matches!(t, MatroskaSpec::TagName(_))),
+                            tag_data
+                                .iter()
+                                .find(|t| matches!(t, MatroskaSpec::TagString(_))),
                         ) {
-                            if !tags.contains_key(name.as_str()) {
-                                writer.write(&MatroskaSpec::SimpleTag(Master::Full(tag_data)))?;
+                            if name == ORIGINAL_PATH {
+                                original_path_seen = true;
+                            }
+                            // Empty values aren't written, so the file's own value is kept
+                            if tags.get(name.as_str()).is_none_or(|value| value.is_empty()) {
+                                writer.write(&sanitize_tag(MatroskaSpec::SimpleTag(
+                                    Master::Full(tag_data),
+                                )))?;
                             }
                         }
                         continue;
@@ -329,24 +1153,702 @@ impl Video {
             }
 
             writer.write(&tag)?;
+            if let MatroskaSpec::Timecode(cluster_timestamp) = tag {
+                if in_cluster {
+                    muxer.write_into_cluster(&mut writer, cluster_timestamp)?;
+                }
+            }
         }
+        muxer.write_remaining(&mut writer)?;
 
         if !tags_written {
             writer.write(&MatroskaSpec::Tags(Master::Start))?;
-            writer.write(&MatroskaSpec::Tag(Master::Start))?;
-            writer.write(&MatroskaSpec::Targets(Master::Full(vec![])))?;
-            for (k, v) in tags.iter() {
-                if v.len() > 0 {
-                    writer.write(&MatroskaSpec::SimpleTag(Master::Start))?;
-                    writer.write(&MatroskaSpec::TagName(k.to_string()))?;
-                    writer.write(&MatroskaSpec::TagString(v.to_string()))?;
-                    writer.write(&MatroskaSpec::SimpleTag(Master::End))?;
-                }
-            }
-            writer.write(&MatroskaSpec::Tag(Master::End))?;
+            write_tag_entries(&mut writer, &tags_with_original_path(original_path_seen))?;
             writer.write(&MatroskaSpec::Tags(Master::End))?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn video(name: &str) -> Video {
+        Video::from_path_with_backend(PathBuf::from(name), FileType::AVI, MetadataBackend::Native)
+            .unwrap()
+    }
+
+    fn read_tags(data: &[u8]) -> Vec<MatroskaSpec> {
+        WebmIterator::new(data, &BUFFERED_MASTERS)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn episode(name: &str) -> Episode {
+        match video(name).info {
+            VideoData::Episode(episode, _) => episode,
+            VideoData::Movie(movie, _) => panic!("{:?} parsed as a movie", movie),
+        }
+    }
+
+    fn movie(name: &str) -> Entity {
+        match video(name).info {
+            VideoData::Movie(movie, _) => movie,
+            VideoData::Episode(episode, _) => panic!("{:?} parsed as an episode", episode),
+        }
+    }
+
+    #[test]
+    fn truncated_matroska_uses_the_file_name_resolution() {
+        let temp = TempDir::with_prefix("renamer-").unwrap();
+        let dir = temp.path();
+        let path = dir.join("Some.Movie.2010.1080p.mkv");
+        // An EBML header cut off part way through its first child
+        std::fs::write(&path, [0x1a, 0x45, 0xdf, 0xa3, 0x84, 0x42, 0x82]).unwrap();
+
+        let video =
+            Video::from_path_with_backend(path, FileType::MKV, MetadataBackend::Native).unwrap();
+        assert_eq!(video.metadata().resolution, (1920, 1080));
+    }
+
+    #[test]
+    fn identically_parsed_entities_are_equal() {
+        use std::collections::HashSet;
+
+        let a = movie("Some.Movie.2010.1080p.avi");
+        let b = movie("Some Movie (2010) [1080p].avi");
+        assert_eq!(a, b);
+        assert_eq!(HashSet::from([a.clone(), b]).len(), 1);
+        assert_ne!(a, movie("Some.Movie.2011.1080p.avi"));
+
+        let a = episode("Show.S01E02.720p.avi");
+        assert_eq!(a, episode("Show.S01E02.720p.avi"));
+        assert_eq!(HashSet::from([a.clone(), a.clone()]).len(), 1);
+        assert_ne!(a, episode("Show.S01E03.720p.avi"));
+    }
+
+    #[test]
+    fn numeric_show_titles_survive() {
+        for (name, title, season, number) in [
+            ("24.S01E01.720p.avi", "24", 1, 1),
+            ("9-1-1.S02E03.720p.avi", "9-1-1", 2, 3),
+            ("The.100.S01E01.720p.avi", "The 100", 1, 1),
+            ("1923.S01E05.1080p.avi", "1923", 1, 5),
+            ("Se7en.Deadly.S03E10.avi", "Se7en Deadly", 3, 10),
+        ] {
+            let parsed = episode(name);
+            assert_eq!(parsed.series.title, title, "{}", name);
+            assert_eq!(
+                (parsed.season, parsed.episode),
+                (season, number),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn imdb_id_token_ends_the_title() {
+        let parsed = movie("Movie.2020.tt1234567.1080p.avi");
+        assert_eq!(parsed.title, "Movie");
+        assert_eq!(parsed.imdb_id.as_deref(), Some("tt1234567"));
+
+        let parsed = episode("Show.S01E02.TT12345678.720p.avi");
+        assert_eq!(parsed.series.title, "Show");
+        assert_eq!(parsed.imdb_id.as_deref(), Some("tt12345678"));
+
+        // Too short to be an id
+        assert_eq!(movie("Movie.2020.tt123.1080p.avi").imdb_id, None);
+    }
+
+    #[test]
+    fn title_source_modes() {
+        let title = |name: &str, source| {
+            let mut parsed = video(name);
+            parsed.apply_title_source(source);
+            match parsed.info {
+                VideoData::Episode(episode, _) => episode.series.title,
+                VideoData::Movie(movie, _) => movie.title,
+            }
+        };
+        let junk = "Show Name/Season 1/t01.avi";
+        let named = "Show Name/Season 1/Other.Name.S01E02.avi";
+        assert_eq!(title(junk, TitleSource::File), "t01");
+        assert_eq!(title(named, TitleSource::File), "Other Name");
+        assert_eq!(title(junk, TitleSource::Dir), "Show Name");
+        assert_eq!(title(named, TitleSource::Dir), "Show Name");
+        assert_eq!(title(junk, TitleSource::Auto), "Show Name");
+        assert_eq!(title(named, TitleSource::Auto), "Other Name");
+    }
+
+    #[test]
+    fn imdb_tag_formats() {
+        let mut parsed = video("Movie.2020.tt1234567.1080p.avi");
+        for (format, tag) in [
+            ("id", "tt1234567"),
+            ("number", "1234567"),
+            ("url", "https://www.imdb.com/title/tt1234567/"),
+        ] {
+            parsed.imdb_tag_format = format.parse().unwrap();
+            assert_eq!(parsed.compute_tags()[IMDB_ID], tag);
+        }
+        assert_eq!(ImdbTagFormat::default(), ImdbTagFormat::Id);
+        assert!("hex".parse::<ImdbTagFormat>().is_err());
+    }
+
+    #[test]
+    fn tmdb_id_token() {
+        for name in [
+            "Movie (2020) {tmdb-12345}.avi",
+            "Movie.2020.[tmdbid-12345].1080p.avi",
+        ] {
+            let parsed = movie(name);
+            assert_eq!(parsed.title, "Movie", "{}", name);
+            assert_eq!(parsed.tmdb_id.as_deref(), Some("12345"), "{}", name);
+        }
+        let parsed = episode("Show.S01E02.{tmdb-678}.avi");
+        assert_eq!(parsed.tmdb_id.as_deref(), Some("678"));
+        assert_eq!(
+            video("Show.S01E02.{tmdb-678}.avi").compute_tags()[TMDB_ID],
+            "678"
+        );
+        assert_eq!(movie("Movie.2020.tmdb.avi").tmdb_id, None);
+    }
+
+    #[test]
+    fn imdb_lookup_gating() {
+        let needs = |name, when| video(name).needs_imdb_lookup(when);
+        let tidy = "Some.Movie.2010.1080p.avi";
+        let no_year = "Some.Movie.1080p.avi";
+        let one_word = "Heat.1995.1080p.avi";
+        let junk = "t01.2010.avi";
+
+        for name in [tidy, no_year, one_word, junk] {
+            assert!(needs(name, ImdbWhen::Always), "{}", name);
+        }
+        assert!(!needs(tidy, ImdbWhen::Missing));
+        assert!(needs(no_year, ImdbWhen::Missing));
+        assert!(!needs(one_word, ImdbWhen::Missing));
+
+        assert!(!needs(tidy, ImdbWhen::Ambiguous));
+        assert!(needs(no_year, ImdbWhen::Ambiguous));
+        assert!(needs(one_word, ImdbWhen::Ambiguous));
+        assert!(needs(junk, ImdbWhen::Ambiguous));
+    }
+
+    #[test]
+    fn series_year_in_episode_names() {
+        let format = NameFormat {
+            series_year: true,
+            ..NameFormat::default()
+        };
+        let mut parsed = video("Show.S01E05.720p.avi");
+        // The year is unknown so no empty brackets are written
+        assert_eq!(
+            parsed.generate_file_name_with(&format),
+            "Show-S01E05-720p.avi"
+        );
+        if let VideoData::Episode(episode, _) = &mut parsed.info {
+            episode.series.release_year = 2020;
+        }
+        assert_eq!(
+            parsed.generate_file_name_with(&format),
+            "Show (2020)-S01E05-720p.avi"
+        );
+        assert_eq!(parsed.generate_file_name(), "Show-S01E05-720p.avi");
+    }
+
+    #[test]
+    fn doubled_extensions_are_not_in_the_title() {
+        assert_eq!(movie("movie.mkv.mkv").title, "movie");
+        let parsed = episode("show.s01e01.avi.mkv");
+        assert_eq!(parsed.series.title, "show");
+        assert_eq!((parsed.season, parsed.episode), (1, 1));
+        assert_eq!(video("movie.mkv.mkv").file_extension, "mkv");
+    }
+
+    #[test]
+    fn numbering_keeps_the_source_width() {
+        assert_eq!(episode("Show.S001E001.avi").numbering(), "S001E001");
+        assert_eq!(episode("Show.S1E1.avi").numbering(), "S01E01");
+        assert_eq!(episode("Show.S01E001.avi").numbering(), "S01E001");
+    }
+
+    #[test]
+    fn section_separator_joins_the_sections() {
+        let format = NameFormat {
+            section_separator: " - ".to_string(),
+            ..NameFormat::default()
+        };
+        assert_eq!(
+            video("Show.S01E05.1080p.mkv").generate_file_name_with(&format),
+            "Show - S01E05 - 1080p.mkv"
+        );
+        assert_eq!(
+            video("Some.Movie.2010.720p.avi").generate_file_name_with(&format),
+            "Some Movie - 720p.avi"
+        );
+    }
+
+    #[test]
+    fn multi_episode_ranges() {
+        for name in [
+            "Show.S01E01-E02.mkv",
+            "Show.S01E01E02.1080p.mkv",
+            "Show.S01E01-02.1080p.mkv",
+        ] {
+            let episode = episode(name);
+            assert_eq!(
+                (episode.episode, episode.episode_end),
+                (1, Some(2)),
+                "{}",
+                name
+            );
+            assert_eq!(episode.numbering(), "S01E01-E02", "{}", name);
+            assert_eq!(episode.episode_number(), "1-2", "{}", name);
+        }
+        // A lower second number isn't a range
+        assert_eq!(episode("Show.S01E05E02.mkv").episode_end, None);
+
+        let video = video("Show.S01E01E02.1080p.mkv");
+        assert!(video.generate_file_name().contains("S01E01-E02"));
+        assert_eq!(video.compute_tags()[EPISODE_NUMBER], "1-2");
+    }
+
+    #[test]
+    fn dotted_titles_are_title_cased() {
+        let mut movie = video("the.lord.of.the.rings.2001.1080p.mkv");
+        movie.normalize_title_case();
+        assert_eq!(
+            movie.generate_file_name(),
+            "The Lord of the Rings-1080p.mkv"
+        );
+
+        let mut episode = video("SOME.SHOW.S01E02.the.pilot.part.ii.mkv");
+        episode.normalize_title_case();
+        match episode.info {
+            VideoData::Episode(episode, _) => {
+                assert_eq!(episode.series.title, "Some Show");
+                assert_eq!(episode.title, "The Pilot Part II");
+            }
+            VideoData::Movie(movie, _) => panic!("{:?} parsed as a movie", movie),
+        }
+    }
+
+    #[test]
+    fn episode_titles_without_a_resolution() {
+        for (name, title) in [
+            ("Show.S01E05.The.Big.One.mkv", "The Big One"),
+            ("Show.S01E05.The.Big.One.720p.mkv", "The Big One"),
+            ("Show.S01E05.The.Big.One.WEB.x264-GROUP.mkv", "The Big One"),
+            ("Show.S01E05.mkv", ""),
+            ("Show.S01E05.720p.mkv", ""),
+        ] {
+            assert_eq!(episode(name).title, title, "{}", name);
+        }
+    }
+
+    #[test]
+    fn generated_names_are_sanitized() {
+        let mut parsed = video("Face.Off.1997.1080p.avi");
+        if let VideoData::Movie(movie, _) = &mut parsed.info {
+            movie.title = "Face/Off: Part 2?".to_string();
+        }
+        assert_eq!(parsed.generate_file_name(), "Face-Off - Part 2-1080p.avi");
+
+        let mut parsed = video("Show.S01E02.720p.avi");
+        if let VideoData::Episode(episode, _) = &mut parsed.info {
+            episode.series.title = "Law & Order: SVU".to_string();
+        }
+        assert_eq!(
+            parsed.generate_file_name(),
+            "Law & Order - SVU-S01E02-720p.avi"
+        );
+    }
+
+    #[test]
+    fn cross_numbering() {
+        for (name, title, season, number) in [
+            ("Show.1x02.mkv", "Show", 1, 2),
+            ("Show.01x02.1080p.mkv", "Show", 1, 2),
+            ("Show.10x12.Title.mkv", "Show", 10, 12),
+            // SxxExx wins over a later 1x02
+            ("Show.S03E04.1x02.mkv", "Show", 3, 4),
+        ] {
+            let parsed = episode(name);
+            assert_eq!(parsed.series.title, title, "{}", name);
+            assert_eq!(
+                (parsed.season, parsed.episode),
+                (season, number),
+                "{}",
+                name
+            );
+        }
+        // A frame size isn't numbering
+        let parsed = movie("Some.Movie.2010.1920x1080.avi");
+        assert_eq!(parsed.title, "Some Movie");
+    }
+
+    #[test]
+    fn episode_title_window() {
+        for name in [
+            "Show.S01E05.The Reveal.720p.mkv",
+            "Show.S01E05.The Reveal.mkv",
+            "Show.S01E05.The Reveal.tt1234567.mkv",
+            "Show.S01E05.The Reveal.Extended.mkv",
+            "Show.S01E05.The Reveal.x264.mkv",
+        ] {
+            let (parsed, trace) = Video::from_path_traced(
+                PathBuf::from(name),
+                FileType::AVI,
+                MetadataBackend::Native,
+            )
+            .unwrap();
+            let VideoData::Episode(episode, _) = parsed.info else {
+                panic!("{} parsed as a movie", name);
+            };
+            assert_eq!(episode.title, "The Reveal", "{}", name);
+            assert_eq!(trace.title_end, 1, "{}", name);
+            assert_eq!(
+                trace.parts[trace.episode_title_start..trace.episode_title_end],
+                ["The", "Reveal"],
+                "{}",
+                name
+            );
+        }
+    }
+
+    fn write_tags(tags: &[MatroskaSpec]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut writer = WebmWriter::new(&mut data);
+        for tag in tags {
+            writer.write(tag).unwrap();
+        }
+        drop(writer);
+        data
+    }
+
+    fn simple_tag(name: &str, value: &str) -> MatroskaSpec {
+        MatroskaSpec::SimpleTag(Master::Full(vec![
+            MatroskaSpec::TagName(name.to_string()),
+            MatroskaSpec::TagString(value.to_string()),
+        ]))
+    }
+
+    /// Every TagName and TagString pair in the file
+    fn simple_tags(data: &[u8]) -> Vec<(String, String)> {
+        read_tags(data)
+            .into_iter()
+            .filter_map(|tag| match tag {
+                MatroskaSpec::SimpleTag(Master::Full(children)) => match &children[..] {
+                    [MatroskaSpec::TagName(name), MatroskaSpec::TagString(value), ..] => {
+                        Some((name.clone(), value.clone()))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn preserved_tags_lose_boms_and_trailing_nulls() {
+        let input = write_tags(&[
+            MatroskaSpec::Segment(Master::Start),
+            MatroskaSpec::Tags(Master::Start),
+            MatroskaSpec::Tag(Master::Start),
+            MatroskaSpec::SimpleTag(Master::Full(vec![
+                MatroskaSpec::TagName("COMMENT".to_string()),
+                MatroskaSpec::TagString("\u{feff}Director's cut\0\0".to_string()),
+            ])),
+            MatroskaSpec::Tag(Master::End),
+            MatroskaSpec::Tags(Master::End),
+            MatroskaSpec::Segment(Master::End),
+        ]);
+        let mut output = Vec::new();
+        video("Some.Movie.2010.1080p.avi")
+            .insert_into_matroska(&mut input.as_slice(), &mut output)
+            .unwrap();
+
+        let tags = simple_tags(&output);
+        assert!(tags.contains(&("COMMENT".to_string(), "Director's cut".to_string())));
+        for (name, value) in tags {
+            assert!(
+                !value.starts_with('\u{feff}') && !value.ends_with('\0'),
+                "{} is {:?}",
+                name,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn large_attachment_streams_through() {
+        let font: Vec<u8> = (0..50 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let attachment = [
+            MatroskaSpec::Attachments(Master::Start),
+            MatroskaSpec::AttachedFile(Master::Start),
+            MatroskaSpec::FileName("font.ttf".to_string()),
+            MatroskaSpec::FileData(font),
+            MatroskaSpec::AttachedFile(Master::End),
+            MatroskaSpec::Attachments(Master::End),
+        ];
+        let mut tags = vec![MatroskaSpec::Segment(Master::Start)];
+        tags.extend(attachment.iter().cloned());
+        tags.push(MatroskaSpec::Segment(Master::End));
+        let input = write_tags(&tags);
+
+        // The rewriter reads the attachment element by element, only the
+        // font's own FileData is held whole, rather than as one master
+        for tag in WebmIterator::new(input.as_slice(), &BUFFERED_MASTERS) {
+            assert!(!matches!(
+                tag.unwrap(),
+                MatroskaSpec::Attachments(Master::Full(_))
+                    | MatroskaSpec::AttachedFile(Master::Full(_))
+            ));
+        }
+
+        let mut output = Vec::new();
+        video("Some.Movie.2010.1080p.avi")
+            .insert_into_matroska(&mut input.as_slice(), &mut output)
+            .unwrap();
+
+        let output = read_tags(&output);
+        let start = output.iter().position(|tag| *tag == attachment[0]).unwrap();
+        assert_eq!(output[start..start + attachment.len()], attachment);
+    }
+
+    /// Positions of each Info start and the first Cluster start
+    fn info_positions(tags: &[MatroskaSpec]) -> (Vec<usize>, Option<usize>) {
+        let infos = tags
+            .iter()
+            .enumerate()
+            .filter(|(_, tag)| matches!(tag, MatroskaSpec::Info(Master::Start | Master::Full(_))))
+            .map(|(i, _)| i)
+            .collect();
+        let cluster = tags
+            .iter()
+            .position(|tag| matches!(tag, MatroskaSpec::Cluster(Master::Start)));
+        (infos, cluster)
+    }
+
+    #[test]
+    fn info_is_written_once_whatever_the_element_order() {
+        let track = MatroskaSpec::TrackEntry(Master::Full(vec![MatroskaSpec::TrackNumber(1)]));
+        let tags = MatroskaSpec::Tags(Master::Full(vec![MatroskaSpec::Tag(Master::Full(vec![
+            MatroskaSpec::SimpleTag(Master::Full(vec![
+                MatroskaSpec::TagName("COMMENT".to_string()),
+                MatroskaSpec::TagString("Kept".to_string()),
+            ])),
+        ]))]));
+        let info = MatroskaSpec::Info(Master::Full(vec![MatroskaSpec::Title("Old".to_string())]));
+        let cluster = MatroskaSpec::Cluster(Master::Full(vec![MatroskaSpec::Timecode(0)]));
+        for (order, elements) in [
+            (
+                "tracks first",
+                vec![
+                    MatroskaSpec::Tracks(Master::Full(vec![track.clone()])),
+                    cluster.clone(),
+                ],
+            ),
+            (
+                "tags first",
+                vec![
+                    tags.clone(),
+                    info.clone(),
+                    MatroskaSpec::Tracks(Master::Full(vec![track.clone()])),
+                    cluster.clone(),
+                ],
+            ),
+            (
+                "tags then tracks",
+                vec![
+                    tags.clone(),
+                    MatroskaSpec::Tracks(Master::Full(vec![track.clone()])),
+                    cluster.clone(),
+                ],
+            ),
+        ] {
+            let mut input = vec![MatroskaSpec::Segment(Master::Start)];
+            input.extend(elements);
+            input.push(MatroskaSpec::Segment(Master::End));
+            let input = write_tags(&input);
+
+            let mut output = Vec::new();
+            video("Some.Movie.2010.1080p.avi")
+                .insert_into_matroska(&mut input.as_slice(), &mut output)
+                .unwrap();
+
+            let tags = read_tags(&output);
+            let (infos, cluster) = info_positions(&tags);
+            assert_eq!(infos.len(), 1, "{}", order);
+            assert!(infos[0] < cluster.unwrap(), "{}", order);
+            assert!(
+                tags.contains(&MatroskaSpec::Title("Some Movie".to_string())),
+                "{}",
+                order
+            );
+            assert!(
+                !tags.contains(&MatroskaSpec::Title("Old".to_string())),
+                "{}",
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn existing_tags_are_kept_unless_replaced() {
+        let input = write_tags(&[
+            MatroskaSpec::Segment(Master::Start),
+            MatroskaSpec::Tags(Master::Full(vec![MatroskaSpec::Tag(Master::Full(vec![
+                simple_tag("COMMENT", "Keep me"),
+                simple_tag(DATE_RELEASED, "1999"),
+                simple_tag(TITLE, "Old"),
+            ]))])),
+            MatroskaSpec::Segment(Master::End),
+        ]);
+        let mut output = Vec::new();
+        // Without a year our DATE_RELEASED is empty so the file's is kept
+        video("Some.Movie.1080p.avi")
+            .insert_into_matroska(&mut input.as_slice(), &mut output)
+            .unwrap();
+
+        let mut tags = simple_tags(&output);
+        tags.sort();
+        assert_eq!(
+            tags,
+            [
+                ("COMMENT".to_string(), "Keep me".to_string()),
+                (DATE_RELEASED.to_string(), "1999".to_string()),
+                (TITLE.to_string(), "Some Movie".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_elements_pass_through() {
+        let unknown = MatroskaSpec::RawTag(0x1f7a_7a7a, vec![1, 2, 3, 4]);
+        let input = write_tags(&[
+            MatroskaSpec::Segment(Master::Start),
+            unknown.clone(),
+            MatroskaSpec::Info(Master::Full(vec![MatroskaSpec::Title("Old".to_string())])),
+            MatroskaSpec::Segment(Master::End),
+        ]);
+        let mut output = Vec::new();
+        video("Some.Movie.2010.1080p.avi")
+            .insert_into_matroska(&mut input.as_slice(), &mut output)
+            .unwrap();
+
+        let tags = read_tags(&output);
+        assert_eq!(tags.iter().filter(|tag| **tag == unknown).count(), 1);
+        assert!(tags.contains(&MatroskaSpec::Title("Some Movie".to_string())));
+    }
+
+    #[test]
+    fn comment_survives_an_episode_rewrite() {
+        let input = write_tags(&[
+            MatroskaSpec::Segment(Master::Start),
+            MatroskaSpec::Tags(Master::Full(vec![MatroskaSpec::Tag(Master::Full(vec![
+                simple_tag("COMMENT", "Ripped from my own disc"),
+                simple_tag(SEASON_NUMBER, "9"),
+                simple_tag(EPISODE_NUMBER, "9"),
+            ]))])),
+            MatroskaSpec::Segment(Master::End),
+        ]);
+        let mut output = Vec::new();
+        video("Show.S01E05.720p.avi")
+            .insert_into_matroska(&mut input.as_slice(), &mut output)
+            .unwrap();
+
+        let mut tags = read_matroska_tags(&mut output.as_slice()).unwrap().tags;
+        tags.sort();
+        assert_eq!(
+            tags,
+            [
+                ("COMMENT".to_string(), "Ripped from my own disc".to_string()),
+                (EPISODE_NUMBER.to_string(), "5".to_string()),
+                (SEASON_NUMBER.to_string(), "1".to_string()),
+                (TITLE.to_string(), "Show".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn original_path_round_trips() {
+        let mut parsed = video("Some.Movie.2010.1080p.avi");
+        parsed.original_path = Some("movies/Some Movie/Some.Movie.2010.1080p.mkv".to_string());
+        let original_path = |input: &[u8]| {
+            let mut output = Vec::new();
+            parsed
+                .insert_into_matroska(&mut &input[..], &mut output)
+                .unwrap();
+            let existing = read_matroska_tags(&mut output.as_slice()).unwrap();
+            existing
+                .tags
+                .into_iter()
+                .filter(|(name, _)| name == ORIGINAL_PATH)
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+        };
+
+        let input = write_tags(&[
+            MatroskaSpec::Segment(Master::Start),
+            MatroskaSpec::Segment(Master::End),
+        ]);
+        assert_eq!(
+            original_path(&input),
+            ["movies/Some Movie/Some.Movie.2010.1080p.mkv"]
+        );
+
+        // A path from an earlier flattening is the real origin, so it is kept
+        let input = write_tags(&[
+            MatroskaSpec::Segment(Master::Start),
+            MatroskaSpec::Tags(Master::Full(vec![MatroskaSpec::Tag(Master::Full(vec![
+                MatroskaSpec::SimpleTag(Master::Full(vec![
+                    MatroskaSpec::TagName(ORIGINAL_PATH.to_string()),
+                    MatroskaSpec::TagString("old/Movie.mkv".to_string()),
+                ])),
+            ]))])),
+            MatroskaSpec::Segment(Master::End),
+        ]);
+        assert_eq!(original_path(&input), ["old/Movie.mkv"]);
+    }
+
+    #[test]
+    fn title_is_written_into_only_the_first_info() {
+        let input = write_tags(&[
+            MatroskaSpec::Segment(Master::Start),
+            MatroskaSpec::Info(Master::Start),
+            MatroskaSpec::Title("First".to_string()),
+            MatroskaSpec::Info(Master::End),
+            MatroskaSpec::Info(Master::Start),
+            MatroskaSpec::Title("Second".to_string()),
+            MatroskaSpec::Info(Master::End),
+            MatroskaSpec::Segment(Master::End),
+        ]);
+
+        let mut output = Vec::new();
+        video("Some.Movie.2010.1080p.avi")
+            .insert_into_matroska(&mut input.as_slice(), &mut output)
+            .unwrap();
+
+        let tags = read_tags(&output);
+        let titles: Vec<_> = tags
+            .iter()
+            .filter_map(|tag| match tag {
+                MatroskaSpec::Title(title) => Some(title.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(titles, ["Some Movie"]);
+        let infos = tags
+            .iter()
+            .filter(|tag| matches!(tag, MatroskaSpec::Info(Master::Start)))
+            .count();
+        assert_eq!(infos, 2);
+    }
+}
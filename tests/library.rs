@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use not_sus_renamer::magic::FileType;
+use not_sus_renamer::types::Video;
+
+#[test]
+fn video_from_a_path_string() {
+    let movie =
+        Video::from_path(PathBuf::from("Some.Movie.2010.1080p.avi"), FileType::AVI).unwrap();
+    assert_eq!(movie.generate_file_name(), "Some Movie-1080p.avi");
+
+    let episode = Video::from_path(PathBuf::from("Show.S01E05.720p.avi"), FileType::AVI).unwrap();
+    assert_eq!(episode.generate_file_name(), "Show-S01E05-720p.avi");
+}
+
+#[test]
+fn file_type_from_a_path() {
+    let path = std::env::temp_dir().join(format!("renamer-library-{}.avi", std::process::id()));
+    let mut avi = b"RIFF\0\0\0\0AVI ".to_vec();
+    avi.resize(2048, 0);
+    std::fs::write(&path, avi).unwrap();
+
+    assert_eq!(FileType::from_path(&path).unwrap(), FileType::AVI);
+
+    std::fs::remove_file(&path).unwrap();
+}